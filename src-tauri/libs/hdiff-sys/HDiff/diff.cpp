@@ -31,6 +31,7 @@
 #include <stdio.h>  //fprintf
 #include <algorithm> //std::max std::sort
 #include <vector>
+#include <stdexcept> //std::runtime_error
 #include "private_diff/suffix_string.h"
 #include "private_diff/bytes_rle.h"
 #include "private_diff/compress_detect.h"
@@ -1081,6 +1082,23 @@ void create_single_compressed_diff_stream(const hpatch_TStreamInput*  newData,
                                      out_diff,compressPlugin,patchStepMemSize);
 }
 
+extern "C" bool create_single_compressed_diff_stream(const hpatch_TStreamInput*  newData,
+                                          const hpatch_TStreamInput*  oldData,
+                                          const hpatch_TStreamOutput* out_diff,
+                                          const hdiff_TCompress* compressPlugin,
+                                          size_t kMatchBlockSize,size_t patchStepMemSize,
+                                          size_t threadNum){
+    hdiff_TMTSets_s mtsets=hdiff_TMTSets_s_kEmpty;
+    mtsets.threadNum=threadNum;
+    try{
+        create_single_compressed_diff_stream(newData,oldData,out_diff,compressPlugin,
+                                             kMatchBlockSize,patchStepMemSize,&mtsets);
+    }catch(const std::exception&){
+        return false;
+    }
+    return true;
+}
+
 
 bool check_diff(const TByte* newData,const TByte* newData_end,
                 const TByte* oldData,const TByte* oldData_end,
@@ -152,6 +152,14 @@ void create_single_compressed_diff_stream(const hpatch_TStreamInput*  newData,
                                           size_t kMatchBlockSize=kMatchBlockSize_default,
                                           size_t patchStepMemSize=kDefaultPatchStepMemSize,
                                           const hdiff_TMTSets_s* mtsets=0);
+//same as create_single_compressed_diff_stream(), but callable from C;
+//  return false instead of throw std::runtime_error when I/O error,etc.
+extern "C" bool create_single_compressed_diff_stream(const hpatch_TStreamInput*  newData,
+                                          const hpatch_TStreamInput*  oldData,
+                                          const hpatch_TStreamOutput* out_diff,
+                                          const hdiff_TCompress* compressPlugin,
+                                          size_t kMatchBlockSize,size_t patchStepMemSize,
+                                          size_t threadNum);
 
 //return patch_single_?(oldData+diff)==newData?
 bool check_single_compressed_diff(const unsigned char* newData,const unsigned char* newData_end,
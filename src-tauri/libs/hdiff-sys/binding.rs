@@ -2239,6 +2239,17 @@ unsafe extern "C" {
         threadNum: usize,
     );
 }
+unsafe extern "C" {
+    pub fn create_single_compressed_diff_stream(
+        newData: *const hpatch_TStreamInput,
+        oldData: *const hpatch_TStreamInput,
+        out_diff: *const hpatch_TStreamOutput,
+        compressPlugin: *const hdiff_TCompress,
+        kMatchBlockSize: usize,
+        patchStepMemSize: usize,
+        threadNum: usize,
+    ) -> bool;
+}
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct __crt_locale_data {
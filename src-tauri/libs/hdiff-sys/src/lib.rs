@@ -6,14 +6,52 @@ use std::ffi::c_void;
 
 include!("../binding.rs");
 
+mod zstd_plugin;
+
 trait WriteSeek: std::io::Write + std::io::Seek {}
 
 impl<T: std::io::Write + std::io::Seek> WriteSeek for T {}
 
+trait ReadSeek: std::io::Read + std::io::Seek {}
+
+impl<T: std::io::Read + std::io::Seek> ReadSeek for T {}
+
 struct WriteStreamWrapper<'a> {
     stream: &'a mut dyn WriteSeek,
 }
 
+struct ReadSeekStreamWrapper<'a> {
+    stream: &'a mut dyn ReadSeek,
+}
+
+extern "C" fn read_seek_callback(
+    stream: *const hpatch_TStreamInput,
+    read_from: u64,
+    out_data: *mut u8,
+    out_data_end: *mut u8,
+) -> i32 {
+    let read_size = unsafe { out_data_end.offset_from(out_data) };
+    let stream: &hpatch_TStreamInput = unsafe { &*stream };
+    let input_wrapper = unsafe { &mut *(stream.streamImport as *mut ReadSeekStreamWrapper) };
+    // seek
+    if let Err(err) = input_wrapper
+        .stream
+        .seek(std::io::SeekFrom::Start(read_from))
+    {
+        println!("Error in read_seek: {:?}", err);
+        return 0;
+    }
+    // buffer: out_data to out_data_end
+    let buffer = unsafe { std::slice::from_raw_parts_mut(out_data, read_size as usize) };
+    // read exact, return 0 if failed
+    let res = input_wrapper.stream.read_exact(buffer);
+    if let Err(err) = res {
+        println!("Error in read_seek_callback: {:?}", err);
+        return 0;
+    }
+    read_size as i32
+}
+
 extern "C" fn write_seek_callback(
     stream: *const hpatch_TStreamOutput,
     write_to: u64,
@@ -47,7 +85,10 @@ pub fn safe_create_single_patch(
     old_data: &[u8],
     mut output: impl std::io::Write + std::io::Seek,
     level: u8,
+    thread_num: usize,
+    zstd_level: Option<i32>,
 ) -> Result<(), String> {
+    let compress = zstd_level.map(zstd_plugin::ZstdCompress::new);
     let new_start_ptr = new_data.as_ptr();
     let new_end_ptr = unsafe { new_start_ptr.add(new_data.len()) };
     let old_start_ptr = old_data.as_ptr();
@@ -56,8 +97,8 @@ pub fn safe_create_single_patch(
         stream: &mut output,
     };
     let mut stream_output = hpatch_TStreamOutput {
-        // 1G
-        streamSize: 1 << 30,
+        // no limit, the diff size is not known before it is written
+        streamSize: u64::MAX,
         streamImport: &mut output_wrapper as *mut WriteStreamWrapper as *mut c_void,
         write: Some(write_seek_callback),
         read_writed: None,
@@ -69,13 +110,336 @@ pub fn safe_create_single_patch(
             old_start_ptr,
             old_end_ptr,
             &mut stream_output,
-            std::ptr::null_mut(),
+            compress.as_ref().map_or(std::ptr::null(), |c| c.as_ptr()),
             level as i32,
             1024 * 256,
             true,
             std::ptr::null_mut(),
-            1,
+            thread_num.max(1),
         );
     }
     Ok(())
 }
+
+const PATCH_STEP_MEM_SIZE: usize = 1024 * 256;
+const MATCH_BLOCK_SIZE_DEFAULT: usize = 1 << 6;
+const MATCH_BLOCK_SIZE_MAX: usize = 1 << 20;
+
+/// Picks the smallest match block size whose digest index for `old_size`
+/// bytes fits in `max_memory`. The stream diff needs about
+/// `old_size * 16 / block_size` bytes plus a few step buffers.
+pub fn stream_match_block_size(old_size: u64, max_memory: u64) -> usize {
+    let budget = max_memory.saturating_sub(PATCH_STEP_MEM_SIZE as u64 * 4);
+    let mut block_size = MATCH_BLOCK_SIZE_DEFAULT;
+    while block_size < MATCH_BLOCK_SIZE_MAX && old_size * 16 / block_size as u64 > budget {
+        block_size *= 2;
+    }
+    block_size
+}
+
+/// Same output format as [`safe_create_single_patch`], but reads both inputs
+/// through `Read + Seek` instead of loading them into memory.
+pub fn safe_create_single_patch_stream(
+    mut new_data: impl std::io::Read + std::io::Seek,
+    mut old_data: impl std::io::Read + std::io::Seek,
+    mut output: impl std::io::Write + std::io::Seek,
+    match_block_size: usize,
+    thread_num: usize,
+    zstd_level: Option<i32>,
+) -> Result<(), String> {
+    let compress = zstd_level.map(zstd_plugin::ZstdCompress::new);
+    let new_size = new_data
+        .seek(std::io::SeekFrom::End(0))
+        .map_err(|e| e.to_string())?;
+    let old_size = old_data
+        .seek(std::io::SeekFrom::End(0))
+        .map_err(|e| e.to_string())?;
+    let mut new_wrapper = ReadSeekStreamWrapper {
+        stream: &mut new_data,
+    };
+    let mut old_wrapper = ReadSeekStreamWrapper {
+        stream: &mut old_data,
+    };
+    let mut output_wrapper = WriteStreamWrapper {
+        stream: &mut output,
+    };
+    let new_stream = hpatch_TStreamInput {
+        streamImport: &mut new_wrapper as *mut ReadSeekStreamWrapper as *mut c_void,
+        streamSize: new_size,
+        read: Some(read_seek_callback),
+        _private_reserved: std::ptr::null_mut(),
+    };
+    let old_stream = hpatch_TStreamInput {
+        streamImport: &mut old_wrapper as *mut ReadSeekStreamWrapper as *mut c_void,
+        streamSize: old_size,
+        read: Some(read_seek_callback),
+        _private_reserved: std::ptr::null_mut(),
+    };
+    let stream_output = hpatch_TStreamOutput {
+        // the diff of large files may exceed 1G
+        streamSize: u64::MAX,
+        streamImport: &mut output_wrapper as *mut WriteStreamWrapper as *mut c_void,
+        write: Some(write_seek_callback),
+        read_writed: None,
+    };
+    let ok = unsafe {
+        create_single_compressed_diff_stream(
+            &new_stream,
+            &old_stream,
+            &stream_output,
+            compress.as_ref().map_or(std::ptr::null(), |c| c.as_ptr()),
+            match_block_size,
+            PATCH_STEP_MEM_SIZE,
+            thread_num.max(1),
+        )
+    };
+    if !ok {
+        return Err("failed to create stream diff".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sample() -> (Vec<u8>, Vec<u8>) {
+        let mut seed: u32 = 1;
+        let old: Vec<u8> = (0..2_000_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                // a small alphabet keeps the diff data compressible
+                b'a' + (seed >> 24) as u8 % 4
+            })
+            .collect();
+        let mut new = old.clone();
+        new.splice(1000..1000, b"inserted".iter().copied());
+        for byte in new[500_000..510_000].iter_mut().step_by(7) {
+            *byte ^= 0x55;
+        }
+        new.extend_from_slice(&old[..100_000]);
+        (old, new)
+    }
+
+    fn apply(old: &[u8], diff: &[u8], new_size: usize) -> Vec<u8> {
+        let mut output = vec![];
+        let res = hpatch_sys::safe_patch_single_stream(
+            &mut output,
+            Cursor::new(diff),
+            diff.len(),
+            Cursor::new(old),
+            old.len(),
+            new_size,
+        );
+        assert_eq!(res, 1);
+        output
+    }
+
+    const GIB: u64 = 1 << 30;
+
+    // Mostly zero data with a few pseudo-random islands, read without
+    // allocating the whole file.
+    struct Sparse {
+        size: u64,
+        islands: Vec<(u64, Vec<u8>)>,
+        pos: u64,
+    }
+
+    impl Sparse {
+        fn new(size: u64, islands: &[(u64, u32)]) -> Self {
+            let islands = islands
+                .iter()
+                .map(|&(offset, mut seed)| {
+                    let data = (0..64 * 1024)
+                        .map(|_| {
+                            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                            (seed >> 24) as u8
+                        })
+                        .collect();
+                    (offset, data)
+                })
+                .collect();
+            Self {
+                size,
+                islands,
+                pos: 0,
+            }
+        }
+
+        fn fill(&self, pos: u64, buf: &mut [u8]) {
+            buf.fill(0);
+            let end = pos + buf.len() as u64;
+            for (offset, data) in self.islands.iter() {
+                let island_end = offset + data.len() as u64;
+                if *offset < end && island_end > pos {
+                    let from = pos.max(*offset);
+                    let to = end.min(island_end);
+                    buf[(from - pos) as usize..(to - pos) as usize]
+                        .copy_from_slice(&data[(from - offset) as usize..(to - offset) as usize]);
+                }
+            }
+        }
+    }
+
+    impl std::io::Read for Sparse {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = (self.size - self.pos.min(self.size)).min(buf.len() as u64) as usize;
+            self.fill(self.pos, &mut buf[..len]);
+            self.pos += len as u64;
+            Ok(len)
+        }
+    }
+
+    impl std::io::Seek for Sparse {
+        fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.pos = match pos {
+                std::io::SeekFrom::Start(pos) => pos,
+                std::io::SeekFrom::End(offset) => self.size.saturating_add_signed(offset),
+                std::io::SeekFrom::Current(offset) => self.pos.saturating_add_signed(offset),
+            };
+            Ok(self.pos)
+        }
+    }
+
+    // Compares the patched output with the expected data instead of storing it
+    struct Expect {
+        data: Sparse,
+        pos: u64,
+        buffer: Vec<u8>,
+    }
+
+    impl std::io::Write for Expect {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.pos + buf.len() as u64 > self.data.size {
+                return Err(std::io::Error::other("output too long"));
+            }
+            self.buffer.resize(buf.len(), 0);
+            self.data.fill(self.pos, &mut self.buffer);
+            if self.buffer != buf {
+                return Err(std::io::Error::other(format!("mismatch near {}", self.pos)));
+            }
+            self.pos += buf.len() as u64;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_patch_roundtrip() {
+        let (old, new) = sample();
+        for thread_num in [1, 4] {
+            let mut diff = Cursor::new(vec![]);
+            safe_create_single_patch(&new, &old, &mut diff, 7, thread_num, None).unwrap();
+            assert_eq!(apply(&old, diff.get_ref(), new.len()), new);
+        }
+        let block_size = stream_match_block_size(old.len() as u64, 64 * 1024 * 1024);
+        for thread_num in [1, 4] {
+            let mut diff = Cursor::new(vec![]);
+            safe_create_single_patch_stream(
+                Cursor::new(&new),
+                Cursor::new(&old),
+                &mut diff,
+                block_size,
+                thread_num,
+                None,
+            )
+            .unwrap();
+            assert_eq!(apply(&old, diff.get_ref(), new.len()), new);
+        }
+    }
+
+    #[test]
+    fn test_zstd_patch_roundtrip() {
+        let (old, new) = sample();
+        let mut plain = Cursor::new(vec![]);
+        safe_create_single_patch(&new, &old, &mut plain, 7, 1, None).unwrap();
+        let mut diff = Cursor::new(vec![]);
+        safe_create_single_patch(&new, &old, &mut diff, 7, 1, Some(19)).unwrap();
+        assert!(diff.get_ref().len() < plain.get_ref().len());
+        assert_eq!(apply(&old, diff.get_ref(), new.len()), new);
+        let mut diff = Cursor::new(vec![]);
+        let block_size = stream_match_block_size(old.len() as u64, 64 * 1024 * 1024);
+        safe_create_single_patch_stream(
+            Cursor::new(&new),
+            Cursor::new(&old),
+            &mut diff,
+            block_size,
+            1,
+            Some(19),
+        )
+        .unwrap();
+        assert_eq!(apply(&old, diff.get_ref(), new.len()), new);
+    }
+
+    #[test]
+    fn test_patch_size_mismatch() {
+        let (old, new) = sample();
+        let mut diff = Cursor::new(vec![]);
+        safe_create_single_patch(&new, &old, &mut diff, 7, 1, Some(3)).unwrap();
+        for new_size in [new.len() - 1, new.len() + 1] {
+            let res = hpatch_sys::safe_patch_single_stream(
+                std::io::sink(),
+                Cursor::new(diff.get_ref()),
+                diff.get_ref().len(),
+                Cursor::new(&old),
+                old.len(),
+                new_size,
+            );
+            assert_eq!(res, hpatch_sys::PATCH_SIZE_MISMATCH);
+        }
+    }
+
+    #[test]
+    #[ignore = "diffs multi-GB inputs, run with --ignored"]
+    fn test_large_patch_roundtrip() {
+        let old_islands = [(0, 1), (GIB - 100, 2), (2 * GIB + 4096, 3)];
+        let new_islands = [
+            (0, 1),
+            (GIB + 12345, 2),
+            (2 * GIB + 4096, 4),
+            (3 * GIB - 70_000, 3),
+        ];
+        let old_size = 2 * GIB + 200_000;
+        let new_size = 3 * GIB;
+        let mut diff = Cursor::new(vec![]);
+        let block_size = stream_match_block_size(old_size, 64 * 1024 * 1024);
+        safe_create_single_patch_stream(
+            Sparse::new(new_size, &new_islands),
+            Sparse::new(old_size, &old_islands),
+            &mut diff,
+            block_size,
+            4,
+            Some(3),
+        )
+        .unwrap();
+        let diff = diff.into_inner();
+        let mut expect = Expect {
+            data: Sparse::new(new_size, &new_islands),
+            pos: 0,
+            buffer: vec![],
+        };
+        let res = hpatch_sys::safe_patch_single_stream(
+            &mut expect,
+            Cursor::new(&diff),
+            diff.len(),
+            Sparse::new(old_size, &old_islands),
+            old_size as usize,
+            new_size as usize,
+        );
+        assert_eq!(res, 1);
+        assert_eq!(expect.pos, new_size);
+        let res = hpatch_sys::safe_patch_single_stream(
+            std::io::sink(),
+            Cursor::new(&diff),
+            diff.len(),
+            Sparse::new(old_size, &old_islands),
+            old_size as usize,
+            GIB as usize,
+        );
+        assert_eq!(res, hpatch_sys::PATCH_SIZE_MISMATCH);
+    }
+}
@@ -0,0 +1,123 @@
+use std::ffi::{c_char, c_int};
+use std::io::{Read, Write};
+
+use crate::*;
+
+// Compress plugin that writes each compressed part of the diff as a zstd
+// frame, decompressed by hpatch-sys' zstd plugin.
+#[repr(C)]
+pub(crate) struct ZstdCompress {
+    base: hdiff_TCompress,
+    level: i32,
+}
+
+impl ZstdCompress {
+    pub(crate) fn new(level: i32) -> Self {
+        Self {
+            base: hdiff_TCompress {
+                compressType: Some(compress_type),
+                maxCompressedSize: Some(max_compressed_size),
+                setParallelThreadNumber: Some(set_parallel_thread_number),
+                compress: Some(compress),
+                compressTypeForDisplay: None,
+            },
+            level,
+        }
+    }
+
+    pub(crate) fn as_ptr(&self) -> *const hdiff_TCompress {
+        &self.base
+    }
+}
+
+struct StreamReader {
+    stream: *const hpatch_TStreamInput,
+    pos: u64,
+}
+
+impl Read for StreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let stream = unsafe { &*self.stream };
+        let len = (stream.streamSize - self.pos).min(buf.len() as u64) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+        let read = stream
+            .read
+            .ok_or_else(|| std::io::Error::other("no read"))?;
+        let data = buf.as_mut_ptr();
+        if unsafe { read(stream, self.pos, data, data.add(len)) } == 0 {
+            return Err(std::io::Error::other("read failed"));
+        }
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+struct StreamWriter {
+    stream: *const hpatch_TStreamOutput,
+    pos: u64,
+}
+
+impl Write for StreamWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let stream = unsafe { &*self.stream };
+        let write = stream
+            .write
+            .ok_or_else(|| std::io::Error::other("no write"))?;
+        let data = buf.as_ptr();
+        // hdiff cancels the write once the code is no smaller than the data
+        if unsafe { write(stream, self.pos, data, data.add(buf.len())) } == 0 {
+            return Err(std::io::Error::other("write canceled"));
+        }
+        self.pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+extern "C" fn compress_type() -> *const c_char {
+    c"zstd".as_ptr()
+}
+
+extern "C" fn max_compressed_size(data_size: hpatch_StreamPos_t) -> hpatch_StreamPos_t {
+    zstd::zstd_safe::compress_bound(data_size as usize) as hpatch_StreamPos_t
+}
+
+extern "C" fn set_parallel_thread_number(
+    _plugin: *mut hdiff_TCompress,
+    _thread_num: c_int,
+) -> c_int {
+    1
+}
+
+// return the compressed size, 0 on error or when compressing is not worth it
+extern "C" fn compress(
+    plugin: *const hdiff_TCompress,
+    out_code: *const hpatch_TStreamOutput,
+    in_data: *const hpatch_TStreamInput,
+) -> hpatch_StreamPos_t {
+    let level = unsafe { (*(plugin as *const ZstdCompress)).level };
+    let mut reader = StreamReader {
+        stream: in_data,
+        pos: 0,
+    };
+    let mut writer = StreamWriter {
+        stream: out_code,
+        pos: 0,
+    };
+    let result = (|| -> std::io::Result<()> {
+        let mut encoder = zstd::stream::write::Encoder::new(&mut writer, level)?;
+        encoder.include_checksum(false)?;
+        std::io::copy(&mut reader, &mut encoder)?;
+        encoder.finish()?;
+        Ok(())
+    })();
+    match result {
+        Ok(()) => writer.pos,
+        Err(_) => 0,
+    }
+}
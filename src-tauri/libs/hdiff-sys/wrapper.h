@@ -30,4 +30,13 @@ void create_single_compressed_diff(const unsigned char *newData, const unsigned
                                    int kMinSingleMatchScore,
                                    size_t patchStepMemSize,
                                    bool isUseBigCacheMatch,
-                                   void *listener, size_t threadNum);
\ No newline at end of file
+                                   void *listener, size_t threadNum);
+// create single compressed diff data by stream, memory requires O(oldSize*16/kMatchBlockSize)
+//   kMatchBlockSize: recommended (1<<4)--(1<<14), larger is faster and uses less memory, but out_diff size increase
+//   return false when I/O error,etc.
+bool create_single_compressed_diff_stream(const hpatch_TStreamInput *newData,
+                                          const hpatch_TStreamInput *oldData,
+                                          const hpatch_TStreamOutput *out_diff, const hdiff_TCompress *compressPlugin,
+                                          size_t kMatchBlockSize,
+                                          size_t patchStepMemSize,
+                                          size_t threadNum);
@@ -2,22 +2,47 @@
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 
-use std::{ffi::c_void, mem::ManuallyDrop};
+use std::{
+    ffi::{c_void, CStr},
+    mem::ManuallyDrop,
+};
 
 include!("../binding.rs");
 
+mod zstd_plugin;
+
 extern "C" fn on_diff_info(
     listener: *mut sspatch_listener_t,
     _info: *const hpatch_singleCompressedDiffInfo,
-    _out_decompress_plugin: *mut *mut hpatch_TDecompress,
+    out_decompress_plugin: *mut *mut hpatch_TDecompress,
     out_temp_cache: *mut *mut u8,
     out_temp_cache_end: *mut *mut u8,
 ) -> i32 {
     let listener = unsafe { &mut *listener };
     let info = unsafe { &*(_info as *const hpatch_singleCompressedDiffInfo) };
+    let buffer_info = unsafe { &mut *(listener.import as *mut BufferInfo) };
+    // a diff made for another version would write a file of the wrong size
+    if info.newDataSize != buffer_info.new_size {
+        println!(
+            "Diff new data size {} does not match expected {}",
+            info.newDataSize, buffer_info.new_size
+        );
+        buffer_info.size_mismatch = true;
+        return 0;
+    }
+    // empty for uncompressed diffs, otherwise only zstd from hdiff-sys is supported
+    let compress_type = unsafe { CStr::from_ptr(info.compressType.as_ptr()) };
+    if !compress_type.is_empty() {
+        if !zstd_plugin::can_open(compress_type) {
+            println!("Unsupported diff compress type: {:?}", compress_type);
+            return 0;
+        }
+        unsafe {
+            *out_decompress_plugin = &mut buffer_info.decompress_plugin;
+        }
+    }
     let buffer_size = info.stepMemSize as usize + hpatch_kStreamCacheSize as usize * 3;
     let mut buffer = ManuallyDrop::new(vec![0u8; buffer_size]);
-    let buffer_info = unsafe { &mut *(listener.import as *mut BufferInfo) };
     let buffer_slice = buffer.as_mut_ptr();
     let buffer_end = unsafe { buffer_slice.add(buffer_size) };
     buffer_info.buffer = Some(buffer);
@@ -32,8 +57,15 @@ extern "C" fn on_diff_info(
 pub struct BufferInfo {
     buffer: Option<ManuallyDrop<Vec<u8>>>,
     buffer_size: usize,
+    decompress_plugin: hpatch_TDecompress,
+    new_size: u64,
+    size_mismatch: bool,
 }
 
+/// Returned by [`safe_patch_single_stream`] when the diff does not produce
+/// exactly `output_size` bytes, e.g. a diff made for another file version.
+pub const PATCH_SIZE_MISMATCH: i32 = 6000;
+
 impl sspatch_listener_t {
     pub fn new_dummy(buffer: &mut BufferInfo) -> sspatch_listener_t {
         sspatch_listener_t {
@@ -130,11 +162,15 @@ pub fn safe_patch_single_stream(
     diff_size: usize,
     mut input: impl std::io::Read + std::io::Seek,
     input_size: usize,
+    output_size: usize,
 ) -> i32 {
     // 10k buffer
     let mut buffer_info = BufferInfo {
         buffer: None,
         buffer_size: 0,
+        decompress_plugin: zstd_plugin::zstd_decompress_plugin(),
+        new_size: output_size as u64,
+        size_mismatch: false,
     };
     let mut listener = sspatch_listener_t::new_dummy(&mut buffer_info);
     let listener_ptr = &mut listener as *mut sspatch_listener_t;
@@ -157,8 +193,7 @@ pub fn safe_patch_single_stream(
         read: Some(read_seq_callback),
     };
     let mut stream_output = hpatch_TStreamOutput {
-        // 1G
-        streamSize: 1 << 30,
+        streamSize: output_size as u64,
         streamImport: &mut output_wrapper as *mut WriteStreamWrapper as *mut c_void,
         write: Some(write_seq_callback),
         read_writed: None,
@@ -179,5 +214,9 @@ pub fn safe_patch_single_stream(
             ManuallyDrop::drop(&mut buffer);
         }
     }
+    // hpatch returns 2000 when the diff's new data is larger than the output
+    if buffer_info.size_mismatch || res == 2000 {
+        return PATCH_SIZE_MISMATCH;
+    }
     res
 }
@@ -0,0 +1,137 @@
+use std::ffi::{c_char, CStr};
+
+use zstd::stream::raw::{Decoder, InBuffer, Operation, OutBuffer};
+
+use crate::*;
+
+const CODE_BUFFER_SIZE: usize = 64 * 1024;
+
+// Compress type written by hdiff-sys' zstd compress plugin
+pub(crate) fn can_open(compress_type: &CStr) -> bool {
+    compress_type.to_bytes() == b"zstd"
+}
+
+struct DecompressHandle {
+    code_stream: *const hpatch_TStreamInput,
+    code_pos: u64,
+    code_end: u64,
+    decoder: Decoder<'static>,
+    buffer: Vec<u8>,
+    buffer_pos: usize,
+    buffer_len: usize,
+}
+
+impl DecompressHandle {
+    // read the next part of compressed code into buffer
+    fn fill(&mut self) -> bool {
+        let len = (self.code_end - self.code_pos).min(CODE_BUFFER_SIZE as u64) as usize;
+        if len == 0 {
+            return false;
+        }
+        let stream = unsafe { &*self.code_stream };
+        let Some(read) = stream.read else {
+            return false;
+        };
+        let data = self.buffer.as_mut_ptr();
+        if unsafe { read(stream, self.code_pos, data, data.add(len)) } == 0 {
+            return false;
+        }
+        self.code_pos += len as u64;
+        self.buffer_pos = 0;
+        self.buffer_len = len;
+        true
+    }
+
+    // fill out entirely, hpatch never asks for more than the uncompressed size
+    fn decompress(&mut self, out: &mut [u8]) -> bool {
+        let mut output = OutBuffer::around(out);
+        while output.pos() < output.capacity() {
+            if self.buffer_pos == self.buffer_len && !self.fill() {
+                return false;
+            }
+            let mut input = InBuffer::around(&self.buffer[self.buffer_pos..self.buffer_len]);
+            if self.decoder.run(&mut input, &mut output).is_err() {
+                return false;
+            }
+            self.buffer_pos += input.pos();
+        }
+        true
+    }
+}
+
+extern "C" fn is_can_open(compress_type: *const c_char) -> hpatch_BOOL {
+    can_open(unsafe { CStr::from_ptr(compress_type) }) as hpatch_BOOL
+}
+
+extern "C" fn open(
+    _plugin: *mut hpatch_TDecompress,
+    _data_size: hpatch_StreamPos_t,
+    code_stream: *const hpatch_TStreamInput,
+    code_begin: hpatch_StreamPos_t,
+    code_end: hpatch_StreamPos_t,
+) -> hpatch_decompressHandle {
+    let Ok(decoder) = Decoder::new() else {
+        return std::ptr::null_mut();
+    };
+    let handle = Box::new(DecompressHandle {
+        code_stream,
+        code_pos: code_begin,
+        code_end,
+        decoder,
+        buffer: vec![0u8; CODE_BUFFER_SIZE],
+        buffer_pos: 0,
+        buffer_len: 0,
+    });
+    Box::into_raw(handle) as hpatch_decompressHandle
+}
+
+extern "C" fn close(
+    _plugin: *mut hpatch_TDecompress,
+    handle: hpatch_decompressHandle,
+) -> hpatch_BOOL {
+    if !handle.is_null() {
+        drop(unsafe { Box::from_raw(handle as *mut DecompressHandle) });
+    }
+    1
+}
+
+extern "C" fn decompress_part(
+    handle: hpatch_decompressHandle,
+    out_part_data: *mut u8,
+    out_part_data_end: *mut u8,
+) -> hpatch_BOOL {
+    let handle = unsafe { &mut *(handle as *mut DecompressHandle) };
+    let len = unsafe { out_part_data_end.offset_from(out_part_data) } as usize;
+    let out = unsafe { std::slice::from_raw_parts_mut(out_part_data, len) };
+    handle.decompress(out) as hpatch_BOOL
+}
+
+extern "C" fn reset_code(
+    handle: hpatch_decompressHandle,
+    _data_size: hpatch_StreamPos_t,
+    code_stream: *const hpatch_TStreamInput,
+    code_begin: hpatch_StreamPos_t,
+    code_end: hpatch_StreamPos_t,
+) -> hpatch_BOOL {
+    let handle = unsafe { &mut *(handle as *mut DecompressHandle) };
+    if handle.decoder.reinit().is_err() {
+        return 0;
+    }
+    handle.code_stream = code_stream;
+    handle.code_pos = code_begin;
+    handle.code_end = code_end;
+    handle.buffer_pos = 0;
+    handle.buffer_len = 0;
+    1
+}
+
+pub(crate) fn zstd_decompress_plugin() -> hpatch_TDecompress {
+    hpatch_TDecompress {
+        is_can_open: Some(is_can_open),
+        open: Some(open),
+        close: Some(close),
+        decompress_part: Some(decompress_part),
+        reset_code: Some(reset_code),
+        decError: hpatch_dec_error_t_hpatch_dec_ok,
+    }
+}
@@ -0,0 +1,600 @@
+//! Packs that keep a valid Authenticode signature.
+//!
+//! The Authenticode hash skips the PE checksum, the security data directory and
+//! the certificate table itself. A signed pack therefore leaves the DOS stub
+//! untouched and stores the payload as a private `WIN_CERTIFICATE` after the
+//! signature:
+//!   signed exe | WIN_CERTIFICATE(signature) | WIN_CERTIFICATE(payload)
+//! The payload entry holds the pre-index header followed by the usual TLVs, with
+//! `base_end` pointing at `\0CONFIG`.
+
+use std::io::{self, SeekFrom};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+use crate::format::{
+    IndexHeader, DOS_STUB_STRING, INDEX_HEADER_LEN, INDEX_HEADER_MAGIC, INDEX_HEADER_SEARCH_LEN,
+};
+
+pub const WIN_CERT_REVISION_2_0: u16 = 0x0200;
+pub const WIN_CERT_TYPE_PKCS_SIGNED_DATA: u16 = 0x0002;
+/// Private certificate type of the payload entry, ignored by signature checks.
+pub const WIN_CERT_TYPE_KACHINA: u16 = 0x4b49;
+/// dwLength, wRevision and wCertificateType.
+pub const WIN_CERT_HEADER_LEN: u64 = 8;
+
+/// Enough to cover the PE headers.
+const PE_HEAD_LEN: u64 = 4096;
+
+/// Largest payload the private entry can hold: its length is a u32 that also
+/// counts the entry header and the pre-index header, and the table size padded
+/// to 8 bytes must be a u32 as well.
+pub const MAX_ATTACHED_PAYLOAD: u64 =
+    (u32::MAX as u64 & !7) - WIN_CERT_HEADER_LEN - INDEX_HEADER_LEN as u64;
+
+/// Where the certificate table of a PE file is described.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecurityDirectory {
+    /// File offset of the data directory entry itself.
+    pub entry_pos: usize,
+    /// File offset of the optional header checksum.
+    pub checksum_pos: usize,
+    /// File offset of the certificate table, 0 when unsigned.
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// Locate the security data directory. `data` only needs to cover the PE headers.
+pub fn get_security_directory(data: &[u8]) -> Option<SecurityDirectory> {
+    if data.get(0..2)? != b"MZ" {
+        return None;
+    }
+    let pe = u32::from_le_bytes(data.get(0x3c..0x40)?.try_into().ok()?) as usize;
+    if data.get(pe..pe.checked_add(4)?)? != b"PE\0\0" {
+        return None;
+    }
+    let coff = pe + 4;
+    let optional_size =
+        u16::from_le_bytes(data.get(coff + 16..coff + 18)?.try_into().ok()?) as usize;
+    let optional = coff + 20;
+    let magic = u16::from_le_bytes(data.get(optional..optional + 2)?.try_into().ok()?);
+    // PE32 and PE32+ differ in the size of the fields before the directories
+    let (count_pos, directories) = match magic {
+        0x10b => (optional + 92, optional + 96),
+        0x20b => (optional + 108, optional + 112),
+        _ => return None,
+    };
+    let count = u32::from_le_bytes(data.get(count_pos..count_pos + 4)?.try_into().ok()?);
+    let entry_pos = directories + 4 * 8;
+    if count < 5 || entry_pos + 8 > optional + optional_size {
+        return None;
+    }
+    let entry = data.get(entry_pos..entry_pos + 8)?;
+    Some(SecurityDirectory {
+        entry_pos,
+        checksum_pos: optional + 64,
+        offset: u32::from_le_bytes(entry[0..4].try_into().ok()?) as u64,
+        size: u32::from_le_bytes(entry[4..8].try_into().ok()?) as u64,
+    })
+}
+
+/// Header of a single certificate table entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WinCertificate {
+    /// File offset of the entry header.
+    pub offset: u64,
+    /// Length of the entry including its header, without the alignment padding.
+    pub length: u32,
+    pub revision: u16,
+    pub cert_type: u16,
+}
+
+impl WinCertificate {
+    pub fn parse(data: &[u8], offset: u64) -> Option<Self> {
+        let data = data.get(..WIN_CERT_HEADER_LEN as usize)?;
+        Some(WinCertificate {
+            offset,
+            length: u32::from_le_bytes(data[0..4].try_into().ok()?),
+            revision: u16::from_le_bytes(data[4..6].try_into().ok()?),
+            cert_type: u16::from_le_bytes(data[6..8].try_into().ok()?),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = self.length.to_le_bytes().to_vec();
+        data.extend_from_slice(&self.revision.to_le_bytes());
+        data.extend_from_slice(&self.cert_type.to_le_bytes());
+        data
+    }
+
+    pub fn content_offset(&self) -> u64 {
+        self.offset + WIN_CERT_HEADER_LEN
+    }
+
+    pub fn end(&self) -> u64 {
+        self.offset + self.length as u64
+    }
+
+    /// Entries are 8-byte aligned, so the next one starts after the padding.
+    pub fn next(&self) -> u64 {
+        align8(self.end())
+    }
+}
+
+fn align8(value: u64) -> u64 {
+    value.div_ceil(8) * 8
+}
+
+/// Find the payload entry in the certificate table of `inner`.
+pub async fn find_payload_certificate(
+    inner: &mut (impl AsyncRead + AsyncSeek + Unpin),
+    head: &[u8],
+    len: u64,
+) -> io::Result<Option<WinCertificate>> {
+    let Some(dir) = get_security_directory(head) else {
+        return Ok(None);
+    };
+    if dir.size == 0 {
+        return Ok(None);
+    }
+    // entries may run past the end of a truncated copy, only their headers must exist
+    let table_end = (dir.offset + dir.size).min(len);
+    let mut pos = dir.offset;
+    let mut header = [0u8; WIN_CERT_HEADER_LEN as usize];
+    while pos + WIN_CERT_HEADER_LEN <= table_end {
+        inner.seek(SeekFrom::Start(pos)).await?;
+        inner.read_exact(&mut header).await?;
+        let Some(cert) = WinCertificate::parse(&header, pos) else {
+            break;
+        };
+        if cert.cert_type == WIN_CERT_TYPE_KACHINA {
+            return Ok(Some(cert));
+        }
+        if (cert.length as u64) < WIN_CERT_HEADER_LEN {
+            break;
+        }
+        pos = cert.next();
+    }
+    Ok(None)
+}
+
+/// The exe to hand to the signing tool: `data` up to the payload, without the
+/// pre-index header in the DOS stub and without any certificate table.
+pub fn detached_base(data: &[u8]) -> Option<Vec<u8>> {
+    let dir = get_security_directory(data)?;
+    let mut base = data.to_vec();
+    if dir.size != 0 && (dir.offset as usize) < base.len() {
+        base.truncate(dir.offset as usize);
+    }
+    base[dir.entry_pos..dir.entry_pos + 8].fill(0);
+    let search = base.len().min(INDEX_HEADER_SEARCH_LEN);
+    if let Some(pos) = base[..search]
+        .windows(INDEX_HEADER_MAGIC.len())
+        .position(|w| w == INDEX_HEADER_MAGIC)
+    {
+        base[pos..pos + DOS_STUB_STRING.len()].copy_from_slice(DOS_STUB_STRING);
+    }
+    Some(base)
+}
+
+/// Whether `signed` is `unsigned` after signing, i.e. both hash to the same
+/// Authenticode digest apart from the alignment padding of the signing tool.
+pub fn same_image(unsigned: &[u8], signed: &[u8]) -> bool {
+    let (Some(unsigned), Some(signed)) = (authenticode_image(unsigned), authenticode_image(signed))
+    else {
+        return false;
+    };
+    signed.len() >= unsigned.len()
+        && signed.len() - unsigned.len() < 8
+        && signed[..unsigned.len()] == unsigned[..]
+        && signed[unsigned.len()..].iter().all(|&b| b == 0)
+}
+
+/// The bytes covered by the Authenticode hash, with the skipped fields zeroed.
+fn authenticode_image(data: &[u8]) -> Option<Vec<u8>> {
+    let dir = get_security_directory(data)?;
+    let mut end = data.len();
+    if dir.size != 0 {
+        end = end.min(dir.offset as usize);
+    }
+    let mut image = data[..end].to_vec();
+    image
+        .get_mut(dir.checksum_pos..dir.checksum_pos + 4)?
+        .fill(0);
+    image.get_mut(dir.entry_pos..dir.entry_pos + 8)?.fill(0);
+    Some(image)
+}
+
+/// Check that `payload_len` bytes fit into the certificate table of `base`,
+/// so callers can fail before writing anything.
+pub fn check_attached(base: &[u8], payload_len: u64) -> io::Result<()> {
+    attached_head(base, payload_len).map(|_| ())
+}
+
+/// `base` padded and pointed at the new certificate table, and the payload entry.
+fn attached_head(base: &[u8], payload_len: u64) -> io::Result<(Vec<u8>, WinCertificate)> {
+    if payload_len > MAX_ATTACHED_PAYLOAD {
+        return Err(invalid_input(&format!(
+            "Payload is {payload_len} bytes, the certificate table holds at most {} bytes (4 GiB)",
+            MAX_ATTACHED_PAYLOAD
+        )));
+    }
+    let dir =
+        get_security_directory(base).ok_or_else(|| invalid_input("Failed to parse PE header"))?;
+    if dir.size != 0 && dir.offset > base.len() as u64 {
+        return Err(invalid_input(
+            "Certificate table is not at the end of the exe",
+        ));
+    }
+    let mut head = base.to_vec();
+    // an unsigned exe gets a table of its own
+    let table_offset = if dir.size != 0 {
+        dir.offset
+    } else {
+        align8(head.len() as u64)
+    };
+    head.resize(align8(head.len() as u64) as usize, 0);
+    let cert = WinCertificate {
+        offset: head.len() as u64,
+        length: (WIN_CERT_HEADER_LEN + INDEX_HEADER_LEN as u64 + payload_len) as u32,
+        revision: WIN_CERT_REVISION_2_0,
+        cert_type: WIN_CERT_TYPE_KACHINA,
+    };
+    let table_size = u32::try_from(cert.next() - table_offset)
+        .map_err(|_| invalid_input("Certificate table must be smaller than 4 GiB"))?;
+    head[dir.entry_pos..dir.entry_pos + 4].copy_from_slice(&(table_offset as u32).to_le_bytes());
+    head[dir.entry_pos + 4..dir.entry_pos + 8].copy_from_slice(&table_size.to_le_bytes());
+    Ok((head, cert))
+}
+
+/// Write the (signed) `base` with `payload` stored after its certificate table.
+/// The certificate table of `base`, if any, must run to its end. `payload` is
+/// every entry from `\0CONFIG` on, at most [`MAX_ATTACHED_PAYLOAD`] bytes.
+/// Returns the header with `base_end` pointing at the payload, or the cleared
+/// header of a config-only pack.
+pub async fn write_attached(
+    output: &mut (impl AsyncWrite + Unpin),
+    base: &[u8],
+    header: &IndexHeader,
+    payload: impl AsyncRead + Unpin,
+    payload_len: u64,
+) -> io::Result<IndexHeader> {
+    let (head, cert) = attached_head(base, payload_len)?;
+    let mut header = *header;
+    if header.base_end != 0 {
+        header.base_end = u32::try_from(cert.content_offset() + INDEX_HEADER_LEN as u64)
+            .map_err(|_| invalid_input("Base must be smaller than 4 GiB"))?;
+    }
+    output.write_all(&head).await?;
+    output.write_all(&cert.to_bytes()).await?;
+    output.write_all(&header.to_bytes()).await?;
+    let copied = tokio::io::copy(&mut payload.take(payload_len), output).await?;
+    if copied != payload_len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("payload: expected {payload_len} bytes, got {copied}"),
+        ));
+    }
+    output
+        .write_all(&vec![0u8; (cert.next() - cert.end()) as usize])
+        .await?;
+    output.flush().await?;
+    Ok(header)
+}
+
+/// Counterpart of clearing the DOS stub header for packs in the certificate
+/// table: fit the payload entry to the end of a copy cut after its leading
+/// entries, and clear its header. Returns `false` if there is no payload entry.
+pub async fn clear_certificate_header(
+    file: &mut (impl AsyncRead + AsyncWrite + AsyncSeek + Unpin),
+) -> io::Result<bool> {
+    let len = file.seek(SeekFrom::End(0)).await?;
+    let mut head = vec![0u8; PE_HEAD_LEN.min(len) as usize];
+    file.seek(SeekFrom::Start(0)).await?;
+    file.read_exact(&mut head).await?;
+    let Some(dir) = get_security_directory(&head) else {
+        return Ok(false);
+    };
+    let Some(mut cert) = find_payload_certificate(file, &head, len).await? else {
+        return Ok(false);
+    };
+    if len < cert.content_offset() + INDEX_HEADER_LEN as u64 {
+        return Err(invalid_input("Payload certificate is truncated"));
+    }
+    cert.length = u32::try_from(len - cert.offset).map_err(|_| {
+        invalid_input("Payload in the certificate table must be smaller than 4 GiB")
+    })?;
+    file.seek(SeekFrom::Start(cert.offset)).await?;
+    file.write_all(&cert.to_bytes()).await?;
+    file.write_all(&IndexHeader::default().to_bytes()).await?;
+    file.seek(SeekFrom::Start(len)).await?;
+    file.write_all(&vec![0u8; (cert.next() - len) as usize])
+        .await?;
+    file.seek(SeekFrom::Start(dir.entry_pos as u64 + 4)).await?;
+    file.write_all(&((cert.next() - dir.offset) as u32).to_le_bytes())
+        .await?;
+    file.flush().await?;
+    Ok(true)
+}
+
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::format::{CONFIG_NAME, IMAGE_NAME, META_NAME};
+    use crate::reader::PackReader;
+    use crate::writer::{PackContent, PackFile, PackWriter};
+
+    const OPTIONAL: usize = 0x98;
+
+    /// Minimal PE32+ with a single section covering `len` bytes.
+    fn pe(len: usize) -> Vec<u8> {
+        let mut base = vec![0u8; len];
+        base[0..2].copy_from_slice(b"MZ");
+        base[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        base[0x4e..0x4e + DOS_STUB_STRING.len()].copy_from_slice(DOS_STUB_STRING);
+        base[0x80..0x84].copy_from_slice(b"PE\0\0");
+        base[0x86..0x88].copy_from_slice(&1u16.to_le_bytes());
+        // optional header with 16 data directories
+        base[0x94..0x96].copy_from_slice(&240u16.to_le_bytes());
+        base[OPTIONAL..OPTIONAL + 2].copy_from_slice(&0x20bu16.to_le_bytes());
+        base[OPTIONAL + 108..OPTIONAL + 112].copy_from_slice(&16u32.to_le_bytes());
+        let section = OPTIONAL + 240;
+        base[section + 16..section + 20].copy_from_slice(&((len - 0x400) as u32).to_le_bytes());
+        base[section + 20..section + 24].copy_from_slice(&0x400u32.to_le_bytes());
+        base
+    }
+
+    /// What a signing tool does: pad, append a signature entry, set the
+    /// directory and the checksum.
+    fn sign(unsigned: &[u8]) -> Vec<u8> {
+        let mut signed = unsigned.to_vec();
+        signed.resize(align8(signed.len() as u64) as usize, 0);
+        let cert = WinCertificate {
+            offset: signed.len() as u64,
+            length: 8 + 13,
+            revision: WIN_CERT_REVISION_2_0,
+            cert_type: WIN_CERT_TYPE_PKCS_SIGNED_DATA,
+        };
+        signed.extend(cert.to_bytes());
+        signed.extend(b"pkcs7 content");
+        signed.resize(cert.next() as usize, 0);
+        let dir = get_security_directory(&signed).unwrap();
+        signed[dir.entry_pos..dir.entry_pos + 4]
+            .copy_from_slice(&(cert.offset as u32).to_le_bytes());
+        signed[dir.entry_pos + 4..dir.entry_pos + 8].copy_from_slice(&24u32.to_le_bytes());
+        signed[dir.checksum_pos..dir.checksum_pos + 4].copy_from_slice(&0x1234u32.to_le_bytes());
+        signed
+    }
+
+    fn file(name: &str, content: &[u8]) -> PackFile {
+        PackFile {
+            name: name.to_string(),
+            size: content.len() as u64,
+            data: Box::new(Cursor::new(content.to_vec())),
+            checksum: None,
+        }
+    }
+
+    async fn pack(files: Vec<PackFile>) -> Vec<u8> {
+        let base = pe(0x1003);
+        let content = PackContent {
+            config: b"{}".to_vec(),
+            image: Some(file(IMAGE_NAME, b"png")),
+            metadata: Some(b"{\"tag_name\":\"1.0\"}".to_vec()),
+            files,
+        };
+        let mut writer = PackWriter::new(Vec::new());
+        writer
+            .write(base.as_slice(), base.len() as u64, content)
+            .await
+            .unwrap();
+        writer.into_inner()
+    }
+
+    /// detach, sign, attach
+    async fn sign_pack(data: &[u8]) -> Vec<u8> {
+        let reader = PackReader::new(Cursor::new(data)).await.unwrap();
+        let start = reader.base_end() as usize;
+        let end = match reader.certificate() {
+            Some(cert) => cert.end() as usize,
+            None => data.len(),
+        };
+        let header = reader.header().copied().unwrap_or_default();
+        let base = detached_base(&data[..start]).unwrap();
+        let signed = sign(&base);
+        assert!(same_image(&base, &signed));
+        let mut output = Vec::new();
+        write_attached(
+            &mut output,
+            &signed,
+            &header,
+            &data[start..end],
+            (end - start) as u64,
+        )
+        .await
+        .unwrap();
+        output
+    }
+
+    /// conda's `cli-64.exe` launcher, a PE32+ with a real Authenticode
+    /// signature over a SHA-256 digest.
+    const SIGNED_EXE: &[u8] = include_bytes!("../testdata/signed.exe");
+
+    /// The SHA-256 Authenticode digest, what the signature actually signs:
+    /// everything before the certificate table except the checksum and the
+    /// security directory.
+    fn authenticode_digest(data: &[u8]) -> [u8; 32] {
+        use sha2::{Digest, Sha256};
+        let dir = get_security_directory(data).unwrap();
+        let mut hasher = Sha256::new();
+        hasher.update(&data[..dir.checksum_pos]);
+        hasher.update(&data[dir.checksum_pos + 4..dir.entry_pos]);
+        hasher.update(&data[dir.entry_pos + 8..dir.offset as usize]);
+        hasher.finalize().into()
+    }
+
+    #[test]
+    fn test_security_directory() {
+        let unsigned = pe(0x1003);
+        let dir = get_security_directory(&unsigned).unwrap();
+        assert_eq!(dir.entry_pos, OPTIONAL + 112 + 4 * 8);
+        assert_eq!(dir.checksum_pos, OPTIONAL + 64);
+        assert_eq!((dir.offset, dir.size), (0, 0));
+
+        let signed = sign(&unsigned);
+        let dir = get_security_directory(&signed).unwrap();
+        assert_eq!((dir.offset, dir.size), (0x1008, 24));
+        let cert = WinCertificate::parse(&signed[0x1008..], 0x1008).unwrap();
+        assert_eq!(cert.cert_type, WIN_CERT_TYPE_PKCS_SIGNED_DATA);
+        assert_eq!(cert.next(), signed.len() as u64);
+        assert!(same_image(&unsigned, &signed));
+
+        let mut changed = signed.clone();
+        changed[0x500] = 1;
+        assert!(!same_image(&unsigned, &changed));
+        // PE32 keeps its directories 16 bytes earlier
+        let mut pe32 = unsigned.clone();
+        pe32[OPTIONAL..OPTIONAL + 2].copy_from_slice(&0x10bu16.to_le_bytes());
+        pe32[OPTIONAL + 92..OPTIONAL + 96].copy_from_slice(&16u32.to_le_bytes());
+        assert_eq!(
+            get_security_directory(&pe32).unwrap().entry_pos,
+            OPTIONAL + 96 + 4 * 8
+        );
+        assert!(get_security_directory(&unsigned[..0x100]).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_attached_pack() {
+        let data = pack(vec![file("a", b"aaaa"), file("b", b"bb")]).await;
+        let signed = sign_pack(&data).await;
+        // the signed image is left alone, the payload sits in the certificate table
+        let base = detached_base(&data[..0x1003]).unwrap();
+        assert!(same_image(&base, &signed));
+        assert_eq!(&signed[0x4e..0x4e + DOS_STUB_STRING.len()], DOS_STUB_STRING);
+        let dir = get_security_directory(&signed).unwrap();
+        assert_eq!(dir.offset + dir.size, signed.len() as u64);
+
+        let mut reader = PackReader::new(Cursor::new(signed.as_slice()))
+            .await
+            .unwrap();
+        let cert = *reader.certificate().unwrap();
+        assert_eq!(cert.offset, 0x1008 + 24);
+        let header = *reader.header().unwrap();
+        assert_eq!(header.base_end as u64, cert.content_offset() + 38);
+        assert_eq!(reader.config().await.unwrap().unwrap(), b"{}");
+        let a = reader.find("a").unwrap().clone();
+        assert_eq!(reader.read_entry(&a).await.unwrap(), b"aaaa");
+        let (_, index) = reader.index().await.unwrap().unwrap();
+        let original = PackReader::new(Cursor::new(data.as_slice()))
+            .await
+            .unwrap()
+            .index()
+            .await
+            .unwrap()
+            .unwrap()
+            .1;
+        assert_eq!(index, original);
+
+        // signing again replaces the old signature instead of stacking payloads
+        let resigned = sign_pack(&signed).await;
+        assert_eq!(resigned, signed);
+    }
+
+    #[tokio::test]
+    async fn test_attach_to_signed_exe() {
+        let dir = get_security_directory(SIGNED_EXE).unwrap();
+        let signature = &SIGNED_EXE[dir.offset as usize..];
+        let digest = authenticode_digest(SIGNED_EXE);
+        // the signature embeds the digest, so this is the hash Windows checks
+        assert!(signature.windows(digest.len()).any(|w| w == digest));
+
+        let data = pack(vec![file("a", b"aaaa")]).await;
+        let reader = PackReader::new(Cursor::new(data.as_slice())).await.unwrap();
+        let start = reader.base_end() as usize;
+        let header = *reader.header().unwrap();
+        let mut output = Vec::new();
+        write_attached(
+            &mut output,
+            SIGNED_EXE,
+            &header,
+            &data[start..],
+            (data.len() - start) as u64,
+        )
+        .await
+        .unwrap();
+
+        // the digest still matches and the signature entry is kept verbatim
+        assert_eq!(authenticode_digest(&output), digest);
+        assert_eq!(
+            &output[dir.offset as usize..(dir.offset + dir.size) as usize],
+            signature
+        );
+        // the table is the signature followed by the payload, both aligned,
+        // and runs to the end of the file
+        let new_dir = get_security_directory(&output).unwrap();
+        assert_eq!(new_dir.offset, dir.offset);
+        assert_eq!(new_dir.offset + new_dir.size, output.len() as u64);
+        let first = WinCertificate::parse(signature, dir.offset).unwrap();
+        assert_eq!(first.cert_type, WIN_CERT_TYPE_PKCS_SIGNED_DATA);
+        assert_eq!(first.next(), dir.offset + dir.size);
+        let payload =
+            WinCertificate::parse(&output[first.next() as usize..], first.next()).unwrap();
+        assert_eq!(payload.cert_type, WIN_CERT_TYPE_KACHINA);
+        assert_eq!(payload.next(), output.len() as u64);
+
+        let mut reader = PackReader::new(Cursor::new(output.as_slice()))
+            .await
+            .unwrap();
+        assert_eq!(reader.certificate(), Some(&payload));
+        let a = reader.find("a").unwrap().clone();
+        assert_eq!(reader.read_entry(&a).await.unwrap(), b"aaaa");
+    }
+
+    #[test]
+    fn test_attached_payload_limit() {
+        let unsigned = pe(0x1003);
+        assert!(check_attached(&unsigned, MAX_ATTACHED_PAYLOAD).is_ok());
+        let err = check_attached(&unsigned, MAX_ATTACHED_PAYLOAD + 1).unwrap_err();
+        assert!(err.to_string().contains("4 GiB"));
+        // the signature takes part of the table
+        assert!(check_attached(SIGNED_EXE, 1 << 20).is_ok());
+        assert!(check_attached(SIGNED_EXE, MAX_ATTACHED_PAYLOAD).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_clear_certificate_header() {
+        let signed = sign_pack(&pack(vec![file("a", b"aaaa")]).await).await;
+        let reader = PackReader::new(Cursor::new(signed.as_slice()))
+            .await
+            .unwrap();
+        // copy what the installer keeps: base, config and image
+        let image = reader.find(IMAGE_NAME).unwrap();
+        let mut copy = Cursor::new(signed[..image.offset + image.size].to_vec());
+        assert!(clear_certificate_header(&mut copy).await.unwrap());
+        let copy = copy.into_inner();
+        assert_eq!(copy.len() % 8, 0);
+        let dir = get_security_directory(&copy).unwrap();
+        assert_eq!(dir.offset + dir.size, copy.len() as u64);
+        assert!(same_image(
+            &detached_base(&signed[..0x1003]).unwrap(),
+            &copy
+        ));
+
+        let reader = PackReader::new(Cursor::new(copy.as_slice())).await.unwrap();
+        assert!(reader.header().is_none());
+        assert!(reader.certificate().is_some());
+        let names: Vec<_> = reader.entries().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, [CONFIG_NAME, IMAGE_NAME]);
+        assert!(reader.find(META_NAME).is_none());
+
+        // nothing to do for packs with the header in the DOS stub
+        let mut plain = Cursor::new(pack(vec![]).await);
+        assert!(!clear_certificate_header(&mut plain).await.unwrap());
+    }
+}
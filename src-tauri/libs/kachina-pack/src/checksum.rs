@@ -0,0 +1,240 @@
+use std::hash::Hasher;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, ReadBuf};
+use twox_hash::XxHash3_64;
+
+/// Error code carried by every checksum failure, so callers can tell a damaged
+/// entry apart from a broken connection or a bad zstd stream.
+pub const ENTRY_CHECKSUM_ERR: &str = "ENTRY_CHECKSUM_ERR";
+
+/// xxh3-64 of the stored (usually compressed) bytes of an entry.
+pub fn checksum(data: &[u8]) -> u64 {
+    XxHash3_64::oneshot(data)
+}
+
+/// Checksum of a whole stream, used when the entry is too large to buffer.
+pub async fn read_checksum(mut reader: impl AsyncRead + Unpin) -> io::Result<(u64, u64)> {
+    let mut hasher = XxHash3_64::new();
+    let mut buffer = vec![0u8; 256 * 1024];
+    let mut size = 0;
+    loop {
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.write(&buffer[..read]);
+        size += read as u64;
+    }
+    Ok((hasher.finish(), size))
+}
+
+/// Check a fully received entry, e.g. a buffered range response.
+pub fn verify_checksum(name: &str, data: &[u8], expected: u64) -> io::Result<()> {
+    let actual = checksum(data);
+    if actual != expected {
+        return Err(checksum_error(name, expected, actual));
+    }
+    Ok(())
+}
+
+fn checksum_error(name: &str, expected: u64, actual: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{ENTRY_CHECKSUM_ERR}: {name}: expected {expected:016x}, got {actual:016x}"),
+    )
+}
+
+/// Hashes the raw entry bytes as they pass through and fails the read once
+/// the stream ends with the wrong checksum, or runs past the expected size.
+///
+/// Put it below the zstd decoder so the compressed bytes are checked, and
+/// wrap the decoder in a [`DrainReader`] so the check actually runs.
+pub struct ChecksumReader<R> {
+    inner: R,
+    name: String,
+    expected: u64,
+    size: u64,
+    read: u64,
+    hasher: XxHash3_64,
+}
+
+impl<R: AsyncRead + Unpin> ChecksumReader<R> {
+    /// `name` only shows up in the error, to tell which entry is damaged.
+    pub fn new(inner: R, name: impl Into<String>, expected: u64, size: u64) -> Self {
+        ChecksumReader {
+            inner,
+            name: name.into(),
+            expected,
+            size,
+            read: 0,
+            hasher: XxHash3_64::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ChecksumReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => {}
+            other => return other,
+        }
+        let chunk = &buf.filled()[before..];
+        if chunk.is_empty() {
+            let actual = this.hasher.finish();
+            if this.read != this.size {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!(
+                        "{ENTRY_CHECKSUM_ERR}: {}: expected {} bytes, got {}",
+                        this.name, this.size, this.read
+                    ),
+                )));
+            }
+            if actual != this.expected {
+                return Poll::Ready(Err(checksum_error(&this.name, this.expected, actual)));
+            }
+            return Poll::Ready(Ok(()));
+        }
+        this.read += chunk.len() as u64;
+        if this.read > this.size {
+            // an error must not leave bytes in the buffer
+            buf.set_filled(before);
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{ENTRY_CHECKSUM_ERR}: {}: more than {} bytes received",
+                    this.name, this.size
+                ),
+            )));
+        }
+        this.hasher.write(chunk);
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Reads the stream below a decoder through to EOF once the decoder is done.
+///
+/// A zstd decoder stops at the end of its frame and never polls the reader
+/// below it again, so a [`ChecksumReader`] under it would never see EOF and
+/// never check the entry. `inner` returns that reader, e.g. `ZstdDecoder::get_mut`.
+pub struct DrainReader<D, R> {
+    decoder: D,
+    inner: fn(&mut D) -> &mut R,
+}
+
+impl<D, R> DrainReader<D, R> {
+    pub fn new(decoder: D, inner: fn(&mut D) -> &mut R) -> Self {
+        DrainReader { decoder, inner }
+    }
+}
+
+impl<D: AsyncRead + Unpin, R: AsyncBufRead + Unpin> AsyncRead for DrainReader<D, R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        match Pin::new(&mut this.decoder).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => {}
+            other => return other,
+        }
+        if buf.filled().len() > before || buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        let inner = (this.inner)(&mut this.decoder);
+        loop {
+            let read = match Pin::new(&mut *inner).poll_fill_buf(cx) {
+                Poll::Ready(Ok(data)) => data.len(),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            };
+            if read == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            Pin::new(&mut *inner).consume(read);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read_all(data: &[u8], expected: u64, size: u64) -> io::Result<Vec<u8>> {
+        let mut reader = ChecksumReader::new(data, "a.bin", expected, size);
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).await?;
+        Ok(out)
+    }
+
+    #[tokio::test]
+    async fn test_checksum_reader() {
+        let data = vec![7u8; 300 * 1024];
+        let sum = checksum(&data);
+        assert_eq!(
+            read_checksum(data.as_slice()).await.unwrap(),
+            (sum, data.len() as u64)
+        );
+        assert_eq!(read_all(&data, sum, data.len() as u64).await.unwrap(), data);
+
+        let mut damaged = data.clone();
+        damaged[1000] ^= 1;
+        let err = read_all(&damaged, sum, data.len() as u64)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("ENTRY_CHECKSUM_ERR: a.bin"));
+        // truncated and oversized responses are rejected as well
+        assert!(read_all(&data[1..], sum, data.len() as u64).await.is_err());
+        assert!(read_all(&data, sum, data.len() as u64 - 1).await.is_err());
+        assert!(verify_checksum("a.bin", &damaged, sum).is_err());
+    }
+
+    async fn decode_all(stored: &[u8], expected: u64) -> io::Result<Vec<u8>> {
+        use async_compression::tokio::bufread::ZstdDecoder;
+        use tokio::io::BufReader;
+
+        let reader = ChecksumReader::new(stored, "a.bin", expected, stored.len() as u64);
+        let decoder = ZstdDecoder::new(BufReader::new(reader));
+        let mut reader = DrainReader::new(decoder, ZstdDecoder::get_mut);
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).await?;
+        Ok(out)
+    }
+
+    #[tokio::test]
+    async fn test_drain_reader() {
+        // incompressible data is stored in raw blocks, and the frame has no
+        // zstd checksum, so a flipped byte decodes without a zstd error
+        let mut seed = 1u32;
+        let data: Vec<u8> = (0..64 * 1024)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        let stored = zstd::bulk::compress(&data, 3).unwrap();
+        let sum = checksum(&stored);
+        assert_eq!(decode_all(&stored, sum).await.unwrap(), data);
+
+        let mut damaged = stored.clone();
+        let middle = damaged.len() / 2;
+        damaged[middle] ^= 1;
+        let err = decode_all(&damaged, sum).await.unwrap_err();
+        assert!(err.to_string().starts_with("ENTRY_CHECKSUM_ERR: a.bin"));
+    }
+}
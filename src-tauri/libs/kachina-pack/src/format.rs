@@ -0,0 +1,415 @@
+use serde::{Deserialize, Serialize};
+
+// Packed installer layout:
+//   base exe | \0CONFIG | \0IMAGE? | \0INDEX? | \0META? | \0SIGN? | files... | appended...
+// Every entry is a TLV:
+//   header: !IN\0
+//   name length: 2 bytes big endian
+//   name: variable length
+//   content length: 4 bytes big endian, u32::MAX means a u64 follows
+//   content: variable length
+// The DOS stub of the base exe is overwritten by the pre-index header:
+//   !KachinaInstaller! base_end config_len image_len index_len meta_len (u32 BE)
+// Section lengths include the TLV header, base_end is an absolute offset.
+// Index offsets point at the content and are relative to base_end.
+// Index v3 adds a checksum of the stored bytes to every entry.
+// Signed packs add \0SIGN right after \0META, counted in meta_len and left out
+// of the index, see `signature`.
+// Authenticode-signed packs keep the DOS stub and move the header and every
+// entry into the certificate table, see `authenticode`.
+
+/// TLV size value that means "the real size follows as u64".
+pub const TLV_SIZE_EXTENDED: u32 = u32::MAX;
+/// Original index layout: u32 size and u32 offset per entry, no version prefix.
+pub const INDEX_VERSION_LEGACY: u8 = 1;
+/// 64-bit index layout, prefixed with `[0x00, version]`. A zero name length
+/// never occurs in a legacy index, so the prefix is unambiguous.
+pub const INDEX_VERSION_64: u8 = 2;
+/// 64-bit layout with an xxh3-64 checksum of the stored bytes after each
+/// offset. A zero checksum means the entry was packed without one.
+pub const INDEX_VERSION_CHECKSUM: u8 = 3;
+
+pub const INDEX_HEADER_MAGIC: &[u8] = b"!KachinaInstaller!";
+/// Magic followed by five u32 fields.
+pub const INDEX_HEADER_LEN: usize = INDEX_HEADER_MAGIC.len() + 5 * 4;
+/// The pre-index header replaces this string, so both must have the same length.
+pub const DOS_STUB_STRING: &[u8] = b"This program cannot be run in DOS mode";
+/// The pre-index header always lives in the DOS stub.
+pub const INDEX_HEADER_SEARCH_LEN: usize = 256;
+
+pub const CONFIG_NAME: &str = "\0CONFIG";
+pub const IMAGE_NAME: &str = "\0IMAGE";
+pub const INDEX_NAME: &str = "\0INDEX";
+pub const META_NAME: &str = "\0META";
+/// Optional build provenance, stored as a regular indexed entry.
+pub const BUILD_INFO_NAME: &str = "\0BUILDINFO";
+
+pub(crate) fn tlv_magic() -> Vec<u8> {
+    // never keep the marker verbatim in our own binary
+    "!in\0".to_ascii_uppercase().into_bytes()
+}
+
+pub fn get_header_size(name: &str, size: u64) -> usize {
+    let extended = if size >= TLV_SIZE_EXTENDED as u64 {
+        8
+    } else {
+        0
+    };
+    "!in\0".len() + 2 + name.len() + 4 + extended
+}
+
+pub fn tlv_header(name: &str, size: u64) -> Vec<u8> {
+    let mut header = tlv_magic();
+    let name = name.as_bytes();
+    header.extend_from_slice(&(name.len() as u16).to_be_bytes());
+    header.extend_from_slice(name);
+    if size >= TLV_SIZE_EXTENDED as u64 {
+        // u32::MAX marks a 64-bit size that follows
+        header.extend_from_slice(&TLV_SIZE_EXTENDED.to_be_bytes());
+        header.extend_from_slice(&size.to_be_bytes());
+    } else {
+        header.extend_from_slice(&(size as u32).to_be_bytes());
+    }
+    header
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Embedded {
+    pub name: String,
+    pub offset: usize,
+    pub raw_offset: usize,
+    pub size: usize,
+    /// From a v3 index, sent to the frontend as hex since JS numbers are f64.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "hex_checksum"
+    )]
+    pub checksum: Option<u64>,
+}
+
+/// A single `\0INDEX` record, `offset` is relative to `base_end`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub name: String,
+    pub size: u64,
+    pub offset: u64,
+    pub checksum: Option<u64>,
+}
+
+mod hex_checksum {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.serialize_str(&format!("{value:016x}")),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u64>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(value) => u64::from_str_radix(&value, 16)
+                .map(Some)
+                .map_err(serde::de::Error::custom),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IndexHeader {
+    pub base_end: u32,
+    pub config_len: u32,
+    pub image_len: u32,
+    pub index_len: u32,
+    pub meta_len: u32,
+}
+
+impl IndexHeader {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = INDEX_HEADER_MAGIC.to_vec();
+        data.extend_from_slice(&self.base_end.to_be_bytes());
+        data.extend_from_slice(&self.config_len.to_be_bytes());
+        data.extend_from_slice(&self.image_len.to_be_bytes());
+        data.extend_from_slice(&self.index_len.to_be_bytes());
+        data.extend_from_slice(&self.meta_len.to_be_bytes());
+        data
+    }
+
+    /// Read the header from the start of a file, `None` if missing or zeroed.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let search = &data[..data.len().min(INDEX_HEADER_SEARCH_LEN)];
+        let pos = search
+            .windows(INDEX_HEADER_MAGIC.len())
+            .position(|w| w == INDEX_HEADER_MAGIC)?;
+        let fields = pos + INDEX_HEADER_MAGIC.len();
+        let header = IndexHeader {
+            base_end: read_u32(data, fields)?,
+            config_len: read_u32(data, fields + 4)?,
+            image_len: read_u32(data, fields + 8)?,
+            index_len: read_u32(data, fields + 12)?,
+            meta_len: read_u32(data, fields + 16)?,
+        };
+        // config-only packs and installed copies carry a cleared header
+        if header.base_end == 0 {
+            return None;
+        }
+        Some(header)
+    }
+
+    /// End of config, image, index and meta, which are laid out back to back.
+    pub fn leading_end(&self) -> u64 {
+        self.base_end as u64
+            + self.config_len as u64
+            + self.image_len as u64
+            + self.index_len as u64
+            + self.meta_len as u64
+    }
+}
+
+pub(crate) fn read_u16(data: &[u8], pos: usize) -> Option<u16> {
+    let bytes = data.get(pos..pos.checked_add(2)?)?;
+    Some(u16::from_be_bytes(bytes.try_into().ok()?))
+}
+
+pub(crate) fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    let bytes = data.get(pos..pos.checked_add(4)?)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+pub(crate) fn read_u64(data: &[u8], pos: usize) -> Option<u64> {
+    let bytes = data.get(pos..pos.checked_add(8)?)?;
+    Some(u64::from_be_bytes(bytes.try_into().ok()?))
+}
+
+/// Pick the smallest index layout that can address every entry, so packs under
+/// 4 GiB stay readable by older installers.
+pub fn get_index_version(index: &[IndexEntry]) -> u8 {
+    // offsets are shifted by the index length later, so check against the larger layout
+    let index_bytes_len = index_to_bin(index, INDEX_VERSION_64).len() as u64;
+    let index_len = index_bytes_len + get_header_size(INDEX_NAME, index_bytes_len) as u64;
+    let fits = index.iter().all(|entry| {
+        entry.size < TLV_SIZE_EXTENDED as u64
+            && entry.offset + entry.size + index_len <= u32::MAX as u64
+    });
+    if fits {
+        INDEX_VERSION_LEGACY
+    } else {
+        INDEX_VERSION_64
+    }
+}
+
+pub fn index_to_bin(index: &[IndexEntry], version: u8) -> Vec<u8> {
+    let mut data = vec![];
+    if version != INDEX_VERSION_LEGACY {
+        data.push(0);
+        data.push(version);
+    }
+    // u8: name_len var: name u32/u64: size u32/u64: offset (u64: checksum)
+    for entry in index.iter() {
+        let name = entry.name.as_bytes();
+        data.push(name.len() as u8);
+        data.extend_from_slice(name);
+        if version == INDEX_VERSION_LEGACY {
+            data.extend_from_slice(&(entry.size as u32).to_be_bytes());
+            data.extend_from_slice(&(entry.offset as u32).to_be_bytes());
+        } else {
+            data.extend_from_slice(&entry.size.to_be_bytes());
+            data.extend_from_slice(&entry.offset.to_be_bytes());
+        }
+        if version >= INDEX_VERSION_CHECKSUM {
+            data.extend_from_slice(&entry.checksum.unwrap_or_default().to_be_bytes());
+        }
+    }
+    data
+}
+
+/// Decode `\0INDEX` content, `None` if it is truncated.
+pub fn bin_to_index(content: &[u8]) -> Option<(u8, Vec<IndexEntry>)> {
+    // legacy: u8: name_len var: name u32: size u32: offset
+    // v2: [0x00, version] then u8: name_len var: name u64: size u64: offset
+    // v3: same as v2 with u64: checksum after the offset
+    let mut pos = 0;
+    let mut version = INDEX_VERSION_LEGACY;
+    if content.len() >= 2 && content[0] == 0 {
+        version = content[1];
+        pos = 2;
+    }
+    let wide = version >= INDEX_VERSION_64;
+    let read_field = |pos: usize| -> Option<u64> {
+        if wide {
+            read_u64(content, pos)
+        } else {
+            read_u32(content, pos).map(|x| x as u64)
+        }
+    };
+    let field_len = if wide { 8 } else { 4 };
+    let mut entries = Vec::new();
+    while pos < content.len() {
+        let name_len = content[pos] as usize;
+        pos += 1;
+        let name = content.get(pos..pos + name_len)?;
+        let name = String::from_utf8_lossy(name).to_string();
+        pos += name_len;
+        let size = read_field(pos)?;
+        pos += field_len;
+        let offset = read_field(pos)?;
+        pos += field_len;
+        let mut checksum = None;
+        if version >= INDEX_VERSION_CHECKSUM {
+            checksum = Some(read_u64(content, pos)?).filter(|&c| c != 0);
+            pos += 8;
+        }
+        entries.push(IndexEntry {
+            name,
+            size,
+            offset,
+            checksum,
+        });
+    }
+    Some((version, entries))
+}
+
+/// Decode `\0INDEX` content into absolute positions, given the pack's `base_end`.
+pub fn parse_index(content: &[u8], start_offset: usize) -> Option<Vec<Embedded>> {
+    let (_, entries) = bin_to_index(content)?;
+    entries
+        .into_iter()
+        .map(|entry| {
+            let offset = (start_offset as u64).checked_add(entry.offset)?;
+            let raw_offset = offset.checked_sub(get_header_size(&entry.name, entry.size) as u64)?;
+            Some(Embedded {
+                name: entry.name,
+                offset: usize::try_from(offset).ok()?,
+                raw_offset: usize::try_from(raw_offset).ok()?,
+                size: usize::try_from(entry.size).ok()?,
+                checksum: entry.checksum,
+            })
+        })
+        .collect()
+}
+
+/// End of the last PE section, i.e. where appended data starts.
+/// `data` only needs to cover the PE headers.
+pub fn get_overlay_offset(data: &[u8], file_len: u64) -> Option<u64> {
+    if data.get(0..2)? != b"MZ" {
+        return None;
+    }
+    let pe = u32::from_le_bytes(data.get(0x3c..0x40)?.try_into().ok()?) as usize;
+    if data.get(pe..pe.checked_add(4)?)? != b"PE\0\0" {
+        return None;
+    }
+    let coff = pe + 4;
+    let sections = u16::from_le_bytes(data.get(coff + 2..coff + 4)?.try_into().ok()?) as usize;
+    let optional_size =
+        u16::from_le_bytes(data.get(coff + 16..coff + 18)?.try_into().ok()?) as usize;
+    let table = coff + 20 + optional_size;
+    let mut end = 0;
+    for i in 0..sections {
+        let section = data.get(table + i * 40..table + (i + 1) * 40)?;
+        let raw_size = u32::from_le_bytes(section[16..20].try_into().ok()?) as u64;
+        let raw_ptr = u32::from_le_bytes(section[20..24].try_into().ok()?) as u64;
+        end = end.max(raw_ptr + raw_size);
+    }
+    if end == 0 || end > file_len {
+        return None;
+    }
+    Some(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<IndexEntry> {
+        vec![
+            IndexEntry {
+                name: CONFIG_NAME.to_string(),
+                size: 16,
+                offset: get_header_size(CONFIG_NAME, 16) as u64,
+                checksum: None,
+            },
+            IndexEntry {
+                name: "a.bin".to_string(),
+                size: 5,
+                offset: 100,
+                checksum: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_index_roundtrip() {
+        let index = entries();
+        assert_eq!(get_index_version(&index), INDEX_VERSION_LEGACY);
+        for version in [INDEX_VERSION_LEGACY, INDEX_VERSION_64] {
+            let bin = index_to_bin(&index, version);
+            assert_eq!(bin_to_index(&bin), Some((version, index.clone())));
+        }
+    }
+
+    #[test]
+    fn test_checksum_index() {
+        let mut index = entries();
+        index[1].checksum = Some(0x0123_4567_89ab_cdef);
+        let bin = index_to_bin(&index, INDEX_VERSION_CHECKSUM);
+        assert_eq!(bin[..2], [0, INDEX_VERSION_CHECKSUM]);
+        assert_eq!(bin_to_index(&bin), Some((INDEX_VERSION_CHECKSUM, index)));
+        let embedded = parse_index(&bin, 1000).unwrap();
+        assert_eq!(embedded[0].checksum, None);
+        assert_eq!(embedded[1].checksum, Some(0x0123_4567_89ab_cdef));
+        assert!(bin_to_index(&bin[..bin.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn test_index_version_for_large_payload() {
+        let mut index = entries();
+        index[1].size = 5 << 32;
+        assert_eq!(get_index_version(&index), INDEX_VERSION_64);
+        let embedded = parse_index(&index_to_bin(&index, INDEX_VERSION_64), 1000).unwrap();
+        assert_eq!(embedded[1].size, 5 << 32);
+        assert_eq!(embedded[1].offset, 1100);
+        assert_eq!(
+            embedded[1].raw_offset,
+            1100 - get_header_size("a.bin", 5 << 32)
+        );
+    }
+
+    #[test]
+    fn test_truncated_index() {
+        let bin = index_to_bin(&entries(), INDEX_VERSION_64);
+        assert!(bin_to_index(&bin[..bin.len() - 1]).is_none());
+        assert!(parse_index(&bin[..bin.len() - 1], 0).is_none());
+    }
+
+    #[test]
+    fn test_index_header() {
+        let header = IndexHeader {
+            base_end: 0x1000,
+            config_len: 20,
+            image_len: 0,
+            index_len: 30,
+            meta_len: 40,
+        };
+        let bytes = header.to_bytes();
+        assert_eq!(bytes.len(), DOS_STUB_STRING.len());
+        let mut data = vec![0u8; 0x80];
+        data[0x4e..0x4e + bytes.len()].copy_from_slice(&bytes);
+        assert_eq!(IndexHeader::parse(&data), Some(header));
+        assert_eq!(header.leading_end(), 0x1000 + 90);
+        // cleared header
+        data[0x4e..0x4e + bytes.len()].copy_from_slice(&IndexHeader::default().to_bytes());
+        assert_eq!(IndexHeader::parse(&data), None);
+    }
+
+    #[test]
+    fn test_extended_tlv_header() {
+        assert_eq!(tlv_header("a", 1).len(), get_header_size("a", 1));
+        let header = tlv_header("a", 5 << 32);
+        assert_eq!(header.len(), get_header_size("a", 5 << 32));
+        assert_eq!(&header[7..11], &TLV_SIZE_EXTENDED.to_be_bytes());
+    }
+}
@@ -0,0 +1,39 @@
+//! Kachina pack container: a base exe followed by TLV entries, located through
+//! the `!KachinaInstaller!` pre-index header and the `\0INDEX` entry.
+//!
+//! Platform independent, used by both the installer and the builder.
+
+pub mod authenticode;
+pub mod checksum;
+pub mod format;
+pub mod reader;
+pub mod resource;
+pub mod signature;
+pub mod writer;
+
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+pub use authenticode::{
+    check_attached, clear_certificate_header, detached_base, get_security_directory, same_image,
+    write_attached, SecurityDirectory, WinCertificate, MAX_ATTACHED_PAYLOAD,
+};
+pub use checksum::{
+    checksum, read_checksum, verify_checksum, ChecksumReader, DrainReader, ENTRY_CHECKSUM_ERR,
+};
+pub use format::{
+    bin_to_index, get_header_size, get_index_version, get_overlay_offset, index_to_bin,
+    parse_index, tlv_header, Embedded, IndexEntry, IndexHeader, BUILD_INFO_NAME, CONFIG_NAME,
+    IMAGE_NAME, INDEX_HEADER_LEN, INDEX_NAME, INDEX_VERSION_64, INDEX_VERSION_CHECKSUM,
+    INDEX_VERSION_LEGACY, META_NAME, TLV_SIZE_EXTENDED,
+};
+pub use reader::PackReader;
+pub use resource::{
+    read_resources, write_resources, Resource, ResourceName, ResourceTable, VersionBlock,
+};
+pub use signature::{
+    parse_public_key, parse_signing_key, sign, verify_signature, SignatureError, SIGNATURE_ERR,
+    SIGNATURE_NAME,
+};
+pub use writer::{
+    write_base, write_file, write_header, PackContent, PackFile, PackLayout, PackWriter,
+};
@@ -0,0 +1,579 @@
+use std::collections::HashSet;
+use std::io::{self, SeekFrom};
+
+use ed25519_dalek::VerifyingKey;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, Take};
+
+use crate::authenticode::{find_payload_certificate, WinCertificate};
+use crate::format::{
+    bin_to_index, get_header_size, get_overlay_offset, read_u16, read_u32, read_u64, tlv_magic,
+    Embedded, IndexEntry, IndexHeader, CONFIG_NAME, IMAGE_NAME, INDEX_HEADER_LEN, INDEX_NAME,
+    META_NAME, TLV_SIZE_EXTENDED,
+};
+use crate::signature::{verify_signature, SignatureError, SIGNATURE_NAME};
+
+/// Enough to cover the DOS stub and the PE section table.
+const HEAD_LEN: u64 = 4096;
+/// Chunk size of the legacy marker scan.
+const SCAN_CHUNK: u64 = 64 * 1024;
+/// Entries written by the packer, which may appear only once.
+const RESERVED_NAMES: [&str; 5] = [
+    CONFIG_NAME,
+    IMAGE_NAME,
+    INDEX_NAME,
+    META_NAME,
+    SIGNATURE_NAME,
+];
+
+/// Reads a packed installer from any seekable stream. Use a `Cursor` over the
+/// mapped bytes for mmap.
+///
+/// Entries are located through the `!KachinaInstaller!` header and `\0INDEX`,
+/// with the header either in the DOS stub or in the certificate table.
+/// Packs with a cleared header are walked from the end of the PE image, and the
+/// full marker scan is only used when neither works.
+pub struct PackReader<R> {
+    inner: R,
+    len: u64,
+    header: Option<IndexHeader>,
+    certificate: Option<WinCertificate>,
+    index_version: Option<u8>,
+    entries: Vec<Embedded>,
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> PackReader<R> {
+    /// Entries appended after the pack are kept, but a second copy of a
+    /// reserved entry is rejected: lookups and the signature check take the
+    /// first one, so an appended copy would otherwise go unverified.
+    pub async fn new(inner: R) -> io::Result<Self> {
+        let mut reader = Self::locate(inner).await?;
+        if reader.entries.is_empty() {
+            reader.entries = reader.scan_entries().await?;
+            // legacy packs predate signing, and the scan may pick up stray
+            // markers in the base exe
+            if reader.find(SIGNATURE_NAME).is_none() {
+                return Ok(reader);
+            }
+        }
+        check_duplicates(&reader.entries, |name| RESERVED_NAMES.contains(&name))?;
+        Ok(reader)
+    }
+
+    /// Entries found through the header or from the end of the PE image,
+    /// none if the pack has to be scanned.
+    async fn locate(inner: R) -> io::Result<Self> {
+        let mut reader = PackReader {
+            inner,
+            len: 0,
+            header: None,
+            certificate: None,
+            index_version: None,
+            entries: Vec::new(),
+        };
+        reader.len = reader.inner.seek(SeekFrom::End(0)).await?;
+        let head = reader.read_at(0, HEAD_LEN.min(reader.len)).await?;
+        if let Some(header) = IndexHeader::parse(&head) {
+            if let Some(entries) = reader.parse_with_header(&header).await? {
+                reader.header = Some(header);
+                reader.entries = entries;
+                return Ok(reader);
+            }
+        } else if let Some(cert) =
+            find_payload_certificate(&mut reader.inner, &head, reader.len).await?
+        {
+            reader.certificate = Some(cert);
+            let header_pos = cert.content_offset();
+            if header_pos + INDEX_HEADER_LEN as u64 <= reader.len {
+                let raw = reader.read_at(header_pos, INDEX_HEADER_LEN as u64).await?;
+                if let Some(header) = IndexHeader::parse(&raw) {
+                    if let Some(entries) = reader.parse_with_header(&header).await? {
+                        reader.header = Some(header);
+                        reader.entries = entries;
+                        return Ok(reader);
+                    }
+                } else {
+                    // copies cut after the leading entries carry a cleared header
+                    let entries = reader
+                        .walk_entries(header_pos + INDEX_HEADER_LEN as u64)
+                        .await?;
+                    if !entries.is_empty() {
+                        reader.entries = entries;
+                        return Ok(reader);
+                    }
+                }
+            }
+        } else if let Some(overlay) = get_overlay_offset(&head, reader.len) {
+            // packs without files have a cleared header, but still start at the overlay
+            let entries = reader.walk_entries(overlay).await?;
+            if !entries.is_empty() {
+                reader.entries = entries;
+                return Ok(reader);
+            }
+        }
+        Ok(reader)
+    }
+
+    /// Only follow the entries starting at `pos`, for data that is not a whole
+    /// pack such as a remotely fetched header.
+    ///
+    /// The entries must fill the data up to the end and every name may only
+    /// appear once, so nothing unsigned can hide next to the signed entries.
+    pub async fn walk(inner: R, pos: u64) -> io::Result<Self> {
+        let mut reader = PackReader {
+            inner,
+            len: 0,
+            header: None,
+            certificate: None,
+            index_version: None,
+            entries: Vec::new(),
+        };
+        reader.len = reader.inner.seek(SeekFrom::End(0)).await?;
+        reader.entries = reader.walk_entries(pos).await?;
+        let end = reader
+            .entries
+            .last()
+            .map(|e| (e.offset + e.size) as u64)
+            .unwrap_or(pos);
+        if end != reader.len {
+            return Err(invalid_data("Unexpected data after the last entry"));
+        }
+        check_duplicates(&reader.entries, |_| true)?;
+        Ok(reader)
+    }
+
+    /// The pre-index header, `None` for config-only or legacy packs.
+    pub fn header(&self) -> Option<&IndexHeader> {
+        self.header.as_ref()
+    }
+
+    /// The private certificate table entry holding the payload of an
+    /// Authenticode-signed pack, see [`crate::authenticode`].
+    pub fn certificate(&self) -> Option<&WinCertificate> {
+        self.certificate.as_ref()
+    }
+
+    /// Layout of `\0INDEX`, if the pack has one.
+    pub fn index_version(&self) -> Option<u8> {
+        self.index_version
+    }
+
+    pub fn entries(&self) -> &[Embedded] {
+        &self.entries
+    }
+
+    pub fn into_entries(self) -> Vec<Embedded> {
+        self.entries
+    }
+
+    pub fn file_len(&self) -> u64 {
+        self.len
+    }
+
+    /// Size of the base exe, i.e. where the first entry starts.
+    pub fn base_end(&self) -> u64 {
+        if let Some(header) = self.header.as_ref() {
+            return header.base_end as u64;
+        }
+        self.entries
+            .first()
+            .map(|e| e.raw_offset as u64)
+            .unwrap_or(self.len)
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Embedded> {
+        self.entries.iter().find(|e| e.name == name)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    pub async fn read_entry(&mut self, entry: &Embedded) -> io::Result<Vec<u8>> {
+        self.read_at(entry.offset as u64, entry.size as u64).await
+    }
+
+    pub async fn entry_reader(&mut self, entry: &Embedded) -> io::Result<Take<&mut R>> {
+        self.inner
+            .seek(SeekFrom::Start(entry.offset as u64))
+            .await?;
+        Ok((&mut self.inner).take(entry.size as u64))
+    }
+
+    async fn read_named(&mut self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        match self.find(name).cloned() {
+            Some(entry) => Ok(Some(self.read_entry(&entry).await?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn config(&mut self) -> io::Result<Option<Vec<u8>>> {
+        self.read_named(CONFIG_NAME).await
+    }
+
+    pub async fn image(&mut self) -> io::Result<Option<Vec<u8>>> {
+        self.read_named(IMAGE_NAME).await
+    }
+
+    pub async fn metadata(&mut self) -> io::Result<Option<Vec<u8>>> {
+        self.read_named(META_NAME).await
+    }
+
+    /// Decoded `\0INDEX` together with its layout version.
+    pub async fn index(&mut self) -> io::Result<Option<(u8, Vec<IndexEntry>)>> {
+        match self.read_named(INDEX_NAME).await? {
+            Some(content) => bin_to_index(&content)
+                .map(Some)
+                .ok_or_else(|| invalid_data("Malformed index")),
+            None => Ok(None),
+        }
+    }
+
+    /// Check `\0SIGN` against config, metadata and index. The outer error is
+    /// for I/O, the inner one for the signature itself.
+    pub async fn verify_signature(
+        &mut self,
+        trusted: &[VerifyingKey],
+    ) -> io::Result<Result<(), SignatureError>> {
+        let Some(config) = self.config().await? else {
+            return Ok(Err(SignatureError::Missing));
+        };
+        let metadata = self.metadata().await?;
+        let index = self.read_named(INDEX_NAME).await?;
+        let signature = self.read_named(SIGNATURE_NAME).await?;
+        Ok(verify_signature(
+            signature.as_deref(),
+            trusted,
+            &config,
+            metadata.as_deref(),
+            index.as_deref(),
+        ))
+    }
+
+    pub async fn read_at(&mut self, pos: u64, len: u64) -> io::Result<Vec<u8>> {
+        self.inner.seek(SeekFrom::Start(pos)).await?;
+        let mut buf = vec![0u8; len as usize];
+        self.inner.read_exact(&mut buf).await?;
+        Ok(buf)
+    }
+
+    /// Read a single TLV entry at `pos`, `None` if there is no complete entry there.
+    pub async fn read_tlv(&mut self, pos: u64) -> io::Result<Option<Embedded>> {
+        if pos + 10 > self.len {
+            return Ok(None);
+        }
+        let head = self.read_at(pos, 6).await?;
+        if head[0..4] != tlv_magic()[..] {
+            return Ok(None);
+        }
+        let name_length = read_u16(&head, 4).unwrap_or_default() as u64;
+        let mut mem_pos_content = pos + 6 + name_length + 4;
+        if mem_pos_content > self.len {
+            return Ok(None);
+        }
+        let rest = self.read_at(pos + 6, name_length + 4).await?;
+        let name = String::from_utf8_lossy(&rest[..name_length as usize]).to_string();
+        let mut content_length = read_u32(&rest, name_length as usize).unwrap_or_default() as u64;
+        if content_length == TLV_SIZE_EXTENDED as u64 {
+            // 64-bit content length follows
+            if mem_pos_content + 8 > self.len {
+                return Ok(None);
+            }
+            let extended = self.read_at(mem_pos_content, 8).await?;
+            content_length = read_u64(&extended, 0).unwrap_or_default();
+            mem_pos_content += 8;
+        }
+        match mem_pos_content.checked_add(content_length) {
+            Some(end) if end <= self.len => Ok(Some(Embedded {
+                name,
+                offset: mem_pos_content as usize,
+                size: content_length as usize,
+                raw_offset: pos as usize,
+                checksum: None,
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    /// Follow consecutive TLV entries starting at `pos` until one is missing.
+    async fn walk_entries(&mut self, mut pos: u64) -> io::Result<Vec<Embedded>> {
+        let mut entries = Vec::new();
+        while let Some(entry) = self.read_tlv(pos).await? {
+            pos = (entry.offset + entry.size) as u64;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    async fn parse_with_header(
+        &mut self,
+        header: &IndexHeader,
+    ) -> io::Result<Option<Vec<Embedded>>> {
+        let mut entries = Vec::new();
+        let leading_end = header.leading_end();
+        let mut pos = header.base_end as u64;
+        while pos < leading_end {
+            let Some(entry) = self.read_tlv(pos).await? else {
+                return Ok(None);
+            };
+            pos = (entry.offset + entry.size) as u64;
+            entries.push(entry);
+        }
+        if pos != leading_end || entries.first().map(|e| e.name.as_str()) != Some(CONFIG_NAME) {
+            return Ok(None);
+        }
+        let mut end = leading_end;
+        if let Some(index) = entries.iter().find(|e| e.name == INDEX_NAME).cloned() {
+            let content = self.read_entry(&index).await?;
+            let Some((version, indexed)) = bin_to_index(&content) else {
+                return Ok(None);
+            };
+            self.index_version = Some(version);
+            let mut files = Vec::new();
+            for item in indexed {
+                let content_offset = header.base_end as u64 + item.offset;
+                if content_offset < leading_end {
+                    // config, image and meta are listed in the index as well
+                    continue;
+                }
+                // only trust the index if it points at a matching entry
+                let raw_offset =
+                    content_offset.checked_sub(get_header_size(&item.name, item.size) as u64);
+                let entry = match raw_offset {
+                    Some(raw_offset) => self.read_tlv(raw_offset).await?,
+                    None => None,
+                };
+                match entry {
+                    Some(entry)
+                        if entry.name == item.name
+                            && entry.offset as u64 == content_offset
+                            && entry.size as u64 == item.size =>
+                    {
+                        end = end.max((entry.offset + entry.size) as u64);
+                        files.push(Embedded {
+                            checksum: item.checksum,
+                            ..entry
+                        });
+                    }
+                    _ => {
+                        self.index_version = None;
+                        return Ok(None);
+                    }
+                }
+            }
+            entries.extend(files);
+        }
+        // entries appended after packing are not in the index
+        entries.extend(self.walk_entries(end).await?);
+        Ok(Some(entries))
+    }
+
+    /// Legacy fallback: scan the whole file for TLV markers.
+    async fn scan_entries(&mut self) -> io::Result<Vec<Embedded>> {
+        let magic = tlv_magic();
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        while pos + magic.len() as u64 <= self.len {
+            let chunk = self.read_at(pos, SCAN_CHUNK.min(self.len - pos)).await?;
+            match chunk.windows(magic.len()).position(|w| w == magic) {
+                Some(i) => {
+                    let at = pos + i as u64;
+                    match self.read_tlv(at).await? {
+                        Some(entry) => {
+                            // skip markers inside the content
+                            pos = (entry.offset + entry.size) as u64;
+                            entries.push(entry);
+                        }
+                        None => pos = at + 1,
+                    }
+                }
+                // keep the tail in case a marker crosses the chunk boundary
+                None => pos += (chunk.len() - (magic.len() - 1)) as u64,
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// Fail on the first repeated name among the entries picked by `filter`.
+fn check_duplicates(entries: &[Embedded], filter: impl Fn(&str) -> bool) -> io::Result<()> {
+    let mut names = HashSet::new();
+    match entries
+        .iter()
+        .find(|e| filter(&e.name) && !names.insert(&e.name))
+    {
+        Some(entry) => Err(invalid_data(&format!("Duplicate entry {:?}", entry.name))),
+        None => Ok(()),
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::format::{tlv_header, IndexHeader};
+    use crate::writer::{PackContent, PackFile, PackWriter};
+
+    fn tlv(name: &str, content: &[u8]) -> Vec<u8> {
+        let mut out = tlv_header(name, content.len() as u64);
+        out.extend_from_slice(content);
+        out
+    }
+
+    /// Minimal PE with one section covering `len` bytes, a DOS stub to hold the
+    /// header, and a stray marker inside the code section.
+    fn base_exe(len: usize) -> Vec<u8> {
+        let mut base = vec![0u8; len];
+        base[0..2].copy_from_slice(b"MZ");
+        base[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        base[0x4e..0x4e + 38].copy_from_slice(b"This program cannot be run in DOS mode");
+        base[0x80..0x84].copy_from_slice(b"PE\0\0");
+        base[0x86..0x88].copy_from_slice(&1u16.to_le_bytes());
+        let section = 0x98;
+        base[section + 16..section + 20].copy_from_slice(&((len - 0x200) as u32).to_le_bytes());
+        base[section + 20..section + 24].copy_from_slice(&0x200u32.to_le_bytes());
+        let stray = tlv(CONFIG_NAME, b"{\"fake\":true}");
+        base[0x300..0x300 + stray.len() - 2].copy_from_slice(&stray[..stray.len() - 2]);
+        base
+    }
+
+    fn file(name: &str, content: &[u8]) -> PackFile {
+        PackFile {
+            name: name.to_string(),
+            size: content.len() as u64,
+            data: Box::new(Cursor::new(content.to_vec())),
+            checksum: None,
+        }
+    }
+
+    /// Content that is itself a (truncated) TLV, overrunning the real next entry.
+    fn evil_content() -> Vec<u8> {
+        let mut content = b"padding".to_vec();
+        content.extend_from_slice(&tlv_header("evil.bin", 64));
+        content
+    }
+
+    async fn pack(files: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let base = base_exe(0x1000);
+        let content = PackContent {
+            config: b"{\"title\":\"test\"}".to_vec(),
+            image: None,
+            metadata: Some(b"{}".to_vec()),
+            files: files.iter().map(|(n, c)| file(n, c)).collect(),
+        };
+        let mut writer = PackWriter::new(Vec::new());
+        writer
+            .write(base.as_slice(), base.len() as u64, content)
+            .await
+            .unwrap();
+        writer.into_inner()
+    }
+
+    async fn read(data: &[u8]) -> PackReader<Cursor<&[u8]>> {
+        PackReader::new(Cursor::new(data)).await.unwrap()
+    }
+
+    async fn assert_files(data: &[u8], files: &[(&str, Vec<u8>)]) {
+        let mut reader = read(data).await;
+        assert_eq!(reader.entries()[0].name, CONFIG_NAME);
+        assert_eq!(
+            reader.config().await.unwrap().unwrap(),
+            b"{\"title\":\"test\"}"
+        );
+        for (name, content) in files {
+            let entry = reader.find(name).unwrap().clone();
+            assert_eq!(&reader.read_entry(&entry).await.unwrap(), content);
+        }
+    }
+
+    async fn scan(data: &[u8]) -> Vec<Embedded> {
+        read(data).await.scan_entries().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_header_ignores_marker_in_base() {
+        let files = [("a.bin", b"hello".to_vec())];
+        let data = pack(&files).await;
+        let reader = read(&data).await;
+        assert_eq!(reader.header().unwrap().base_end, 0x1000);
+        assert_eq!(reader.entries().len(), 4);
+        assert_files(&data, &files).await;
+        // the scan picks up the stray marker in the base exe
+        assert_eq!(scan(&data).await[0].raw_offset, 0x300);
+    }
+
+    #[tokio::test]
+    async fn test_header_ignores_marker_in_payload() {
+        let files = [("a.bin", evil_content()), ("b.bin", b"world".to_vec())];
+        let data = pack(&files).await;
+        let reader = read(&data).await;
+        assert_eq!(reader.entries().len(), 5);
+        assert!(reader.find("evil.bin").is_none());
+        assert_files(&data, &files).await;
+    }
+
+    #[tokio::test]
+    async fn test_appended_entries_after_index() {
+        let mut files = vec![("a.bin", evil_content())];
+        let mut data = pack(&files).await;
+        data.extend(tlv("runtime.exe", b"runtime"));
+        files.push(("runtime.exe", b"runtime".to_vec()));
+        let reader = read(&data).await;
+        assert_eq!(reader.entries().last().unwrap().name, "runtime.exe");
+        assert_files(&data, &files).await;
+    }
+
+    #[tokio::test]
+    async fn test_cleared_header_walks_from_overlay() {
+        let mut data = pack(&[]).await;
+        data.extend(tlv("runtime.exe", &evil_content()));
+        let reader = read(&data).await;
+        assert!(reader.header().is_none());
+        assert_eq!(reader.base_end(), 0x1000);
+        let names: Vec<_> = reader.entries().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, [CONFIG_NAME, META_NAME, "runtime.exe"]);
+    }
+
+    #[tokio::test]
+    async fn test_broken_header_falls_back_to_scan() {
+        let mut data = pack(&[("a.bin", b"hello".to_vec())]).await;
+        // point base_end somewhere inside the base exe
+        data[0x4e + 18..0x4e + 22].copy_from_slice(&0x800u32.to_be_bytes());
+        let reader = read(&data).await;
+        assert!(reader.header().is_none());
+        assert_eq!(reader.entries(), scan(&data).await.as_slice());
+    }
+
+    #[tokio::test]
+    async fn test_index_mismatch_falls_back_to_scan() {
+        let mut data = pack(&[("a.bin", b"hello".to_vec())]).await;
+        let len = data.len();
+        // rename the last file so it no longer matches the index
+        data[len - 5 - 4 - 5] = b'x';
+        let mut reader = read(&data).await;
+        let header = IndexHeader::parse(&data).unwrap();
+        assert!(reader.parse_with_header(&header).await.unwrap().is_none());
+        assert!(reader.header().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_marker_across_scan_chunks() {
+        let mut data = vec![0u8; SCAN_CHUNK as usize - 2];
+        data.extend(tlv("a.bin", b"hello"));
+        let entries = scan(&data).await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].raw_offset, SCAN_CHUNK as usize - 2);
+    }
+
+    #[tokio::test]
+    async fn test_truncated_tlv() {
+        let entry = tlv("a.bin", b"hello");
+        assert!(read(&entry).await.read_tlv(0).await.unwrap().is_some());
+        let truncated = &entry[..entry.len() - 1];
+        assert!(read(truncated).await.read_tlv(0).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,1085 @@
+//! Pure Rust PE resource editing, enough to brand the installer base on any
+//! platform: VERSIONINFO strings and the main icon group.
+//!
+//! The whole resource tree is parsed into a [`ResourceTable`], edited, and
+//! written back as a fresh `.rsrc`. The section is rewritten in place when it
+//! is the last one or the new tree fits, otherwise a new section is appended
+//! and the resource directory pointed at it.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+
+pub const RT_ICON: u16 = 3;
+pub const RT_GROUP_ICON: u16 = 14;
+pub const RT_VERSION: u16 = 16;
+
+/// en-US, used for resources created from scratch.
+const DEFAULT_LANG: u16 = 0x409;
+const RESOURCE_DIRECTORY: usize = 2;
+const SECURITY_DIRECTORY: usize = 4;
+const SECTION_HEADER_LEN: usize = 40;
+/// IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ
+const RESOURCE_SECTION_FLAGS: u32 = 0x4000_0040;
+
+/// Type, name or language of a resource. Named entries sort before IDs, which
+/// is the order the directory requires.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ResourceName {
+    Name(String),
+    Id(u16),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resource {
+    pub kind: ResourceName,
+    pub name: ResourceName,
+    pub lang: u16,
+    pub code_page: u32,
+    pub data: Vec<u8>,
+}
+
+/// Every resource of an image, flattened from the type/name/language tree.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResourceTable {
+    pub resources: Vec<Resource>,
+}
+
+impl ResourceTable {
+    pub fn find(&self, kind: &ResourceName, name: &ResourceName) -> Option<&Resource> {
+        self.resources
+            .iter()
+            .find(|r| &r.kind == kind && &r.name == name)
+    }
+
+    fn sort(&mut self) {
+        self.resources
+            .sort_by(|a, b| (&a.kind, &a.name, a.lang).cmp(&(&b.kind, &b.name, b.lang)));
+    }
+
+    /// Set `key` in every string table of every VERSIONINFO resource.
+    pub fn set_version_string(&mut self, key: &str, value: &str) -> io::Result<()> {
+        let mut found = false;
+        for resource in self.resources.iter_mut() {
+            if resource.kind != ResourceName::Id(RT_VERSION) {
+                continue;
+            }
+            let mut root = VersionBlock::parse(&resource.data)?;
+            root.set_string(key, value);
+            resource.data = root.to_bytes();
+            found = true;
+        }
+        if !found {
+            return Err(invalid_data("No version info in the base exe"));
+        }
+        Ok(())
+    }
+
+    /// Replace the first icon group, the one Explorer shows, with the images of
+    /// an `.ico` file.
+    pub fn set_icon(&mut self, ico: &[u8]) -> io::Result<()> {
+        let images = parse_ico(ico)?;
+        self.sort();
+        let group = self
+            .resources
+            .iter()
+            .find(|r| r.kind == ResourceName::Id(RT_GROUP_ICON));
+        let (name, lang, old_ids) = match group {
+            Some(group) => (group.name.clone(), group.lang, group_icon_ids(&group.data)?),
+            None => (ResourceName::Id(1), DEFAULT_LANG, vec![]),
+        };
+        self.resources.retain(|r| {
+            let old_icon = r.kind == ResourceName::Id(RT_ICON)
+                && r.lang == lang
+                && matches!(r.name, ResourceName::Id(id) if old_ids.contains(&id));
+            let old_group =
+                r.kind == ResourceName::Id(RT_GROUP_ICON) && r.name == name && r.lang == lang;
+            !old_icon && !old_group
+        });
+        let mut used: BTreeSet<u16> = self
+            .resources
+            .iter()
+            .filter(|r| r.kind == ResourceName::Id(RT_ICON))
+            .filter_map(|r| match r.name {
+                ResourceName::Id(id) => Some(id),
+                _ => None,
+            })
+            .collect();
+        let mut group_data = vec![0, 0, 1, 0];
+        group_data.extend_from_slice(&(images.len() as u16).to_le_bytes());
+        let mut next_id = 1;
+        for (entry, image) in images {
+            while used.contains(&next_id) {
+                next_id += 1;
+            }
+            used.insert(next_id);
+            // GRPICONDIRENTRY is ICONDIRENTRY with the image offset replaced by an ID
+            group_data.extend_from_slice(&entry[..12]);
+            group_data.extend_from_slice(&next_id.to_le_bytes());
+            self.resources.push(Resource {
+                kind: ResourceName::Id(RT_ICON),
+                name: ResourceName::Id(next_id),
+                lang,
+                code_page: 0,
+                data: image.to_vec(),
+            });
+        }
+        self.resources.push(Resource {
+            kind: ResourceName::Id(RT_GROUP_ICON),
+            name,
+            lang,
+            code_page: 0,
+            data: group_data,
+        });
+        self.sort();
+        Ok(())
+    }
+}
+
+/// ICONDIRENTRY and the image it points at, for every image of an `.ico`.
+fn parse_ico(ico: &[u8]) -> io::Result<Vec<(&[u8], &[u8])>> {
+    if read16(ico, 0)? != 0 || read16(ico, 2)? != 1 {
+        return Err(invalid_data("Not an .ico file"));
+    }
+    let count = read16(ico, 4)? as usize;
+    if count == 0 {
+        return Err(invalid_data("Icon has no images"));
+    }
+    (0..count)
+        .map(|i| {
+            let entry = ico
+                .get(6 + i * 16..6 + (i + 1) * 16)
+                .ok_or_else(|| invalid_data("Truncated icon directory"))?;
+            let size = read32(entry, 8)? as usize;
+            let offset = read32(entry, 12)? as usize;
+            let image = ico
+                .get(offset..offset.saturating_add(size))
+                .ok_or_else(|| invalid_data("Truncated icon image"))?;
+            Ok((entry, image))
+        })
+        .collect()
+}
+
+fn group_icon_ids(group: &[u8]) -> io::Result<Vec<u16>> {
+    let count = read16(group, 4)? as usize;
+    (0..count).map(|i| read16(group, 6 + i * 14 + 12)).collect()
+}
+
+/// A node of VS_VERSIONINFO: the root, StringFileInfo, a string table or a
+/// single string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionBlock {
+    pub key: String,
+    /// 1 for text, 0 for binary
+    pub value_type: u16,
+    pub value: Vec<u8>,
+    pub children: Vec<VersionBlock>,
+}
+
+impl VersionBlock {
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        Ok(Self::parse_at(data, 0)?.0)
+    }
+
+    fn parse_at(data: &[u8], pos: usize) -> io::Result<(Self, usize)> {
+        let length = read16(data, pos)? as usize;
+        let value_length = read16(data, pos + 2)? as usize;
+        let value_type = read16(data, pos + 4)?;
+        let end = pos + length;
+        if length < 6 || end > data.len() {
+            return Err(invalid_data("Malformed version info"));
+        }
+        let (key, mut p) = read_utf16z(&data[..end], pos + 6)?;
+        p = align4(p).min(end);
+        // text lengths count characters, but some compilers store bytes
+        let value_len = if value_type == 1 {
+            value_length * 2
+        } else {
+            value_length
+        };
+        let value_end = (p + value_len).min(end);
+        let value = data[p..value_end].to_vec();
+        p = align4(value_end);
+        let mut children = vec![];
+        while p < end {
+            let (child, child_end) = Self::parse_at(data, p)?;
+            children.push(child);
+            p = align4(child_end);
+        }
+        Ok((
+            VersionBlock {
+                key,
+                value_type,
+                value,
+                children,
+            },
+            end,
+        ))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.write(&mut out);
+        out
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.resize(align4(out.len()), 0);
+        let start = out.len();
+        out.extend_from_slice(&[0; 6]);
+        out.extend(utf16z(&self.key));
+        out.resize(align4(out.len()), 0);
+        out.extend_from_slice(&self.value);
+        for child in self.children.iter() {
+            child.write(out);
+        }
+        let value_length = if self.value_type == 1 {
+            self.value.len() / 2
+        } else {
+            self.value.len()
+        };
+        let length = (out.len() - start) as u16;
+        out[start..start + 2].copy_from_slice(&length.to_le_bytes());
+        out[start + 2..start + 4].copy_from_slice(&(value_length as u16).to_le_bytes());
+        out[start + 4..start + 6].copy_from_slice(&self.value_type.to_le_bytes());
+    }
+
+    /// The value of `key` in the first string table.
+    pub fn get_string(&self, key: &str) -> Option<String> {
+        let table = self
+            .children
+            .iter()
+            .find(|c| c.key == "StringFileInfo")?
+            .children
+            .first()?;
+        let string = table.children.iter().find(|s| s.key == key)?;
+        let (value, _) = read_utf16z(&string.value, 0).ok()?;
+        Some(value)
+    }
+
+    fn set_string(&mut self, key: &str, value: &str) {
+        let string = VersionBlock {
+            key: key.to_string(),
+            value_type: 1,
+            value: utf16z(value),
+            children: vec![],
+        };
+        if !self.children.iter().any(|c| c.key == "StringFileInfo") {
+            self.children.insert(
+                0,
+                VersionBlock {
+                    key: "StringFileInfo".to_string(),
+                    value_type: 1,
+                    value: vec![],
+                    children: vec![VersionBlock {
+                        key: "040904B0".to_string(),
+                        value_type: 1,
+                        value: vec![],
+                        children: vec![],
+                    }],
+                },
+            );
+        }
+        for info in self.children.iter_mut() {
+            if info.key != "StringFileInfo" {
+                continue;
+            }
+            for table in info.children.iter_mut() {
+                match table.children.iter_mut().find(|s| s.key == key) {
+                    Some(existing) => *existing = string.clone(),
+                    None => table.children.push(string.clone()),
+                }
+            }
+        }
+    }
+}
+
+struct Section {
+    virtual_size: u32,
+    virtual_address: u32,
+    raw_size: u32,
+    raw_ptr: u32,
+}
+
+impl Section {
+    fn contains(&self, rva: u32) -> bool {
+        rva >= self.virtual_address && rva < self.virtual_end()
+    }
+
+    fn virtual_end(&self) -> u32 {
+        self.virtual_address + self.virtual_size.max(self.raw_size)
+    }
+}
+
+/// The parts of the PE headers that resource editing touches.
+struct PeLayout {
+    coff: usize,
+    optional: usize,
+    directories: usize,
+    directory_count: u32,
+    section_alignment: u32,
+    file_alignment: u32,
+    size_of_headers: u32,
+    sections_pos: usize,
+    sections: Vec<Section>,
+}
+
+impl PeLayout {
+    fn parse(pe: &[u8]) -> io::Result<Self> {
+        if pe.get(0..2) != Some(b"MZ") {
+            return Err(invalid_data("Not a PE file"));
+        }
+        let offset = read32(pe, 0x3c)? as usize;
+        if pe.get(offset..offset + 4) != Some(b"PE\0\0") {
+            return Err(invalid_data("Not a PE file"));
+        }
+        let coff = offset + 4;
+        let section_count = read16(pe, coff + 2)? as usize;
+        let optional_size = read16(pe, coff + 16)? as usize;
+        let optional = coff + 20;
+        let (count_pos, directories) = match read16(pe, optional)? {
+            0x10b => (optional + 92, optional + 96),
+            0x20b => (optional + 108, optional + 112),
+            _ => return Err(invalid_data("Unknown optional header")),
+        };
+        let sections_pos = optional + optional_size;
+        let sections = (0..section_count)
+            .map(|i| {
+                let pos = sections_pos + i * SECTION_HEADER_LEN;
+                Ok(Section {
+                    virtual_size: read32(pe, pos + 8)?,
+                    virtual_address: read32(pe, pos + 12)?,
+                    raw_size: read32(pe, pos + 16)?,
+                    raw_ptr: read32(pe, pos + 20)?,
+                })
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(PeLayout {
+            coff,
+            optional,
+            directories,
+            directory_count: read32(pe, count_pos)?,
+            section_alignment: read32(pe, optional + 32)?,
+            file_alignment: read32(pe, optional + 36)?,
+            size_of_headers: read32(pe, optional + 60)?,
+            sections_pos,
+            sections,
+        })
+    }
+
+    fn directory(&self, pe: &[u8], index: usize) -> io::Result<(u32, u32)> {
+        if index as u32 >= self.directory_count {
+            return Ok((0, 0));
+        }
+        let pos = self.directories + index * 8;
+        Ok((read32(pe, pos)?, read32(pe, pos + 4)?))
+    }
+
+    fn rva_to_offset(&self, rva: u32) -> io::Result<usize> {
+        let section = self
+            .sections
+            .iter()
+            .find(|s| s.contains(rva))
+            .ok_or_else(|| invalid_data("Resource outside of any section"))?;
+        Ok((rva - section.virtual_address + section.raw_ptr) as usize)
+    }
+
+    /// End of the section data, where the overlay starts.
+    fn raw_end(&self) -> u32 {
+        self.sections
+            .iter()
+            .filter(|s| s.raw_size != 0)
+            .map(|s| s.raw_ptr + s.raw_size)
+            .max()
+            .unwrap_or(self.size_of_headers)
+    }
+
+    fn virtual_end(&self) -> u32 {
+        self.sections
+            .iter()
+            .map(Section::virtual_end)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Parse the resource tree of a PE image.
+pub fn read_resources(pe: &[u8]) -> io::Result<ResourceTable> {
+    let layout = PeLayout::parse(pe)?;
+    let (rva, _) = layout.directory(pe, RESOURCE_DIRECTORY)?;
+    let mut table = ResourceTable::default();
+    if rva == 0 {
+        return Ok(table);
+    }
+    let start = layout.rva_to_offset(rva)?;
+    let rsrc = pe
+        .get(start..)
+        .ok_or_else(|| invalid_data("Resource directory outside of the file"))?;
+    for (kind, kind_dir) in read_directory(rsrc, 0, true)? {
+        for (name, name_dir) in read_directory(rsrc, kind_dir, true)? {
+            for (lang, entry) in read_directory(rsrc, name_dir, false)? {
+                let ResourceName::Id(lang) = lang else {
+                    return Err(invalid_data("Named resource language"));
+                };
+                let data_rva = read32(rsrc, entry as usize)?;
+                let size = read32(rsrc, entry as usize + 4)? as usize;
+                let offset = layout.rva_to_offset(data_rva)?;
+                let data = pe
+                    .get(offset..offset + size)
+                    .ok_or_else(|| invalid_data("Resource data outside of the file"))?;
+                table.resources.push(Resource {
+                    kind: kind.clone(),
+                    name: name.clone(),
+                    lang,
+                    code_page: read32(rsrc, entry as usize + 8)?,
+                    data: data.to_vec(),
+                });
+            }
+        }
+    }
+    table.sort();
+    Ok(table)
+}
+
+/// Entries of one directory level, with the offset of the subdirectory or,
+/// on the last level, of the data entry.
+fn read_directory(
+    rsrc: &[u8],
+    offset: u32,
+    subdirectory: bool,
+) -> io::Result<Vec<(ResourceName, u32)>> {
+    let offset = offset as usize;
+    let count = read16(rsrc, offset + 12)? as usize + read16(rsrc, offset + 14)? as usize;
+    (0..count)
+        .map(|i| {
+            let pos = offset + 16 + i * 8;
+            let name = read32(rsrc, pos)?;
+            let target = read32(rsrc, pos + 4)?;
+            if (target & 0x8000_0000 != 0) != subdirectory {
+                return Err(invalid_data("Malformed resource directory"));
+            }
+            let name = if name & 0x8000_0000 != 0 {
+                let pos = (name & 0x7fff_ffff) as usize;
+                let len = read16(rsrc, pos)? as usize;
+                let units = rsrc
+                    .get(pos + 2..pos + 2 + len * 2)
+                    .ok_or_else(|| invalid_data("Truncated resource name"))?;
+                ResourceName::Name(String::from_utf16_lossy(&to_u16(units)))
+            } else {
+                ResourceName::Id(name as u16)
+            };
+            Ok((name, target & 0x7fff_ffff))
+        })
+        .collect()
+}
+
+type Tree<'a> = BTreeMap<&'a ResourceName, BTreeMap<&'a ResourceName, BTreeMap<u16, &'a Resource>>>;
+
+/// Serialize the tree for a section at `va`: directories, data entries, names,
+/// then the data itself.
+fn build_resources(table: &ResourceTable, va: u32) -> Vec<u8> {
+    let mut tree: Tree = BTreeMap::new();
+    for resource in table.resources.iter() {
+        tree.entry(&resource.kind)
+            .or_default()
+            .entry(&resource.name)
+            .or_default()
+            .insert(resource.lang, resource);
+    }
+    let dir_len = |entries: usize| 16 + entries * 8;
+    let mut dirs_len = dir_len(tree.len());
+    for names in tree.values() {
+        dirs_len += dir_len(names.len());
+        dirs_len += names.values().map(|l| dir_len(l.len())).sum::<usize>();
+    }
+    let resource_count: usize = tree
+        .values()
+        .flat_map(|names| names.values())
+        .map(|langs| langs.len())
+        .sum();
+    let strings_pos = dirs_len + resource_count * 16;
+    let mut strings = vec![];
+    let mut string_offsets = HashMap::new();
+    for (kind, names) in tree.iter() {
+        for name in std::iter::once(*kind).chain(names.keys().copied()) {
+            if let ResourceName::Name(name) = name {
+                string_offsets.entry(name.clone()).or_insert_with(|| {
+                    let pos = strings_pos + strings.len();
+                    let units: Vec<u16> = name.encode_utf16().collect();
+                    strings.extend_from_slice(&(units.len() as u16).to_le_bytes());
+                    strings.extend(units.iter().flat_map(|u| u.to_le_bytes()));
+                    pos
+                });
+            }
+        }
+    }
+    let mut out = vec![0u8; align8(strings_pos + strings.len())];
+    out[strings_pos..strings_pos + strings.len()].copy_from_slice(&strings);
+
+    let name_field = |name: &ResourceName| match name {
+        ResourceName::Name(name) => 0x8000_0000 | string_offsets[name] as u32,
+        ResourceName::Id(id) => *id as u32,
+    };
+    let mut next_dir = dir_len(tree.len());
+    let mut next_entry = dirs_len;
+    let mut root = vec![];
+    for (kind, names) in tree.iter() {
+        let kind_dir = next_dir;
+        next_dir += dir_len(names.len());
+        root.push((name_field(kind), 0x8000_0000 | kind_dir as u32));
+        let mut kind_entries = vec![];
+        for (name, langs) in names.iter() {
+            let name_dir = next_dir;
+            next_dir += dir_len(langs.len());
+            kind_entries.push((name_field(name), 0x8000_0000 | name_dir as u32));
+            let mut lang_entries = vec![];
+            for (lang, resource) in langs.iter() {
+                let entry = next_entry;
+                next_entry += 16;
+                lang_entries.push((*lang as u32, entry as u32));
+                out.resize(align8(out.len()), 0);
+                let data_rva = va + out.len() as u32;
+                out.extend_from_slice(&resource.data);
+                out[entry..entry + 4].copy_from_slice(&data_rva.to_le_bytes());
+                out[entry + 4..entry + 8]
+                    .copy_from_slice(&(resource.data.len() as u32).to_le_bytes());
+                out[entry + 8..entry + 12].copy_from_slice(&resource.code_page.to_le_bytes());
+            }
+            write_directory(&mut out, name_dir, &lang_entries);
+        }
+        write_directory(&mut out, kind_dir, &kind_entries);
+    }
+    write_directory(&mut out, 0, &root);
+    out
+}
+
+fn write_directory(out: &mut [u8], pos: usize, entries: &[(u32, u32)]) {
+    let named = entries.iter().filter(|(n, _)| n & 0x8000_0000 != 0).count();
+    out[pos + 12..pos + 14].copy_from_slice(&(named as u16).to_le_bytes());
+    out[pos + 14..pos + 16].copy_from_slice(&((entries.len() - named) as u16).to_le_bytes());
+    for (i, (name, target)) in entries.iter().enumerate() {
+        let entry = pos + 16 + i * 8;
+        out[entry..entry + 4].copy_from_slice(&name.to_le_bytes());
+        out[entry + 4..entry + 8].copy_from_slice(&target.to_le_bytes());
+    }
+}
+
+/// Write `table` as the resources of `pe`. A certificate table is dropped,
+/// since any edit invalidates the signature.
+pub fn write_resources(pe: &[u8], table: &ResourceTable) -> io::Result<Vec<u8>> {
+    let layout = PeLayout::parse(pe)?;
+    if layout.directory_count as usize <= SECURITY_DIRECTORY {
+        return Err(invalid_data("Too few data directories"));
+    }
+    let (rva, _) = layout.directory(pe, RESOURCE_DIRECTORY)?;
+    let (cert_offset, cert_size) = layout.directory(pe, SECURITY_DIRECTORY)?;
+    let raw_end = (layout.raw_end() as usize).min(pe.len());
+    let overlay_end = if cert_size != 0 && cert_offset as usize >= raw_end {
+        (cert_offset as usize).min(pe.len())
+    } else {
+        pe.len()
+    };
+    let overlay = &pe[raw_end..overlay_end];
+
+    let current = match rva {
+        0 => None,
+        rva => layout.sections.iter().position(|s| s.contains(rva)),
+    };
+    let mut placed = None;
+    if let Some(index) = current {
+        let section = &layout.sections[index];
+        let data = build_resources(table, section.virtual_address);
+        let start = section.raw_ptr as usize;
+        if start + section.raw_size as usize >= raw_end
+            && section.virtual_end() >= layout.virtual_end()
+        {
+            // the last section can simply grow or shrink
+            let raw_size = align(data.len(), layout.file_alignment);
+            let mut out = pe[..start].to_vec();
+            out.extend_from_slice(&data);
+            out.resize(start + raw_size, 0);
+            placed = Some((out, index, section.virtual_address, data.len(), raw_size));
+        } else if data.len() <= section.raw_size as usize
+            && section.virtual_address as usize + data.len() <= next_va(&layout, index)
+        {
+            let mut out = pe[..raw_end].to_vec();
+            out[start..start + section.raw_size as usize].fill(0);
+            out[start..start + data.len()].copy_from_slice(&data);
+            let raw_size = section.raw_size as usize;
+            placed = Some((out, index, section.virtual_address, data.len(), raw_size));
+        }
+    }
+    let (mut out, index, va, len, raw_size) = match placed {
+        Some(placed) => placed,
+        None => append_section(pe, &layout, raw_end, table)?,
+    };
+
+    let header = layout.sections_pos + index * SECTION_HEADER_LEN;
+    put32(&mut out, header + 8, len as u32);
+    put32(&mut out, header + 12, va);
+    put32(&mut out, header + 16, raw_size as u32);
+    out.extend_from_slice(overlay);
+
+    let mut virtual_end = va as usize + len;
+    for (i, section) in layout.sections.iter().enumerate() {
+        if i != index {
+            virtual_end = virtual_end.max(section.virtual_end() as usize);
+        }
+    }
+    let size_of_image = align(virtual_end, layout.section_alignment);
+    put32(&mut out, layout.optional + 56, size_of_image as u32);
+    let resource = layout.directories + RESOURCE_DIRECTORY * 8;
+    put32(&mut out, resource, va);
+    put32(&mut out, resource + 4, len as u32);
+    let security = layout.directories + SECURITY_DIRECTORY * 8;
+    out[security..security + 8].fill(0);
+    let checksum = pe_checksum(&out, layout.optional + 64);
+    put32(&mut out, layout.optional + 64, checksum);
+    Ok(out)
+}
+
+/// Put the resources into a new last section, leaving the old ones as dead
+/// data. Returns the image, section index, address, size and raw size.
+fn append_section(
+    pe: &[u8],
+    layout: &PeLayout,
+    raw_end: usize,
+    table: &ResourceTable,
+) -> io::Result<(Vec<u8>, usize, u32, usize, usize)> {
+    let index = layout.sections.len();
+    let header = layout.sections_pos + index * SECTION_HEADER_LEN;
+    let first_raw = layout
+        .sections
+        .iter()
+        .filter(|s| s.raw_size != 0)
+        .map(|s| s.raw_ptr)
+        .min()
+        .unwrap_or(layout.size_of_headers);
+    if header + SECTION_HEADER_LEN > layout.size_of_headers.min(first_raw) as usize {
+        return Err(invalid_data("No room for another section header"));
+    }
+    let va = align(layout.virtual_end() as usize, layout.section_alignment) as u32;
+    let data = build_resources(table, va);
+    let raw_ptr = align(raw_end, layout.file_alignment);
+    let raw_size = align(data.len(), layout.file_alignment);
+    let mut out = pe[..raw_end].to_vec();
+    out.resize(raw_ptr, 0);
+    out.extend_from_slice(&data);
+    out.resize(raw_ptr + raw_size, 0);
+    out[header..header + SECTION_HEADER_LEN].fill(0);
+    out[header..header + 8].copy_from_slice(b".rsrc\0\0\0");
+    put32(&mut out, header + 20, raw_ptr as u32);
+    put32(&mut out, header + 36, RESOURCE_SECTION_FLAGS);
+    let count = (index + 1) as u16;
+    out[layout.coff + 2..layout.coff + 4].copy_from_slice(&count.to_le_bytes());
+    Ok((out, index, va, data.len(), raw_size))
+}
+
+/// Address of the section after `index`, the most it may grow in memory.
+fn next_va(layout: &PeLayout, index: usize) -> usize {
+    let va = layout.sections[index].virtual_address;
+    layout
+        .sections
+        .iter()
+        .map(|s| s.virtual_address)
+        .filter(|&next| next > va)
+        .min()
+        .map_or(usize::MAX, |next| next as usize)
+}
+
+/// The optional header checksum, as computed by `CheckSumMappedFile`.
+fn pe_checksum(data: &[u8], checksum_pos: usize) -> u32 {
+    let mut sum: u64 = 0;
+    for (i, word) in data.chunks(2).enumerate() {
+        if i * 2 == checksum_pos || i * 2 == checksum_pos + 2 {
+            continue;
+        }
+        let word = match word {
+            [lo, hi] => u16::from_le_bytes([*lo, *hi]),
+            [lo] => *lo as u16,
+            _ => 0,
+        };
+        sum += word as u64;
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum = (sum & 0xffff) + (sum >> 16);
+    (sum as u32).wrapping_add(data.len() as u32)
+}
+
+fn read16(data: &[u8], pos: usize) -> io::Result<u16> {
+    data.get(pos..pos + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| invalid_data("Truncated PE data"))
+}
+
+fn read32(data: &[u8], pos: usize) -> io::Result<u32> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| invalid_data("Truncated PE data"))
+}
+
+fn put32(data: &mut [u8], pos: usize, value: u32) {
+    data[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn to_u16(bytes: &[u8]) -> Vec<u16> {
+    bytes
+        .chunks_exact(2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .collect()
+}
+
+/// Read a NUL-terminated UTF-16 string, returning it and the position after the NUL.
+fn read_utf16z(data: &[u8], pos: usize) -> io::Result<(String, usize)> {
+    let mut end = pos;
+    while read16(data, end)? != 0 {
+        end += 2;
+    }
+    Ok((String::from_utf16_lossy(&to_u16(&data[pos..end])), end + 2))
+}
+
+fn utf16z(value: &str) -> Vec<u8> {
+    value
+        .encode_utf16()
+        .chain(std::iter::once(0))
+        .flat_map(|u| u.to_le_bytes())
+        .collect()
+}
+
+fn align4(value: usize) -> usize {
+    value.div_ceil(4) * 4
+}
+
+fn align8(value: usize) -> usize {
+    value.div_ceil(8) * 8
+}
+
+fn align(value: usize, alignment: u32) -> usize {
+    let alignment = (alignment as usize).max(1);
+    value.div_ceil(alignment) * alignment
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPTIONAL: usize = 0x98;
+    const SECTIONS: usize = OPTIONAL + 0xf0;
+
+    fn string(key: &str, value: &str) -> VersionBlock {
+        VersionBlock {
+            key: key.to_string(),
+            value_type: 1,
+            value: utf16z(value),
+            children: vec![],
+        }
+    }
+
+    fn version_info() -> Vec<u8> {
+        let mut fixed = vec![0u8; 52];
+        fixed[..4].copy_from_slice(&0xfeef04bdu32.to_le_bytes());
+        VersionBlock {
+            key: "VS_VERSION_INFO".to_string(),
+            value_type: 0,
+            value: fixed,
+            children: vec![
+                VersionBlock {
+                    key: "StringFileInfo".to_string(),
+                    value_type: 1,
+                    value: vec![],
+                    children: vec![VersionBlock {
+                        key: "040904B0".to_string(),
+                        value_type: 1,
+                        value: vec![],
+                        children: vec![
+                            string("CompanyName", "YuehaiTeam"),
+                            string("FileDescription", "Kachina Installer"),
+                            string("ProductName", "Kachina"),
+                        ],
+                    }],
+                },
+                VersionBlock {
+                    key: "VarFileInfo".to_string(),
+                    value_type: 1,
+                    value: vec![],
+                    children: vec![VersionBlock {
+                        key: "Translation".to_string(),
+                        value_type: 0,
+                        value: vec![0x09, 0x04, 0xb0, 0x04],
+                        children: vec![],
+                    }],
+                },
+            ],
+        }
+        .to_bytes()
+    }
+
+    fn resource(kind: ResourceName, name: ResourceName, data: Vec<u8>) -> Resource {
+        Resource {
+            kind,
+            name,
+            lang: DEFAULT_LANG,
+            code_page: 0,
+            data,
+        }
+    }
+
+    fn table() -> ResourceTable {
+        let mut group = vec![0, 0, 1, 0, 1, 0];
+        group.extend_from_slice(&[32, 32, 0, 0, 1, 0, 32, 0]);
+        group.extend_from_slice(&64u32.to_le_bytes());
+        group.extend_from_slice(&1u16.to_le_bytes());
+        let mut table = ResourceTable {
+            resources: vec![
+                resource(
+                    ResourceName::Id(RT_VERSION),
+                    ResourceName::Id(1),
+                    version_info(),
+                ),
+                resource(ResourceName::Id(RT_ICON), ResourceName::Id(1), vec![1; 64]),
+                resource(ResourceName::Id(RT_GROUP_ICON), ResourceName::Id(1), group),
+                resource(
+                    ResourceName::Id(24),
+                    ResourceName::Id(1),
+                    b"<assembly/>".to_vec(),
+                ),
+                resource(
+                    ResourceName::Name("KACHINA".to_string()),
+                    ResourceName::Name("CONFIG".to_string()),
+                    b"{}".to_vec(),
+                ),
+            ],
+        };
+        table.sort();
+        table
+    }
+
+    /// PE32+ with .text, .rsrc and .reloc (or .rsrc last), an overlay and a
+    /// certificate table.
+    fn pe(table: &ResourceTable, rsrc_last: bool) -> Vec<u8> {
+        let mut names: Vec<&[u8]> = vec![b".text", b".rsrc", b".reloc"];
+        if rsrc_last {
+            names.swap(1, 2);
+        }
+        let mut data = vec![0u8; 0x400];
+        data[..2].copy_from_slice(b"MZ");
+        put32(&mut data, 0x3c, 0x80);
+        data[0x80..0x84].copy_from_slice(b"PE\0\0");
+        data[0x86..0x88].copy_from_slice(&3u16.to_le_bytes());
+        data[0x94..0x96].copy_from_slice(&0xf0u16.to_le_bytes());
+        data[OPTIONAL..OPTIONAL + 2].copy_from_slice(&0x20bu16.to_le_bytes());
+        put32(&mut data, OPTIONAL + 32, 0x1000);
+        put32(&mut data, OPTIONAL + 36, 0x200);
+        put32(&mut data, OPTIONAL + 60, 0x400);
+        put32(&mut data, OPTIONAL + 108, 16);
+        for (i, name) in names.into_iter().enumerate() {
+            let va = 0x1000 * (i as u32 + 1);
+            let content = match name {
+                b".rsrc" => {
+                    put32(&mut data, OPTIONAL + 112 + RESOURCE_DIRECTORY * 8, va);
+                    build_resources(table, va)
+                }
+                b".text" => vec![0xcc; 0x180],
+                _ => vec![0x5a; 0x20],
+            };
+            let header = SECTIONS + i * SECTION_HEADER_LEN;
+            let start = data.len();
+            data[header..header + name.len()].copy_from_slice(name);
+            put32(&mut data, header + 8, content.len() as u32);
+            put32(&mut data, header + 12, va);
+            put32(&mut data, header + 16, align(content.len(), 0x200) as u32);
+            put32(&mut data, header + 20, start as u32);
+            if name == b".rsrc" {
+                put32(
+                    &mut data,
+                    OPTIONAL + 112 + RESOURCE_DIRECTORY * 8 + 4,
+                    content.len() as u32,
+                );
+            }
+            data.extend_from_slice(&content);
+            data.resize(start + align(content.len(), 0x200), 0);
+        }
+        put32(&mut data, OPTIONAL + 56, 0x4000);
+        data.extend_from_slice(b"overlay!");
+        let cert = data.len() as u32;
+        put32(&mut data, OPTIONAL + 112 + SECURITY_DIRECTORY * 8, cert);
+        put32(&mut data, OPTIONAL + 112 + SECURITY_DIRECTORY * 8 + 4, 16);
+        data.extend_from_slice(&[0xee; 16]);
+        data
+    }
+
+    fn section<'a>(pe: &'a [u8], name: &[u8]) -> &'a [u8] {
+        let layout = PeLayout::parse(pe).unwrap();
+        let index = (0..layout.sections.len())
+            .find(|i| {
+                let header = layout.sections_pos + i * SECTION_HEADER_LEN;
+                pe[header..header + 8].starts_with(name)
+            })
+            .unwrap();
+        let section = &layout.sections[index];
+        &pe[section.raw_ptr as usize..(section.raw_ptr + section.raw_size) as usize]
+    }
+
+    fn check_image(before: &[u8], after: &[u8]) {
+        assert_eq!(section(before, b".text"), section(after, b".text"));
+        assert_eq!(section(before, b".reloc"), section(after, b".reloc"));
+        assert!(after.ends_with(b"overlay!"));
+        let layout = PeLayout::parse(after).unwrap();
+        assert_eq!(layout.directory(after, SECURITY_DIRECTORY).unwrap(), (0, 0));
+        let size_of_image = read32(after, OPTIONAL + 56).unwrap();
+        assert_eq!(
+            size_of_image,
+            align(layout.virtual_end() as usize, 0x1000) as u32
+        );
+        let checksum = read32(after, OPTIONAL + 64).unwrap();
+        assert_eq!(checksum, pe_checksum(after, OPTIONAL + 64));
+    }
+
+    fn without(table: &ResourceTable, kinds: &[u16]) -> Vec<Resource> {
+        table
+            .resources
+            .iter()
+            .filter(|r| !kinds.iter().any(|k| r.kind == ResourceName::Id(*k)))
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn test_resource_roundtrip() {
+        let table = table();
+        let data = pe(&table, false);
+        assert_eq!(read_resources(&data).unwrap(), table);
+        let version = VersionBlock::parse(&version_info()).unwrap();
+        assert_eq!(version.to_bytes(), version_info());
+
+        let rewritten = write_resources(&data, &table).unwrap();
+        assert_eq!(read_resources(&rewritten).unwrap(), table);
+        assert_eq!(PeLayout::parse(&rewritten).unwrap().sections.len(), 3);
+        check_image(&data, &rewritten);
+    }
+
+    #[test]
+    fn test_set_version_string() {
+        let before = table();
+        let data = pe(&before, false);
+        let mut table = read_resources(&data).unwrap();
+        table
+            .set_version_string("FileDescription", "测试安装器")
+            .unwrap();
+        table.set_version_string("ProductVersion", "1.2.3").unwrap();
+        let edited = write_resources(&data, &table).unwrap();
+        check_image(&data, &edited);
+
+        let after = read_resources(&edited).unwrap();
+        assert_eq!(after, table);
+        assert_eq!(
+            without(&after, &[RT_VERSION]),
+            without(&before, &[RT_VERSION])
+        );
+        let version = &after
+            .find(&ResourceName::Id(RT_VERSION), &ResourceName::Id(1))
+            .unwrap()
+            .data;
+        let version = VersionBlock::parse(version).unwrap();
+        let get = |key| version.get_string(key);
+        assert_eq!(get("FileDescription").as_deref(), Some("测试安装器"));
+        assert_eq!(get("ProductVersion").as_deref(), Some("1.2.3"));
+        assert_eq!(get("CompanyName").as_deref(), Some("YuehaiTeam"));
+        assert_eq!(version.children[1].key, "VarFileInfo");
+
+        let mut empty = ResourceTable::default();
+        assert!(empty.set_version_string("ProductName", "x").is_err());
+    }
+
+    fn ico(sizes: &[usize]) -> Vec<u8> {
+        let mut ico = vec![0, 0, 1, 0];
+        ico.extend_from_slice(&(sizes.len() as u16).to_le_bytes());
+        let mut offset = 6 + sizes.len() * 16;
+        for (i, size) in sizes.iter().enumerate() {
+            ico.extend_from_slice(&[16 << i, 16 << i, 0, 0, 1, 0, 32, 0]);
+            ico.extend_from_slice(&(*size as u32).to_le_bytes());
+            ico.extend_from_slice(&(offset as u32).to_le_bytes());
+            offset += size;
+        }
+        for (i, size) in sizes.iter().enumerate() {
+            ico.extend(std::iter::repeat_n(i as u8 + 0x10, *size));
+        }
+        ico
+    }
+
+    fn check_icon(table: &ResourceTable, sizes: &[usize]) {
+        let group = table
+            .find(&ResourceName::Id(RT_GROUP_ICON), &ResourceName::Id(1))
+            .unwrap();
+        let ids = group_icon_ids(&group.data).unwrap();
+        assert_eq!(ids.len(), sizes.len());
+        let icons: Vec<_> = table
+            .resources
+            .iter()
+            .filter(|r| r.kind == ResourceName::Id(RT_ICON))
+            .collect();
+        assert_eq!(icons.len(), sizes.len());
+        for (i, id) in ids.iter().enumerate() {
+            let icon = table
+                .find(&ResourceName::Id(RT_ICON), &ResourceName::Id(*id))
+                .unwrap();
+            assert_eq!(icon.data, vec![i as u8 + 0x10; sizes[i]]);
+            assert_eq!(
+                read32(&group.data, 6 + i * 14 + 8).unwrap(),
+                sizes[i] as u32
+            );
+        }
+    }
+
+    #[test]
+    fn test_set_icon() {
+        let before = table();
+        let data = pe(&before, false);
+        let mut table = read_resources(&data).unwrap();
+        // too large for the old section, which is followed by .reloc
+        let sizes = [0x300, 0x1800];
+        table.set_icon(&ico(&sizes)).unwrap();
+        let edited = write_resources(&data, &table).unwrap();
+        check_image(&data, &edited);
+        let layout = PeLayout::parse(&edited).unwrap();
+        assert_eq!(layout.sections.len(), 4);
+        assert_eq!(
+            layout.directory(&edited, RESOURCE_DIRECTORY).unwrap().0,
+            0x4000
+        );
+
+        let after = read_resources(&edited).unwrap();
+        assert_eq!(after, table);
+        check_icon(&after, &sizes);
+        let kinds = [RT_ICON, RT_GROUP_ICON];
+        assert_eq!(without(&after, &kinds), without(&before, &kinds));
+        assert!(table.set_icon(b"not an icon").is_err());
+    }
+
+    #[test]
+    fn test_set_icon_last_section() {
+        let before = table();
+        let data = pe(&before, true);
+        let mut table = read_resources(&data).unwrap();
+        let sizes = [0x1800];
+        table.set_icon(&ico(&sizes)).unwrap();
+        table.set_version_string("ProductName", "Kachina").unwrap();
+        let edited = write_resources(&data, &table).unwrap();
+        check_image(&data, &edited);
+        let layout = PeLayout::parse(&edited).unwrap();
+        assert_eq!(layout.sections.len(), 3);
+        assert_eq!(
+            layout.directory(&edited, RESOURCE_DIRECTORY).unwrap().0,
+            0x3000
+        );
+
+        let after = read_resources(&edited).unwrap();
+        assert_eq!(after, table);
+        check_icon(&after, &sizes);
+        let kinds = [RT_ICON, RT_GROUP_ICON];
+        assert_eq!(without(&after, &kinds), without(&before, &kinds));
+    }
+}
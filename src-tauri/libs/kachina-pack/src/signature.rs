@@ -0,0 +1,196 @@
+use std::fmt;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+use crate::format::get_header_size;
+
+/// Error code for every signature failure, so the frontend can tell a tampered
+/// or unsigned pack apart from a network error.
+pub const SIGNATURE_ERR: &str = "PACK_SIGNATURE_ERR";
+
+pub const SIGNATURE_NAME: &str = "\0SIGN";
+pub const SIGNATURE_VERSION: u8 = 1;
+/// u8: version, 32 bytes: public key, 64 bytes: signature
+pub const SIGNATURE_LEN: usize = 1 + 32 + 64;
+
+/// Separates our signatures from anything else signed with the same key.
+const SIGNATURE_DOMAIN: &[u8] = b"KachinaInstaller signature v1\0";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureError {
+    Missing,
+    Malformed,
+    UntrustedKey(String),
+    Invalid,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Missing => write!(f, "pack is not signed"),
+            SignatureError::Malformed => write!(f, "malformed signature"),
+            SignatureError::UntrustedKey(key) => write!(f, "signed by untrusted key {key}"),
+            SignatureError::Invalid => write!(f, "signature mismatch"),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+/// Size of the whole `\0SIGN` entry, reserved right after `\0META`.
+pub fn signature_entry_len() -> u64 {
+    (get_header_size(SIGNATURE_NAME, SIGNATURE_LEN as u64) + SIGNATURE_LEN) as u64
+}
+
+/// The signed bytes. Every section is prefixed with its presence and length,
+/// so content can't be moved from one section to another.
+fn signed_message(config: &[u8], metadata: Option<&[u8]>, index: Option<&[u8]>) -> Vec<u8> {
+    let mut message = SIGNATURE_DOMAIN.to_vec();
+    for section in [Some(config), metadata, index] {
+        match section {
+            Some(data) => {
+                message.push(1);
+                message.extend_from_slice(&(data.len() as u64).to_be_bytes());
+                message.extend_from_slice(data);
+            }
+            None => message.push(0),
+        }
+    }
+    message
+}
+
+/// Content of the `\0SIGN` entry covering config, metadata and index.
+pub fn sign(
+    key: &SigningKey,
+    config: &[u8],
+    metadata: Option<&[u8]>,
+    index: Option<&[u8]>,
+) -> Vec<u8> {
+    let signature = key.sign(&signed_message(config, metadata, index));
+    let mut content = Vec::with_capacity(SIGNATURE_LEN);
+    content.push(SIGNATURE_VERSION);
+    content.extend_from_slice(key.verifying_key().as_bytes());
+    content.extend_from_slice(&signature.to_bytes());
+    content
+}
+
+/// Check `signature` (the `\0SIGN` content) against the sections, accepting
+/// only keys in `trusted`.
+pub fn verify_signature(
+    signature: Option<&[u8]>,
+    trusted: &[VerifyingKey],
+    config: &[u8],
+    metadata: Option<&[u8]>,
+    index: Option<&[u8]>,
+) -> Result<(), SignatureError> {
+    let signature = signature.ok_or(SignatureError::Missing)?;
+    if signature.len() != SIGNATURE_LEN || signature[0] != SIGNATURE_VERSION {
+        return Err(SignatureError::Malformed);
+    }
+    let key_bytes: [u8; 32] = signature[1..33].try_into().unwrap();
+    let key = trusted
+        .iter()
+        .find(|k| k.as_bytes() == &key_bytes)
+        .ok_or_else(|| SignatureError::UntrustedKey(to_hex(&key_bytes)))?;
+    let signature =
+        Signature::from_slice(&signature[33..]).map_err(|_| SignatureError::Malformed)?;
+    key.verify(&signed_message(config, metadata, index), &signature)
+        .map_err(|_| SignatureError::Invalid)
+}
+
+/// Public keys are configured as 64 hex characters.
+pub fn parse_public_key(hex: &str) -> Option<VerifyingKey> {
+    VerifyingKey::from_bytes(&from_hex(hex)?).ok()
+}
+
+/// Secret keys are stored as the 64 hex character seed.
+pub fn parse_signing_key(hex: &str) -> Option<SigningKey> {
+    Some(SigningKey::from_bytes(&from_hex(hex)?))
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<[u8; 32]> {
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut out = [0u8; 32];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = key(1);
+        let trusted = [key.verifying_key()];
+        let content = sign(&key, b"{}", Some(b"meta"), Some(b"index"));
+        assert_eq!(content.len(), SIGNATURE_LEN);
+        assert_eq!(
+            verify_signature(
+                Some(&content),
+                &trusted,
+                b"{}",
+                Some(b"meta"),
+                Some(b"index")
+            ),
+            Ok(())
+        );
+        // any change to the sections breaks the signature
+        assert_eq!(
+            verify_signature(
+                Some(&content),
+                &trusted,
+                b"{}",
+                Some(b"metb"),
+                Some(b"index")
+            ),
+            Err(SignatureError::Invalid)
+        );
+        assert_eq!(
+            verify_signature(Some(&content), &trusted, b"{}", None, Some(b"metaindex")),
+            Err(SignatureError::Invalid)
+        );
+        assert_eq!(
+            verify_signature(None, &trusted, b"{}", Some(b"meta"), Some(b"index")),
+            Err(SignatureError::Missing)
+        );
+        assert_eq!(
+            verify_signature(Some(&content[1..]), &trusted, b"{}", None, None),
+            Err(SignatureError::Malformed)
+        );
+        let other = [self::key(2).verifying_key()];
+        assert!(matches!(
+            verify_signature(Some(&content), &other, b"{}", Some(b"meta"), Some(b"index")),
+            Err(SignatureError::UntrustedKey(_))
+        ));
+    }
+
+    #[test]
+    fn test_key_hex() {
+        let key = key(3);
+        let public = to_hex(key.verifying_key().as_bytes());
+        assert_eq!(parse_public_key(&public), Some(key.verifying_key()));
+        let secret = to_hex(&key.to_bytes());
+        assert_eq!(
+            parse_signing_key(&format!("{secret}\n"))
+                .unwrap()
+                .to_bytes(),
+            key.to_bytes()
+        );
+        assert!(parse_public_key(&public[2..]).is_none());
+        assert!(parse_public_key(&"zz".repeat(32)).is_none());
+    }
+}
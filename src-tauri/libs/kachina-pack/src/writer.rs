@@ -0,0 +1,767 @@
+use std::io;
+
+use ed25519_dalek::SigningKey;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::checksum::checksum;
+use crate::format::{
+    get_header_size, get_index_version, index_to_bin, tlv_header, IndexEntry, IndexHeader,
+    CONFIG_NAME, DOS_STUB_STRING, INDEX_HEADER_MAGIC, INDEX_NAME, INDEX_VERSION_CHECKSUM,
+    META_NAME,
+};
+use crate::signature::{sign, signature_entry_len, SIGNATURE_LEN, SIGNATURE_NAME};
+
+/// How much of the base exe is buffered to patch the DOS stub.
+const BASE_HEAD_LEN: usize = 1024;
+
+pub struct PackFile {
+    pub name: String,
+    pub size: u64,
+    pub data: Box<dyn AsyncRead + Unpin + Send>,
+    /// Checksum of `data`, see [`crate::checksum`]. Packing any file with one
+    /// switches the index to v3.
+    pub checksum: Option<u64>,
+}
+
+/// Everything that goes after the base exe. Files are written in the given order.
+pub struct PackContent {
+    pub config: Vec<u8>,
+    pub image: Option<PackFile>,
+    pub metadata: Option<Vec<u8>>,
+    pub files: Vec<PackFile>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PackLayout {
+    pub header: IndexHeader,
+    pub index: Vec<IndexEntry>,
+    pub index_version: u8,
+}
+
+impl PackLayout {
+    /// `signed` reserves room for `\0SIGN` after `\0META`.
+    pub fn new(base_len: u64, content: &PackContent, signed: bool) -> io::Result<Self> {
+        let mut index: Vec<IndexEntry> = vec![];
+        let with_checksum = content.files.iter().any(|f| f.checksum.is_some());
+        let mut push = |name: &str,
+                        size: u64,
+                        checksum: Option<u64>,
+                        current_offset: &mut u64|
+         -> io::Result<()> {
+            if name.len() > u8::MAX as usize {
+                return Err(invalid_input(&format!("Entry name too long: {name}")));
+            }
+            let offset = *current_offset + get_header_size(name, size) as u64;
+            index.push(IndexEntry {
+                name: name.to_string(),
+                size,
+                offset,
+                checksum,
+            });
+            *current_offset = offset + size;
+            Ok(())
+        };
+        let mut current_offset = 0;
+        push(
+            CONFIG_NAME,
+            content.config.len() as u64,
+            with_checksum.then(|| checksum(&content.config)),
+            &mut current_offset,
+        )?;
+        if let Some(img) = content.image.as_ref() {
+            push(&img.name, img.size, img.checksum, &mut current_offset)?;
+        }
+        let config_image_end = current_offset;
+        if let Some(metadata) = content.metadata.as_ref() {
+            push(
+                META_NAME,
+                metadata.len() as u64,
+                with_checksum.then(|| checksum(metadata)),
+                &mut current_offset,
+            )?;
+        }
+        if signed {
+            // not in the index, the index itself is signed
+            current_offset += signature_entry_len();
+        }
+        let meta_end = current_offset;
+        for file in content.files.iter() {
+            push(&file.name, file.size, file.checksum, &mut current_offset)?;
+        }
+        if content.files.is_empty() {
+            // config-only packs have no index and a cleared header
+            return Ok(PackLayout {
+                header: IndexHeader::default(),
+                index: vec![],
+                index_version: get_index_version(&[]),
+            });
+        }
+        let index_version = if with_checksum {
+            INDEX_VERSION_CHECKSUM
+        } else {
+            get_index_version(&index)
+        };
+        let index_bytes_len = index_to_bin(&index, index_version).len() as u64;
+        let index_len = index_bytes_len + get_header_size(INDEX_NAME, index_bytes_len) as u64;
+        // index is after config and image
+        for entry in index.iter_mut() {
+            if entry.offset > config_image_end {
+                entry.offset += index_len;
+            }
+        }
+        // the pre-index only describes the sections before the payload, which stay 32-bit
+        let fields = [
+            base_len,
+            index[0].size + index[0].offset,
+            config_image_end - index[0].size - index[0].offset,
+            index_len,
+            meta_end - config_image_end,
+        ];
+        let mut header = [0u32; 5];
+        for (field, value) in header.iter_mut().zip(fields) {
+            *field = u32::try_from(value).map_err(|_| {
+                invalid_input(
+                    "Base, config, image, index and metadata must each be smaller than 4 GiB",
+                )
+            })?;
+        }
+        Ok(PackLayout {
+            header: IndexHeader {
+                base_end: header[0],
+                config_len: header[1],
+                image_len: header[2],
+                index_len: header[3],
+                meta_len: header[4],
+            },
+            index,
+            index_version,
+        })
+    }
+}
+
+type ProgressCallback = Box<dyn FnMut(&str) + Send>;
+
+pub struct PackWriter<W> {
+    output: W,
+    progress: Option<ProgressCallback>,
+    signing_key: Option<SigningKey>,
+}
+
+impl<W: AsyncWrite + Unpin> PackWriter<W> {
+    pub fn new(output: W) -> Self {
+        PackWriter {
+            output,
+            progress: None,
+            signing_key: None,
+        }
+    }
+
+    /// Sign config, metadata and index into a `\0SIGN` entry.
+    pub fn sign_with(mut self, key: SigningKey) -> Self {
+        self.signing_key = Some(key);
+        self
+    }
+
+    /// Called with the entry name before each entry is written.
+    pub fn on_progress(mut self, progress: impl FnMut(&str) + Send + 'static) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    fn report(&mut self, name: &str) {
+        if let Some(progress) = self.progress.as_mut() {
+            progress(name);
+        }
+    }
+
+    /// Write `base` followed by `content`, with the pre-index header patched
+    /// into the DOS stub of `base`.
+    pub async fn write(
+        &mut self,
+        base: impl AsyncRead + Unpin,
+        base_len: u64,
+        mut content: PackContent,
+    ) -> io::Result<PackLayout> {
+        let layout = self.write_leading(base, base_len, &mut content).await?;
+        for file in content.files.iter_mut() {
+            self.report(&file.name);
+            write_file(&mut self.output, file).await?;
+        }
+        self.output.flush().await?;
+        Ok(layout)
+    }
+
+    /// Write `base` and the sections covered by the pre-index header, up to
+    /// where the files start. The files are only laid out, not read, so the
+    /// payload of an existing pack can be copied after this as is.
+    pub async fn write_leading(
+        &mut self,
+        base: impl AsyncRead + Unpin,
+        base_len: u64,
+        content: &mut PackContent,
+    ) -> io::Result<PackLayout> {
+        let layout = PackLayout::new(base_len, content, self.signing_key.is_some())?;
+        let written = write_base(&mut self.output, base, &layout.header).await?;
+        if written != base_len {
+            return Err(invalid_input(&format!(
+                "Base size mismatch: expected {base_len}, got {written}"
+            )));
+        }
+        self.report(CONFIG_NAME);
+        write_header(&mut self.output, CONFIG_NAME, content.config.len() as u64).await?;
+        self.output.write_all(&content.config).await?;
+        if let Some(image) = content.image.as_mut() {
+            self.report(&image.name);
+            write_file(&mut self.output, image).await?;
+        }
+        let index_bytes =
+            (!content.files.is_empty()).then(|| index_to_bin(&layout.index, layout.index_version));
+        if let Some(index_bytes) = index_bytes.as_ref() {
+            self.report(INDEX_NAME);
+            write_header(&mut self.output, INDEX_NAME, index_bytes.len() as u64).await?;
+            self.output.write_all(index_bytes).await?;
+        }
+        if let Some(metadata) = content.metadata.as_ref() {
+            self.report(META_NAME);
+            write_header(&mut self.output, META_NAME, metadata.len() as u64).await?;
+            self.output.write_all(metadata).await?;
+        }
+        if let Some(key) = self.signing_key.as_ref() {
+            let signature = sign(
+                key,
+                &content.config,
+                content.metadata.as_deref(),
+                index_bytes.as_deref(),
+            );
+            self.report(SIGNATURE_NAME);
+            write_header(&mut self.output, SIGNATURE_NAME, SIGNATURE_LEN as u64).await?;
+            self.output.write_all(&signature).await?;
+        }
+        Ok(layout)
+    }
+
+    /// Write an entry after the existing ones, outside of the index.
+    pub async fn append(&mut self, file: &mut PackFile) -> io::Result<()> {
+        self.report(&file.name);
+        write_file(&mut self.output, file).await?;
+        self.output.flush().await
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+}
+
+pub async fn write_header(
+    output: &mut (impl AsyncWrite + Unpin),
+    name: &str,
+    size: u64,
+) -> io::Result<()> {
+    output.write_all(&tlv_header(name, size)).await
+}
+
+pub async fn write_file(
+    output: &mut (impl AsyncWrite + Unpin),
+    file: &mut PackFile,
+) -> io::Result<()> {
+    write_header(output, &file.name, file.size).await?;
+    let copied = tokio::io::copy(&mut (&mut file.data).take(file.size), output).await?;
+    if copied != file.size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("{}: expected {} bytes, got {copied}", file.name, file.size),
+        ));
+    }
+    Ok(())
+}
+
+/// Copy the base exe, replacing 'This program cannot be run in DOS mode' with
+/// the pre-index header. Returns the number of bytes written.
+pub async fn write_base(
+    output: &mut (impl AsyncWrite + Unpin),
+    mut base: impl AsyncRead + Unpin,
+    header: &IndexHeader,
+) -> io::Result<u64> {
+    let mut head = Vec::with_capacity(BASE_HEAD_LEN);
+    (&mut base)
+        .take(BASE_HEAD_LEN as u64)
+        .read_to_end(&mut head)
+        .await?;
+    let header = header.to_bytes();
+    let pos = head
+        .windows(DOS_STUB_STRING.len())
+        .position(|w| w == DOS_STUB_STRING || w.starts_with(INDEX_HEADER_MAGIC))
+        .ok_or_else(|| invalid_input("Failed to find DOS mode string in PE header"))?;
+    head[pos..pos + header.len()].copy_from_slice(&header);
+    output.write_all(&head).await?;
+    let rest = tokio::io::copy(&mut base, output).await?;
+    Ok(head.len() as u64 + rest)
+}
+
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{Cursor, SeekFrom};
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use tokio::io::{AsyncSeek, ReadBuf};
+
+    use super::*;
+    use crate::format::{
+        bin_to_index, IMAGE_NAME, INDEX_VERSION_64, INDEX_VERSION_LEGACY, TLV_SIZE_EXTENDED,
+    };
+    use crate::reader::PackReader;
+
+    const GIB: u64 = 1 << 30;
+    const BLOCK: usize = 64 * 1024;
+
+    // Keeps only the blocks that are not all zero, so packs over 4 GiB of
+    // mostly zero data fit in memory.
+    #[derive(Default)]
+    struct Sparse {
+        blocks: HashMap<u64, Vec<u8>>,
+        len: u64,
+        pos: u64,
+    }
+
+    impl AsyncWrite for Sparse {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let mut rest = buf;
+            while !rest.is_empty() {
+                let start = (self.pos % BLOCK as u64) as usize;
+                let (chunk, next) = rest.split_at(rest.len().min(BLOCK - start));
+                let index = self.pos / BLOCK as u64;
+                if let Some(block) = self.blocks.get_mut(&index) {
+                    block[start..start + chunk.len()].copy_from_slice(chunk);
+                } else if chunk.iter().any(|&b| b != 0) {
+                    let mut block = vec![0; BLOCK];
+                    block[start..start + chunk.len()].copy_from_slice(chunk);
+                    self.blocks.insert(index, block);
+                }
+                self.pos += chunk.len() as u64;
+                self.len = self.len.max(self.pos);
+                rest = next;
+            }
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncRead for Sparse {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            let start = (self.pos % BLOCK as u64) as usize;
+            let len = (self.len.saturating_sub(self.pos) as usize)
+                .min(buf.remaining())
+                .min(BLOCK - start);
+            let out = buf.initialize_unfilled_to(len);
+            match self.blocks.get(&(self.pos / BLOCK as u64)) {
+                Some(block) => out.copy_from_slice(&block[start..start + len]),
+                None => out.fill(0),
+            }
+            buf.advance(len);
+            self.pos += len as u64;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncSeek for Sparse {
+        fn start_seek(mut self: Pin<&mut Self>, pos: SeekFrom) -> io::Result<()> {
+            self.pos = match pos {
+                SeekFrom::Start(pos) => pos,
+                SeekFrom::End(offset) => self.len.saturating_add_signed(offset),
+                SeekFrom::Current(offset) => self.pos.saturating_add_signed(offset),
+            };
+            Ok(())
+        }
+
+        fn poll_complete(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<u64>> {
+            Poll::Ready(Ok(self.pos))
+        }
+    }
+
+    fn base_exe() -> Vec<u8> {
+        let mut base = vec![0u8; 0x400];
+        base[0x4e..0x4e + DOS_STUB_STRING.len()].copy_from_slice(DOS_STUB_STRING);
+        base
+    }
+
+    fn file(name: &str, content: &[u8]) -> PackFile {
+        PackFile {
+            name: name.to_string(),
+            size: content.len() as u64,
+            data: Box::new(Cursor::new(content.to_vec())),
+            checksum: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_roundtrip() {
+        let base = base_exe();
+        let content = PackContent {
+            config: b"{}".to_vec(),
+            image: Some(file(IMAGE_NAME, b"png")),
+            metadata: Some(b"{\"tag_name\":\"1.0\"}".to_vec()),
+            files: vec![file("a", b"aaaa"), file("b", b"")],
+        };
+        let mut writer = PackWriter::new(Vec::new());
+        let layout = writer
+            .write(base.as_slice(), base.len() as u64, content)
+            .await
+            .unwrap();
+        assert_eq!(layout.index_version, INDEX_VERSION_LEGACY);
+        let data = writer.into_inner();
+
+        let mut reader = PackReader::new(Cursor::new(data.as_slice())).await.unwrap();
+        assert_eq!(reader.header(), Some(&layout.header));
+        assert_eq!(reader.base_end(), base.len() as u64);
+        assert_eq!(reader.config().await.unwrap().unwrap(), b"{}");
+        assert_eq!(reader.image().await.unwrap().unwrap(), b"png");
+        assert_eq!(
+            reader.metadata().await.unwrap().unwrap(),
+            b"{\"tag_name\":\"1.0\"}"
+        );
+        let (version, index) = reader.index().await.unwrap().unwrap();
+        assert_eq!(version, INDEX_VERSION_LEGACY);
+        assert_eq!(index, layout.index);
+        // every index entry points at its content
+        for entry in index.iter() {
+            let embedded = reader.find(&entry.name).unwrap().clone();
+            assert_eq!(embedded.offset as u64, base.len() as u64 + entry.offset);
+            assert_eq!(embedded.size as u64, entry.size);
+        }
+        let a = reader.find("a").unwrap().clone();
+        assert_eq!(reader.read_entry(&a).await.unwrap(), b"aaaa");
+        // the header describes the leading sections exactly
+        let header = layout.header;
+        let index_entry = reader.find(INDEX_NAME).unwrap();
+        assert_eq!(
+            index_entry.raw_offset as u64,
+            (header.base_end + header.config_len + header.image_len) as u64
+        );
+        assert_eq!(
+            bin_to_index(&data[index_entry.offset..index_entry.offset + index_entry.size])
+                .unwrap()
+                .1,
+            layout.index
+        );
+    }
+
+    #[tokio::test]
+    async fn test_config_only_pack() {
+        let base = base_exe();
+        let content = PackContent {
+            config: b"{}".to_vec(),
+            image: None,
+            metadata: None,
+            files: vec![],
+        };
+        let mut writer = PackWriter::new(Vec::new());
+        let layout = writer
+            .write(base.as_slice(), base.len() as u64, content)
+            .await
+            .unwrap();
+        assert_eq!(layout.header, IndexHeader::default());
+        let mut data = writer.into_inner();
+        // appending keeps the pack readable
+        let mut writer = PackWriter::new(&mut data);
+        writer.append(&mut file("runtime", b"rt")).await.unwrap();
+        let reader = PackReader::new(Cursor::new(data.as_slice())).await.unwrap();
+        let names: Vec<_> = reader.entries().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, [CONFIG_NAME, "runtime"]);
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_base_keeps_offsets() {
+        let base = base_exe();
+        let content = PackContent {
+            config: b"{}".to_vec(),
+            image: None,
+            metadata: None,
+            files: vec![file("a", b"aaaa")],
+        };
+        let mut writer = PackWriter::new(Vec::new());
+        let layout = writer
+            .write(base.as_slice(), base.len() as u64, content)
+            .await
+            .unwrap();
+        let data = writer.into_inner();
+        // swap in a bigger base, the payload is copied verbatim
+        let new_base = [base_exe(), vec![0u8; 0x200]].concat();
+        let header = IndexHeader {
+            base_end: new_base.len() as u32,
+            ..layout.header
+        };
+        let mut output = Vec::new();
+        write_base(&mut output, new_base.as_slice(), &header)
+            .await
+            .unwrap();
+        output.extend_from_slice(&data[base.len()..]);
+        let mut reader = PackReader::new(Cursor::new(output.as_slice()))
+            .await
+            .unwrap();
+        assert_eq!(reader.header(), Some(&header));
+        let a = reader.find("a").unwrap().clone();
+        assert_eq!(reader.read_entry(&a).await.unwrap(), b"aaaa");
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_leading_keeps_payload() {
+        let base = base_exe();
+        let mut a = file("a", b"aaaa");
+        a.checksum = Some(checksum(b"aaaa"));
+        let content = PackContent {
+            config: b"{}".to_vec(),
+            image: None,
+            metadata: Some(b"{}".to_vec()),
+            files: vec![a, file("b", b"bb")],
+        };
+        let mut writer = PackWriter::new(Vec::new());
+        let layout = writer
+            .write(base.as_slice(), base.len() as u64, content)
+            .await
+            .unwrap();
+        let data = writer.into_inner();
+        let payload = &data[layout.header.leading_end() as usize..];
+
+        // new config and image, the files are only laid out
+        let placeholder = |entry: &IndexEntry| PackFile {
+            name: entry.name.clone(),
+            size: entry.size,
+            data: Box::new(tokio::io::empty()),
+            checksum: entry.checksum,
+        };
+        let mut content = PackContent {
+            config: b"{\"title\":\"new\"}".to_vec(),
+            image: Some(file(IMAGE_NAME, b"png")),
+            metadata: Some(b"{}".to_vec()),
+            files: layout.index[2..].iter().map(placeholder).collect(),
+        };
+        let mut writer = PackWriter::new(Vec::new());
+        let new_layout = writer
+            .write_leading(base.as_slice(), base.len() as u64, &mut content)
+            .await
+            .unwrap();
+        let mut output = writer.into_inner();
+        assert_eq!(output.len() as u64, new_layout.header.leading_end());
+        output.extend_from_slice(payload);
+
+        let mut reader = PackReader::new(Cursor::new(output.as_slice()))
+            .await
+            .unwrap();
+        assert_eq!(
+            reader.config().await.unwrap().unwrap(),
+            b"{\"title\":\"new\"}"
+        );
+        assert_eq!(reader.image().await.unwrap().unwrap(), b"png");
+        let (version, index) = reader.index().await.unwrap().unwrap();
+        assert_eq!(version, INDEX_VERSION_CHECKSUM);
+        let delta = new_layout.header.leading_end() - layout.header.leading_end();
+        for (old, new) in layout.index[2..].iter().zip(index[3..].iter()) {
+            assert_eq!(new.offset, old.offset + delta);
+            assert_eq!(new.checksum, old.checksum);
+        }
+        let a = reader.find("a").unwrap().clone();
+        assert_eq!(reader.read_entry(&a).await.unwrap(), b"aaaa");
+        let b = reader.find("b").unwrap().clone();
+        assert_eq!(reader.read_entry(&b).await.unwrap(), b"bb");
+    }
+
+    #[tokio::test]
+    async fn test_checksum_index() {
+        let base = base_exe();
+        let mut a = file("a", b"aaaa");
+        a.checksum = Some(checksum(b"aaaa"));
+        let content = PackContent {
+            config: b"{}".to_vec(),
+            image: None,
+            metadata: Some(b"{}".to_vec()),
+            files: vec![a, file("b", b"bb")],
+        };
+        let mut writer = PackWriter::new(Vec::new());
+        let layout = writer
+            .write(base.as_slice(), base.len() as u64, content)
+            .await
+            .unwrap();
+        assert_eq!(layout.index_version, INDEX_VERSION_CHECKSUM);
+        let data = writer.into_inner();
+        let mut reader = PackReader::new(Cursor::new(data.as_slice())).await.unwrap();
+        assert_eq!(reader.index_version(), Some(INDEX_VERSION_CHECKSUM));
+        assert_eq!(reader.find("a").unwrap().checksum, Some(checksum(b"aaaa")));
+        assert_eq!(reader.find("b").unwrap().checksum, None);
+        let (_, index) = reader.index().await.unwrap().unwrap();
+        assert_eq!(index[0].checksum, Some(checksum(b"{}")));
+        // the checksum survives the trip to the frontend as hex
+        let a = reader.find("a").unwrap();
+        let json = serde_json::to_string(a).unwrap();
+        assert!(json.contains(&format!("\"{:016x}\"", checksum(b"aaaa"))));
+        assert_eq!(&serde_json::from_str::<crate::Embedded>(&json).unwrap(), a);
+    }
+
+    #[tokio::test]
+    async fn test_signed_pack() {
+        let base = base_exe();
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let trusted = [key.verifying_key()];
+        let content = PackContent {
+            config: b"{}".to_vec(),
+            image: Some(file(IMAGE_NAME, b"png")),
+            metadata: Some(b"{\"tag_name\":\"1.0\"}".to_vec()),
+            files: vec![file("a", b"aaaa")],
+        };
+        let mut writer = PackWriter::new(Vec::new()).sign_with(key);
+        let layout = writer
+            .write(base.as_slice(), base.len() as u64, content)
+            .await
+            .unwrap();
+        let mut data = writer.into_inner();
+
+        let mut reader = PackReader::new(Cursor::new(data.as_slice())).await.unwrap();
+        // \0SIGN is covered by meta_len and the index still resolves
+        assert_eq!(reader.header(), Some(&layout.header));
+        let sign = reader.find(SIGNATURE_NAME).unwrap().clone();
+        assert_eq!(
+            (sign.offset + sign.size) as u64,
+            layout.header.leading_end()
+        );
+        let a = reader.find("a").unwrap().clone();
+        assert_eq!(reader.read_entry(&a).await.unwrap(), b"aaaa");
+        assert_eq!(reader.verify_signature(&trusted).await.unwrap(), Ok(()));
+
+        // a fetched header only holds the leading sections
+        let header = layout.header;
+        let fetched = &data[header.base_end as usize..header.leading_end() as usize];
+        let mut remote = PackReader::walk(Cursor::new(fetched), 0).await.unwrap();
+        assert_eq!(remote.verify_signature(&trusted).await.unwrap(), Ok(()));
+
+        // an unsigned \0META appended after \0SIGN must not shadow the signed one
+        let mut forged = fetched.to_vec();
+        forged.extend_from_slice(&tlv_header(META_NAME, 2));
+        forged.extend_from_slice(b"{}");
+        assert!(PackReader::walk(Cursor::new(forged.as_slice()), 0)
+            .await
+            .is_err());
+        // neither may anything else follow the last entry
+        let trailing = [fetched, b"junk"].concat();
+        assert!(PackReader::walk(Cursor::new(trailing.as_slice()), 0)
+            .await
+            .is_err());
+
+        // nor may reserved entries be appended to the whole pack
+        for name in [META_NAME, CONFIG_NAME] {
+            let mut forged = data.clone();
+            forged.extend_from_slice(&tlv_header(name, 2));
+            forged.extend_from_slice(b"{}");
+            assert!(PackReader::new(Cursor::new(forged.as_slice()))
+                .await
+                .is_err());
+        }
+        // other appended files are still fine
+        let mut appended = data.clone();
+        appended.extend_from_slice(&tlv_header("runtime", 2));
+        appended.extend_from_slice(b"rt");
+        let mut reader = PackReader::new(Cursor::new(appended.as_slice()))
+            .await
+            .unwrap();
+        assert!(reader.find("runtime").is_some());
+        assert_eq!(reader.verify_signature(&trusted).await.unwrap(), Ok(()));
+
+        // tampering with the metadata is detected
+        let meta = reader.find(META_NAME).unwrap().clone();
+        data[meta.offset + 14] = b'2';
+        let mut reader = PackReader::new(Cursor::new(data.as_slice())).await.unwrap();
+        assert_eq!(
+            reader.verify_signature(&trusted).await.unwrap(),
+            Err(crate::SignatureError::Invalid)
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "writes a pack over 4 GiB, run with --ignored"]
+    async fn test_pack_over_4gib() {
+        let big_size = 4 * GIB + 12345;
+        // without checksums the index needs v2 for the offsets, with them v3
+        for (checksum, version) in [(None, INDEX_VERSION_64), (Some(42), INDEX_VERSION_CHECKSUM)] {
+            let base = base_exe();
+            let big = Cursor::new(b"head".to_vec())
+                .chain(tokio::io::repeat(0).take(big_size - 8))
+                .chain(Cursor::new(b"tail".to_vec()));
+            let mut after = file("after", b"after");
+            after.checksum = checksum;
+            let content = PackContent {
+                config: b"{}".to_vec(),
+                image: None,
+                metadata: Some(b"{}".to_vec()),
+                files: vec![
+                    file("a", b"aaaa"),
+                    PackFile {
+                        name: "big".to_string(),
+                        size: big_size,
+                        data: Box::new(big),
+                        checksum: None,
+                    },
+                    after,
+                ],
+            };
+            let mut output = Sparse::default();
+            let mut writer = PackWriter::new(&mut output);
+            let layout = writer
+                .write(base.as_slice(), base.len() as u64, content)
+                .await
+                .unwrap();
+            assert_eq!(layout.index_version, version);
+            assert!(output.len > 4 * GIB);
+
+            let mut reader = PackReader::new(&mut output).await.unwrap();
+            assert_eq!(reader.header(), Some(&layout.header));
+            assert_eq!(reader.index_version(), Some(version));
+            let big = reader.find("big").unwrap().clone();
+            assert_eq!(big.size as u64, big_size);
+            // the size of the entry does not fit in the 32-bit TLV field
+            let size_field = reader
+                .read_at(big.raw_offset as u64 + 6 + 3, 4)
+                .await
+                .unwrap();
+            assert_eq!(size_field, TLV_SIZE_EXTENDED.to_be_bytes());
+            let head = reader.read_at(big.offset as u64, 4).await.unwrap();
+            assert_eq!(head, b"head");
+            let tail = reader
+                .read_at((big.offset + big.size) as u64 - 4, 4)
+                .await
+                .unwrap();
+            assert_eq!(tail, b"tail");
+            // the file after it sits beyond 4 GiB
+            let after = reader.find("after").unwrap().clone();
+            assert!(after.offset as u64 > 4 * GIB);
+            assert_eq!(after.checksum, checksum);
+            assert_eq!(reader.read_entry(&after).await.unwrap(), b"after");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_short_file_is_rejected() {
+        let mut output = Vec::new();
+        let mut short = file("a", b"aa");
+        short.size = 4;
+        assert!(write_file(&mut output, &mut short).await.is_err());
+    }
+}
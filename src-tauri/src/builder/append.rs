@@ -1,52 +1,221 @@
-use tokio::io::AsyncSeekExt;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
 
-use crate::{
-    cli::AppendArgs,
-    pack::{write_file, PackFile},
+use kachina_pack::{
+    write_file, write_header, Embedded, PackContent, PackFile, PackReader, PackWriter, SigningKey,
+    CONFIG_NAME, IMAGE_NAME, INDEX_NAME, INDEX_VERSION_CHECKSUM, META_NAME, SIGNATURE_NAME,
 };
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
 
-pub async fn append_cli(args: AppendArgs) {
+use crate::cli::AppendArgs;
+use crate::keygen::read_sign_key;
+use crate::pack::file_checksum;
+
+// 索引之前的区段，不属于文件
+const LEADING_NAMES: [&str; 5] = [
+    CONFIG_NAME,
+    IMAGE_NAME,
+    INDEX_NAME,
+    META_NAME,
+    SIGNATURE_NAME,
+];
+
+enum Source {
+    // 安装包中已有的条目，原样复制
+    Packed(Embedded),
+    // 新追加或替换的文件
+    File(PathBuf),
+}
+
+// 追加文件后重建索引与文件头，追加的文件也能通过索引定位
+pub async fn append_cli(args: AppendArgs) -> Result<(), String> {
     // files len should equals to names len, or names len should be 0
     if args.file.len() != args.name.len() && !args.name.is_empty() {
-        panic!("Files length must equal to names length, or names length must be 0");
+        return Err(
+            "Files length must equal to names length, or names length must be 0".to_string(),
+        );
     }
-    // open file as append mode
-    let mut output = tokio::fs::OpenOptions::new()
-        .append(true)
-        .open(&args.output)
-        .await
-        .expect("Failed to open output file");
-    // seek to the end of the file
-    output
-        .seek(std::io::SeekFrom::End(0))
-        .await
-        .expect("Failed to seek to the end of the file");
     // loop through input files, get corresponding name or dafault to the file name
+    let mut names: Vec<String> = vec![];
     for (i, file) in args.file.iter().enumerate() {
         let name = if !args.name.is_empty() {
-            &args.name[i]
+            args.name[i].clone()
         } else {
-            file.file_name().and_then(|s| s.to_str()).unwrap()
+            file.file_name()
+                .and_then(|s| s.to_str())
+                .ok_or_else(|| format!("Invalid file name: {:?}", file))?
+                .to_string()
         };
-        let input_stream = tokio::fs::File::open(file)
-            .await
-            .expect("Failed to open input file");
-        let input_length = input_stream
-            .metadata()
-            .await
-            .expect("Failed to get input file metadata")
-            .len();
-        // write file to output
-        write_file(
-            &mut output,
-            &mut PackFile {
-                name: name.to_string(),
-                data: Box::new(input_stream),
-                size: input_length.try_into().expect("File size too large"),
-            },
-        )
+        if LEADING_NAMES.contains(&name.as_str()) || names.contains(&name) {
+            return Err(format!("Invalid or duplicate name: {name}"));
+        }
+        names.push(name);
+    }
+
+    println!("Parsing installer index...");
+    let input = File::open(&args.output).await.map_err(|e| e.to_string())?;
+    let mut reader = PackReader::new(input).await.map_err(|e| e.to_string())?;
+    if reader.certificate().is_some() {
+        return Err(
+            "Authenticode-signed installers can't be modified, append to the unsigned one and sign it again"
+                .to_string(),
+        );
+    }
+    let config = reader
+        .config()
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "No config found in output file".to_string())?;
+    let sign_key = match args.sign_key.as_ref() {
+        Some(path) => Some(read_sign_key(path).await?),
+        None => None,
+    };
+    if sign_key.is_none() && reader.find(SIGNATURE_NAME).is_some() {
+        return Err("Output is signed, --sign-key is required".to_string());
+    }
+    let image = match reader.find(IMAGE_NAME).cloned() {
+        Some(entry) => {
+            let data = reader.read_entry(&entry).await.map_err(|e| e.to_string())?;
+            Some(PackFile {
+                name: IMAGE_NAME.to_string(),
+                size: data.len() as u64,
+                data: Box::new(Cursor::new(data)),
+                checksum: entry.checksum,
+            })
+        }
+        None => None,
+    };
+    let metadata = reader.metadata().await.map_err(|e| e.to_string())?;
+
+    // 已有文件（索引中的与之前追加的）保持原顺序，同名文件仅在 --replace 时替换
+    let mut packed: Vec<Embedded> = reader
+        .entries()
+        .iter()
+        .filter(|e| !LEADING_NAMES.contains(&e.name.as_str()))
+        .cloned()
+        .collect();
+    packed.sort_by_key(|e| e.offset);
+    let mut sources: Vec<(String, Source)> = packed
+        .into_iter()
+        .map(|e| (e.name.clone(), Source::Packed(e)))
+        .collect();
+    for (name, file) in names.into_iter().zip(args.file.iter()) {
+        match sources.iter_mut().find(|(n, _)| *n == name) {
+            Some(existing) if args.replace => existing.1 = Source::File(file.clone()),
+            Some(_) => {
+                return Err(format!(
+                    "Entry {name} already exists, use --replace to overwrite it"
+                ))
+            }
+            None => sources.push((name, Source::File(file.clone()))),
+        }
+    }
+
+    // 文件只参与布局计算，写入时再逐个复制
+    let with_checksum = reader.index_version() == Some(INDEX_VERSION_CHECKSUM);
+    let mut files = vec![];
+    for (name, source) in sources.iter() {
+        let (size, checksum) = match source {
+            Source::Packed(entry) => (entry.size as u64, entry.checksum),
+            Source::File(path) => {
+                let size = tokio::fs::metadata(path)
+                    .await
+                    .map_err(|e| format!("Failed to get input file metadata {:?}: {e}", path))?
+                    .len();
+                let checksum = file_checksum(path, with_checksum)
+                    .await
+                    .map_err(|e| format!("Failed to checksum file {name}: {e}"))?;
+                (size, checksum)
+            }
+        };
+        files.push(PackFile {
+            name: name.clone(),
+            size,
+            data: Box::new(tokio::io::empty()),
+            checksum,
+        });
+    }
+    let content = PackContent {
+        config,
+        image,
+        metadata,
+        files,
+    };
+    let base = reader
+        .read_at(0, reader.base_end())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // 写入同目录下的临时文件，完成后替换原文件
+    let tmp = args
+        .output
+        .with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+    let input = reader.into_inner();
+    if let Err(e) = write_appended(&tmp, input, &base, content, &sources, sign_key).await {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(e);
+    }
+    tokio::fs::rename(&tmp, &args.output)
+        .await
+        .map_err(|e| format!("Failed to replace {:?}: {e}", args.output))?;
+    Ok(())
+}
+
+async fn write_appended(
+    path: &Path,
+    mut input: File,
+    base: &[u8],
+    mut content: PackContent,
+    sources: &[(String, Source)],
+    sign_key: Option<SigningKey>,
+) -> Result<(), String> {
+    let mut output = File::create(path).await.map_err(|e| e.to_string())?;
+    let mut writer = PackWriter::new(&mut output);
+    if let Some(key) = sign_key {
+        writer = writer.sign_with(key);
+    }
+    let layout = writer
+        .write_leading(base, base.len() as u64, &mut content)
         .await
-        .expect("Failed to write file");
-        println!("Appended file: {name} ({input_length} bytes)");
+        .map_err(|e| e.to_string())?;
+    for (file, (name, source)) in content.files.iter().zip(sources.iter()) {
+        match source {
+            Source::Packed(entry) => {
+                write_header(&mut output, name, file.size)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                input
+                    .seek(SeekFrom::Start(entry.offset as u64))
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let copied = tokio::io::copy(&mut (&mut input).take(file.size), &mut output)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                if copied != file.size {
+                    return Err(format!("Entry {name} is truncated"));
+                }
+            }
+            Source::File(path) => {
+                let input_stream = File::open(path)
+                    .await
+                    .map_err(|e| format!("Failed to open input file {:?}: {e}", path))?;
+                write_file(
+                    &mut output,
+                    &mut PackFile {
+                        name: name.clone(),
+                        size: file.size,
+                        data: Box::new(input_stream),
+                        checksum: file.checksum,
+                    },
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+                println!("Appended file: {name} ({} bytes)", file.size);
+            }
+        }
     }
+    output.flush().await.map_err(|e| e.to_string())?;
+    println!("New index: {:?}", layout.header);
+    Ok(())
 }
@@ -0,0 +1,124 @@
+use kachina_pack::{
+    check_attached, detached_base, get_security_directory, same_image, write_attached, PackReader,
+    MAX_ATTACHED_PAYLOAD,
+};
+use tokio::fs::File;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt, SeekFrom};
+
+use crate::cli::AuthenticodeArgs;
+
+// 签名流程：先 --detach 导出不含数据的 exe，用 signtool 等工具签名后，
+// 再 --attach 把数据放入证书表，签名不受影响
+pub async fn authenticode_cli(args: AuthenticodeArgs) -> Result<(), String> {
+    match (&args.detach, &args.attach) {
+        (Some(_), Some(_)) => Err("Only one of --detach and --attach can be used".to_string()),
+        (None, None) => Err("Either --detach or --attach is required".to_string()),
+        (Some(_), None) => detach(&args).await,
+        (None, Some(_)) => attach(&args).await,
+    }
+}
+
+struct Payload {
+    reader: PackReader<File>,
+    base: Vec<u8>,
+    start: u64,
+    end: u64,
+}
+
+// 找出安装包中 exe 与数据的边界
+async fn read_payload(args: &AuthenticodeArgs) -> Result<Payload, String> {
+    println!("Parsing installer index...");
+    let input = File::open(&args.input).await.map_err(|e| e.to_string())?;
+    let mut reader = PackReader::new(input).await.map_err(|e| e.to_string())?;
+    if reader.entries().is_empty() {
+        return Err("No packed entries found in input file".to_string());
+    }
+    let start = reader.base_end();
+    let head = reader.read_at(0, start).await.map_err(|e| e.to_string())?;
+    let end = match reader.certificate() {
+        Some(cert) => cert.end(),
+        None => match get_security_directory(&head) {
+            // 打包后直接签过名的安装包，证书表在数据之后
+            Some(dir) if dir.size != 0 && dir.offset >= start => dir.offset,
+            _ => reader.file_len(),
+        },
+    };
+    // 证书表条目的长度是 u32，超出时在导出或写入任何文件前报错
+    if end - start > MAX_ATTACHED_PAYLOAD {
+        return Err(format!(
+            "Payload is {} bytes, Authenticode-signed installers can hold at most {} bytes (4 GiB)",
+            end - start,
+            MAX_ATTACHED_PAYLOAD
+        ));
+    }
+    let base = detached_base(&head).ok_or_else(|| "Failed to parse PE header".to_string())?;
+    Ok(Payload {
+        reader,
+        base,
+        start,
+        end,
+    })
+}
+
+async fn detach(args: &AuthenticodeArgs) -> Result<(), String> {
+    let output = args.detach.as_ref().unwrap();
+    let payload = read_payload(args).await?;
+    tokio::fs::write(output, &payload.base)
+        .await
+        .map_err(|e| e.to_string())?;
+    println!(
+        "Unsigned exe written to {}, sign it and run --attach",
+        output.display()
+    );
+    Ok(())
+}
+
+async fn attach(args: &AuthenticodeArgs) -> Result<(), String> {
+    let signed_path = args.attach.as_ref().unwrap();
+    let Some(output_path) = args.output.as_ref() else {
+        return Err("--output is required with --attach".to_string());
+    };
+    if output_path == &args.input {
+        return Err("Output must not overwrite the input file".to_string());
+    }
+    let payload = read_payload(args).await?;
+    let signed = tokio::fs::read(signed_path)
+        .await
+        .map_err(|e| e.to_string())?;
+    // 签名工具只能改动证书表，其余内容必须和 --detach 导出的一致
+    if !same_image(&payload.base, &signed) {
+        return Err(format!(
+            "{} is not the exe detached from {}",
+            signed_path.display(),
+            args.input.display()
+        ));
+    }
+    let header = payload.reader.header().copied().unwrap_or_default();
+    println!("Payload: {} bytes", payload.end - payload.start);
+    check_attached(&signed, payload.end - payload.start).map_err(|e| e.to_string())?;
+
+    println!("Writing signed installer...");
+    let mut input = payload.reader.into_inner();
+    input
+        .seek(SeekFrom::Start(payload.start))
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut output = File::create(output_path).await.map_err(|e| e.to_string())?;
+    let header = write_attached(
+        &mut output,
+        &signed,
+        &header,
+        &mut input,
+        payload.end - payload.start,
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+    output.flush().await.map_err(|e| e.to_string())?;
+    println!("New index: {:?}", header);
+
+    println!(
+        "Successfully created signed installer: {}",
+        output_path.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+use twox_hash::XxHash3_128;
+
+use crate::utils::{hash::run_hash, metadata::Codec};
+
+// 缓存格式变化时递增，旧缓存直接丢弃
+const CACHE_VERSION: u32 = 1;
+const INDEX_NAME: &str = "index.json";
+const OBJECTS_DIR: &str = "objects";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct HashEntry {
+    size: u64,
+    mtime: u64,
+    // 强哈希只在 --strong-hash 时计算，各算法分别缓存
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    xxh: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    blake3: Option<String>,
+}
+
+impl HashEntry {
+    fn hash_mut(&mut self, algorithm: &str) -> &mut Option<String> {
+        match algorithm {
+            "sha256" => &mut self.sha256,
+            "blake3" => &mut self.blake3,
+            _ => &mut self.xxh,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Artifact {
+    // 文件的存储方式，补丁与更新器为 None
+    pub codec: Option<Codec>,
+    // 未压缩的大小，补丁即 diff 的大小
+    pub size: u64,
+    // 补丁过大被丢弃时没有产物，只记录结果
+    pub stored: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheIndex {
+    version: u32,
+    // 绝对路径 -> 大小、修改时间与哈希
+    hashes: HashMap<String, HashEntry>,
+    // 内容哈希与压缩参数 -> 压缩结果
+    artifacts: HashMap<String, Artifact>,
+}
+
+// gen 的增量缓存。未指定目录时只在本次运行内复用哈希，不保存压缩结果
+pub struct GenCache {
+    dir: Option<PathBuf>,
+    index: Mutex<CacheIndex>,
+}
+
+impl GenCache {
+    pub async fn open(dir: Option<&Path>) -> Result<Self, String> {
+        let mut index = CacheIndex {
+            version: CACHE_VERSION,
+            ..Default::default()
+        };
+        if let Some(dir) = dir {
+            tokio::fs::create_dir_all(dir.join(OBJECTS_DIR))
+                .await
+                .map_err(|e| format!("Failed to create cache directory {dir:?}: {e}"))?;
+            if let Ok(data) = tokio::fs::read(dir.join(INDEX_NAME)).await {
+                match serde_json::from_slice::<CacheIndex>(&data) {
+                    Ok(cached) if cached.version == CACHE_VERSION => index = cached,
+                    Ok(_) => println!("Cache version changed, starting over"),
+                    Err(e) => eprintln!("Ignoring broken cache index: {e}"),
+                }
+            }
+        }
+        Ok(Self {
+            dir: dir.map(Path::to_path_buf),
+            index: Mutex::new(index),
+        })
+    }
+
+    pub async fn hash_file(&self, path: &Path) -> anyhow::Result<String> {
+        self.hash_file_with(path, "xxh").await
+    }
+
+    // 大小与修改时间都未变化时直接返回上次的哈希
+    pub async fn hash_file_with(&self, path: &Path, algorithm: &str) -> anyhow::Result<String> {
+        let path = tokio::fs::canonicalize(path).await?;
+        let meta = tokio::fs::metadata(&path).await?;
+        let mtime = meta.modified()?.duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+        let key = path.to_string_lossy().to_string();
+        if let Some(entry) = self.index.lock().unwrap().hashes.get_mut(&key) {
+            if entry.size == meta.len() && entry.mtime == mtime {
+                if let Some(hash) = entry.hash_mut(algorithm) {
+                    return Ok(hash.clone());
+                }
+            }
+        }
+        let hash = run_hash(algorithm, path.to_str().unwrap()).await?;
+        let mut index = self.index.lock().unwrap();
+        let entry = index.hashes.entry(key).or_default();
+        if entry.size != meta.len() || entry.mtime != mtime {
+            // 文件已变化，其他算法的哈希也作废
+            *entry = HashEntry {
+                size: meta.len(),
+                mtime,
+                ..Default::default()
+            };
+        }
+        *entry.hash_mut(algorithm) = Some(hash.clone());
+        Ok(hash)
+    }
+
+    // 命中时把缓存的产物复制到 target
+    pub async fn restore(&self, key: &str, target: &Path) -> Option<Artifact> {
+        let dir = self.dir.as_ref()?;
+        let artifact = self.index.lock().unwrap().artifacts.get(key).cloned()?;
+        if artifact.stored {
+            tokio::fs::copy(object_path(dir, key), target).await.ok()?;
+        }
+        Some(artifact)
+    }
+
+    pub async fn store(&self, key: &str, source: Option<&Path>, artifact: Artifact) {
+        let Some(dir) = self.dir.as_ref() else {
+            return;
+        };
+        if let Some(source) = source {
+            // 先写临时文件，避免中断后留下不完整的产物
+            let path = object_path(dir, key);
+            let tmp_path = path.with_extension("tmp");
+            if let Err(e) = tokio::fs::copy(source, &tmp_path).await {
+                eprintln!("Failed to cache {source:?}: {e}");
+                return;
+            }
+            if let Err(e) = tokio::fs::rename(&tmp_path, &path).await {
+                eprintln!("Failed to cache {source:?}: {e}");
+                return;
+            }
+        }
+        self.index
+            .lock()
+            .unwrap()
+            .artifacts
+            .insert(key.to_string(), artifact);
+    }
+
+    pub async fn save(&self) -> Result<(), String> {
+        let Some(dir) = self.dir.as_ref() else {
+            return Ok(());
+        };
+        let data = serde_json::to_vec(&*self.index.lock().unwrap()).map_err(|e| e.to_string())?;
+        let tmp_path = dir.join(format!("{INDEX_NAME}.tmp"));
+        tokio::fs::write(&tmp_path, data)
+            .await
+            .map_err(|e| format!("Failed to write cache index: {e}"))?;
+        tokio::fs::rename(&tmp_path, dir.join(INDEX_NAME))
+            .await
+            .map_err(|e| format!("Failed to write cache index: {e}"))
+    }
+}
+
+// 产物的缓存键，包含 builder 版本，升级后压缩结果可能不同
+pub fn artifact_key(kind: &str, hash: &str, settings: &str) -> String {
+    format!("{kind}:{hash}:{}:{settings}", env!("CARGO_PKG_VERSION"))
+}
+
+fn object_path(dir: &Path, key: &str) -> PathBuf {
+    let mut hasher = XxHash3_128::new();
+    hasher.write(key.as_bytes());
+    dir.join(OBJECTS_DIR)
+        .join(format!("{:x}", hasher.finish_128()))
+}
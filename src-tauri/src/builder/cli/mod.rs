@@ -19,6 +19,15 @@ pub struct PackArgs {
     pub data_dir: Option<PathBuf>,
     #[clap(long)]
     pub icon: Option<PathBuf>,
+    /// 为每个文件写入 xxh3 校验值（v3 索引，旧版安装器无法读取）
+    #[clap(long)]
+    pub checksum: bool,
+    /// 用于签名配置、元数据与索引的私钥文件，由 keygen 生成
+    #[clap(long)]
+    pub sign_key: Option<PathBuf>,
+    /// 写入构建信息（builder 版本、提交与构建时间），时间可通过 SOURCE_DATE_EPOCH 指定
+    #[clap(long)]
+    pub build_info: bool,
 }
 
 #[derive(Debug, Clone, clap::Args)]
@@ -43,6 +52,39 @@ pub struct GenArgs {
     pub updater_name: Option<String>,
     #[clap(long, short = 'j', default_value = "2")]
     pub zstd_concurrency: usize,
+    /// 压缩后体积减少不足该百分比时直接存储原文件，默认 0 即总是压缩；直接存储的文件需要新版安装器
+    #[clap(long, default_value = "0")]
+    pub raw_threshold: u8,
+    /// 按 glob 覆盖压缩参数，可多次指定，后面的规则优先，如 "*.mp4:raw" 或 "*.pak:level=19,long,workers=4"
+    #[clap(long, short = 'z')]
+    pub compress: Vec<String>,
+    /// 用不超过 1 MiB 的小文件训练 zstd 字典，并用它压缩这些文件
+    #[clap(long)]
+    pub dict: bool,
+    /// 字典的最大字节数
+    #[clap(long, default_value = "112640")]
+    pub dict_size: usize,
+    /// 增量缓存目录，保存文件哈希与压缩结果，多次运行之间复用
+    #[clap(long)]
+    pub cache: Option<PathBuf>,
+    /// 从 --diff-vers 的旧版本中保留最近几个版本的补丁，组成可跨多个版本更新的补丁链，0 表示不保留
+    #[clap(long, default_value = "0")]
+    pub patch_history: u32,
+    /// 每个补丁任务的内存上限（MiB），设置后所有补丁都流式生成；未设置时只有超过 512 MiB 的文件流式生成
+    #[clap(long)]
+    pub max_diff_memory: Option<u64>,
+    /// 每个补丁任务使用的线程数，默认按 CPU 核数平分给 -j 个并行任务；设置 SOURCE_DATE_EPOCH 时默认为 4
+    #[clap(long)]
+    pub diff_threads: Option<usize>,
+    /// 以 HDiffPatch 原生压缩格式生成补丁，需要新版安装器；默认先生成未压缩的补丁再整体以 zstd 压缩，兼容旧版安装器
+    #[clap(long)]
+    pub native_patch: bool,
+    /// 为匹配 glob 的小文件生成目录补丁，一个补丁更新其中所有有变化的文件，可多次指定，如 "scripts/**"
+    #[clap(long)]
+    pub dir_patch: Vec<String>,
+    /// 额外计算并写入 metadata 的强哈希，可多次指定，支持 sha256、blake3；安装器会使用其中最强的一个校验文件
+    #[clap(long)]
+    pub strong_hash: Vec<String>,
 }
 
 #[derive(Debug, Clone, clap::Args)]
@@ -53,6 +95,12 @@ pub struct AppendArgs {
     pub file: Vec<PathBuf>,
     #[clap(long, short = 'n')]
     pub name: Vec<String>,
+    /// 替换同名的已有文件，否则同名时报错
+    #[clap(long)]
+    pub replace: bool,
+    /// 签名私钥，安装包已签名时必须提供
+    #[clap(long)]
+    pub sign_key: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone, clap::Args)]
@@ -84,6 +132,75 @@ pub struct ReplaceBinArgs {
     pub output: PathBuf,
 }
 
+#[derive(Debug, Clone, clap::Args)]
+pub struct VerifyArgs {
+    /// 要校验的安装包文件
+    #[clap(long, short = 'i', default_value = "output.exe")]
+    pub input: PathBuf,
+    /// 旧版本目录，用于校验补丁能否正确应用
+    #[clap(long, short = 's')]
+    pub old_dir: Option<PathBuf>,
+    /// 将校验报告以 JSON 格式写入文件
+    #[clap(long)]
+    pub json: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct KeygenArgs {
+    /// 私钥输出文件，公钥会打印到控制台
+    #[clap(long, short = 'o', default_value = "kachina.key")]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct AuthenticodeArgs {
+    /// 安装包文件
+    #[clap(long, short = 'i', default_value = "output.exe")]
+    pub input: PathBuf,
+    /// 导出不含数据的 exe，交给签名工具签名
+    #[clap(long)]
+    pub detach: Option<PathBuf>,
+    /// 签名后的 exe，安装包数据会放入它的证书表，数据不能超过 4 GiB
+    #[clap(long)]
+    pub attach: Option<PathBuf>,
+    /// 签名后的安装包，与 --attach 一起使用
+    #[clap(long, short = 'o')]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct RepackArgs {
+    /// 输入的安装包文件
+    #[clap(long, short = 'i', default_value = "output.exe")]
+    pub input: PathBuf,
+    /// 输出的新安装包文件
+    #[clap(long, short = 'o')]
+    pub output: PathBuf,
+    /// 新的配置文件，同时更新 exe 的标题与产品名
+    #[clap(long, short = 'c')]
+    pub config: Option<PathBuf>,
+    /// 新的左侧图片或自定义 css
+    #[clap(long, short = 't')]
+    pub image: Option<PathBuf>,
+    /// 新的 exe 图标
+    #[clap(long)]
+    pub icon: Option<PathBuf>,
+    /// 签名私钥，原安装包已签名时必须提供
+    #[clap(long)]
+    pub sign_key: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct DiffArgs {
+    /// 旧版本的安装包或 metadata.json
+    pub old: PathBuf,
+    /// 新版本的安装包或 metadata.json
+    pub new: PathBuf,
+    /// 将差异报告以 JSON 格式写入文件
+    #[clap(long)]
+    pub json: Option<PathBuf>,
+}
+
 #[derive(Subcommand, Clone, Debug)]
 pub enum Command {
     Pack(PackArgs),
@@ -91,6 +208,11 @@ pub enum Command {
     Extract(ExtractArgs),
     Gen(GenArgs),
     ReplaceBin(ReplaceBinArgs),
+    Verify(VerifyArgs),
+    Keygen(KeygenArgs),
+    Authenticode(AuthenticodeArgs),
+    Repack(RepackArgs),
+    Diff(DiffArgs),
 }
 
 #[derive(Parser)]
@@ -0,0 +1,164 @@
+use async_compression::{tokio::bufread::ZstdEncoder, zstd::CParameter};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use tokio::io::AsyncBufRead;
+
+// 固定压缩参数，不随依赖的默认值变化，相同输入总是得到相同输出
+pub const ZSTD_LEVEL: i32 = 22;
+// 安装器解压时默认允许的最大窗口，超过后需要额外设置解压参数
+const MAX_WINDOW_LOG: u32 = 27;
+// 不超过该大小的文件参与字典训练并使用字典压缩，与 packing_info 的小文件一致
+pub const DICT_FILE_MAX_SIZE: u64 = 1024 * 1024;
+// 样本太少时训练出的字典没有意义
+const DICT_MIN_SAMPLES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressOptions {
+    pub raw: bool,
+    pub level: i32,
+    // 开启长距离匹配时的窗口大小
+    pub long: Option<u32>,
+    // 0 为单线程；多线程的输出与线程数无关，但与单线程不同
+    pub workers: u32,
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        Self {
+            raw: false,
+            level: ZSTD_LEVEL,
+            long: None,
+            workers: 0,
+        }
+    }
+}
+
+impl CompressOptions {
+    pub fn encoder<R: AsyncBufRead>(&self, reader: R) -> ZstdEncoder<R> {
+        let mut params = vec![
+            CParameter::checksum_flag(false),
+            CParameter::nb_workers(self.workers),
+        ];
+        if let Some(window_log) = self.long {
+            params.push(CParameter::enable_long_distance_matching(true));
+            params.push(CParameter::window_log(window_log));
+        }
+        ZstdEncoder::with_quality_and_params(
+            reader,
+            async_compression::Level::Precise(self.level),
+            &params,
+        )
+    }
+
+    // 字典模式不支持长距离匹配与多线程，只使用压缩等级
+    pub fn dict_encoder<R: AsyncBufRead>(
+        &self,
+        reader: R,
+        dictionary: &[u8],
+    ) -> std::io::Result<ZstdEncoder<R>> {
+        ZstdEncoder::with_dict(
+            reader,
+            async_compression::Level::Precise(self.level),
+            dictionary,
+        )
+    }
+}
+
+// 用所有小文件训练字典，样本顺序固定，结果可复现
+pub fn train_dictionary(samples: &[Vec<u8>], max_size: usize) -> Result<Vec<u8>, String> {
+    if samples.len() < DICT_MIN_SAMPLES {
+        return Err(format!(
+            "only {} small files, at least {DICT_MIN_SAMPLES} are needed",
+            samples.len()
+        ));
+    }
+    zstd::dict::from_samples(samples, max_size).map_err(|e| e.to_string())
+}
+
+enum CompressOption {
+    Raw,
+    Level(i32),
+    Long(u32),
+    Workers(u32),
+}
+
+struct CompressRule {
+    matcher: Gitignore,
+    options: Vec<CompressOption>,
+}
+
+// --compress 规则：GLOB:选项[,选项...]，按顺序应用，后面的规则覆盖前面的
+pub struct CompressRules {
+    rules: Vec<CompressRule>,
+}
+
+impl CompressRules {
+    pub fn parse(rules: &[String]) -> Result<Self, String> {
+        let rules = rules
+            .iter()
+            .map(|rule| parse_rule(rule).map_err(|e| format!("Invalid --compress {rule:?}: {e}")))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn options_for(&self, file_name: &str) -> CompressOptions {
+        let mut options = CompressOptions::default();
+        for rule in self.rules.iter() {
+            if !rule
+                .matcher
+                .matched_path_or_any_parents(file_name, false)
+                .is_ignore()
+            {
+                continue;
+            }
+            for option in rule.options.iter() {
+                match option {
+                    CompressOption::Raw => options.raw = true,
+                    CompressOption::Level(level) => options.level = *level,
+                    CompressOption::Long(window_log) => options.long = Some(*window_log),
+                    CompressOption::Workers(workers) => options.workers = *workers,
+                }
+            }
+        }
+        options
+    }
+}
+
+fn parse_rule(rule: &str) -> Result<CompressRule, String> {
+    let (glob, options) = rule
+        .rsplit_once(':')
+        .ok_or_else(|| "expected GLOB:OPTIONS".to_string())?;
+    let mut matcher = GitignoreBuilder::new("/");
+    matcher.add_line(None, glob).map_err(|e| e.to_string())?;
+    let matcher = matcher.build().map_err(|e| e.to_string())?;
+    let options = options
+        .split(',')
+        .map(|option| {
+            let (key, value) = match option.split_once('=') {
+                Some((key, value)) => (key.trim(), Some(value.trim())),
+                None => (option.trim(), None),
+            };
+            let number = |default: Option<u32>| match value {
+                Some(value) => value
+                    .parse::<u32>()
+                    .map_err(|e| format!("invalid value for {key}: {e}")),
+                None => default.ok_or_else(|| format!("{key} requires a value")),
+            };
+            match key {
+                "raw" => Ok(CompressOption::Raw),
+                "level" => match number(None)? {
+                    level @ 1..=22 => Ok(CompressOption::Level(level as i32)),
+                    level => Err(format!("level {level} out of range 1-22")),
+                },
+                "long" => match number(Some(MAX_WINDOW_LOG))? {
+                    window_log @ 10..=MAX_WINDOW_LOG => Ok(CompressOption::Long(window_log)),
+                    window_log => Err(format!(
+                        "long window {window_log} out of range 10-{MAX_WINDOW_LOG}"
+                    )),
+                },
+                "workers" => Ok(CompressOption::Workers(number(None)?)),
+                key => Err(format!("unknown option {key:?}")),
+            }
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(CompressRule { matcher, options })
+}
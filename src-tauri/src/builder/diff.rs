@@ -0,0 +1,434 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use fmmap::tokio::AsyncMmapFile;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    cli::DiffArgs,
+    extract::{
+        collect_file_info, format_file_size, parse_metadata, read_embedded, truncate_string,
+    },
+    utils::metadata::{Metadata, PatchInfo, RepoMetadata},
+};
+
+// 一个版本：安装包或单独的 metadata.json
+struct Release {
+    metadata: RepoMetadata,
+    config: Option<Value>,
+    // 安装包内嵌条目的实际（压缩后）大小，metadata.json 时为空
+    sizes: HashMap<String, u64>,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Change {
+    Added,
+    Removed,
+    Changed,
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Added => write!(f, "ADDED"),
+            Change::Removed => write!(f, "REMOVED"),
+            Change::Changed => write!(f, "CHANGED"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct FileDiff {
+    file_name: String,
+    change: Change,
+    old_size: Option<u64>,
+    new_size: Option<u64>,
+    size_delta: i64,
+    // 从旧版本更新时需要下载的大小，删除的文件为 0
+    download: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    patch: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct PatchDiff {
+    file_name: String,
+    from: String,
+    to: String,
+    size: u64,
+    // 旧版本中的文件哈希与 from 一致，更新时会使用该补丁
+    applies: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct ConfigDiff {
+    key: String,
+    old: Option<Value>,
+    new: Option<Value>,
+}
+
+#[derive(Debug, Serialize)]
+struct DiffReport {
+    old: String,
+    new: String,
+    old_tag: String,
+    new_tag: String,
+    unchanged: usize,
+    files: Vec<FileDiff>,
+    patches: Vec<PatchDiff>,
+    config: Vec<ConfigDiff>,
+    // 全新安装与从旧版本更新的预计下载大小
+    full_download: u64,
+    update_download: u64,
+    // 大小来自安装包索引（压缩后），否则为 metadata 中的原始大小
+    compressed_sizes: bool,
+}
+
+async fn load_release(path: &Path) -> Result<Release, String> {
+    let is_json = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+    if is_json {
+        let data = tokio::fs::read(path)
+            .await
+            .map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+        let metadata = serde_json::from_slice(&data)
+            .map_err(|e| format!("Failed to parse metadata {}: {e}", path.display()))?;
+        return Ok(Release {
+            metadata,
+            config: None,
+            sizes: HashMap::new(),
+        });
+    }
+    let mmap = AsyncMmapFile::open(path)
+        .await
+        .map_err(|e| format!("Failed to open input file {}: {e}", path.display()))?;
+    let metadata = parse_metadata(&mmap)
+        .await?
+        .ok_or_else(|| format!("No metadata found in {}", path.display()))?;
+    let config = match read_embedded(&mmap, "\0CONFIG").await? {
+        Some(config) => Some(
+            serde_json::from_slice(&config).map_err(|e| format!("Failed to parse config: {e}"))?,
+        ),
+        None => None,
+    };
+    let sizes = collect_file_info(&mmap)
+        .await?
+        .into_iter()
+        .map(|info| (info.hash_name, info.size as u64))
+        .collect();
+    Ok(Release {
+        metadata,
+        config,
+        sizes,
+    })
+}
+
+// 两个哈希都存在时才比较，与 pack 的命名规则一致优先 md5
+fn same_hash(
+    a_md5: Option<&String>,
+    a_xxh: Option<&String>,
+    b_md5: Option<&String>,
+    b_xxh: Option<&String>,
+) -> bool {
+    match (a_md5, b_md5, a_xxh, b_xxh) {
+        (Some(a), Some(b), _, _) => a == b,
+        (_, _, Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn pack_name(md5: Option<&String>, xxh: Option<&String>) -> String {
+    md5.or(xxh).cloned().unwrap_or_default()
+}
+
+fn patch_name(patch: &PatchInfo) -> String {
+    format!(
+        "{}_{}",
+        pack_name(patch.from.md5.as_ref(), patch.from.xxh.as_ref()),
+        pack_name(patch.to.md5.as_ref(), patch.to.xxh.as_ref())
+    )
+}
+
+fn file_download(release: &Release, file: &Metadata) -> u64 {
+    let name = pack_name(file.md5.as_ref(), file.xxh.as_ref());
+    release.sizes.get(&name).copied().unwrap_or(file.size)
+}
+
+fn patch_download(release: &Release, patch: &PatchInfo) -> u64 {
+    release
+        .sizes
+        .get(&patch_name(patch))
+        .copied()
+        .unwrap_or(patch.size)
+}
+
+// 展开为 a.b.c 形式的键，数组整体比较
+fn flatten_config(value: &Value, prefix: &str, out: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{prefix}.{key}")
+                };
+                flatten_config(value, &key, out);
+            }
+        }
+        _ => {
+            out.insert(prefix.to_string(), value.clone());
+        }
+    }
+}
+
+fn diff_config(old: Option<&Value>, new: Option<&Value>) -> Vec<ConfigDiff> {
+    let (Some(old), Some(new)) = (old, new) else {
+        return vec![];
+    };
+    let mut old_keys = BTreeMap::new();
+    let mut new_keys = BTreeMap::new();
+    flatten_config(old, "", &mut old_keys);
+    flatten_config(new, "", &mut new_keys);
+    let mut keys: Vec<&String> = old_keys.keys().chain(new_keys.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .filter(|key| old_keys.get(*key) != new_keys.get(*key))
+        .map(|key| ConfigDiff {
+            key: key.clone(),
+            old: old_keys.get(key).cloned(),
+            new: new_keys.get(key).cloned(),
+        })
+        .collect()
+}
+
+fn diff(old: &Release, new: &Release) -> DiffReport {
+    let old_files: HashMap<&String, &Metadata> = old
+        .metadata
+        .hashed
+        .iter()
+        .flatten()
+        .map(|f| (&f.file_name, f))
+        .collect();
+    let new_patches: Vec<&PatchInfo> = new.metadata.patches.iter().flatten().collect();
+
+    let mut files = vec![];
+    let mut unchanged = 0;
+    let mut full_download = 0;
+    for file in new.metadata.hashed.iter().flatten() {
+        let download = file_download(new, file);
+        full_download += download;
+        let Some(old_file) = old_files.get(&file.file_name) else {
+            files.push(FileDiff {
+                file_name: file.file_name.clone(),
+                change: Change::Added,
+                old_size: None,
+                new_size: Some(file.size),
+                size_delta: file.size as i64,
+                download,
+                patch: None,
+            });
+            continue;
+        };
+        let same = old_file.size == file.size
+            && same_hash(
+                old_file.md5.as_ref(),
+                old_file.xxh.as_ref(),
+                file.md5.as_ref(),
+                file.xxh.as_ref(),
+            );
+        if same {
+            unchanged += 1;
+            continue;
+        }
+        // 有从旧版本到新版本的补丁时只需下载补丁
+        let patch = new_patches.iter().find(|p| {
+            same_hash(
+                p.from.md5.as_ref(),
+                p.from.xxh.as_ref(),
+                old_file.md5.as_ref(),
+                old_file.xxh.as_ref(),
+            ) && same_hash(
+                p.to.md5.as_ref(),
+                p.to.xxh.as_ref(),
+                file.md5.as_ref(),
+                file.xxh.as_ref(),
+            )
+        });
+        files.push(FileDiff {
+            file_name: file.file_name.clone(),
+            change: Change::Changed,
+            old_size: Some(old_file.size),
+            new_size: Some(file.size),
+            size_delta: file.size as i64 - old_file.size as i64,
+            download: patch.map_or(download, |p| patch_download(new, p).min(download)),
+            patch: patch.map(|p| patch_name(p)),
+        });
+    }
+    let new_names: Vec<&String> = new
+        .metadata
+        .hashed
+        .iter()
+        .flatten()
+        .map(|f| &f.file_name)
+        .collect();
+    for file in old.metadata.hashed.iter().flatten() {
+        if !new_names.contains(&&file.file_name) {
+            files.push(FileDiff {
+                file_name: file.file_name.clone(),
+                change: Change::Removed,
+                old_size: Some(file.size),
+                new_size: None,
+                size_delta: -(file.size as i64),
+                download: 0,
+                patch: None,
+            });
+        }
+    }
+    files.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+
+    let patches = new_patches
+        .iter()
+        .map(|p| PatchDiff {
+            file_name: p.file_name.clone(),
+            from: pack_name(p.from.md5.as_ref(), p.from.xxh.as_ref()),
+            to: pack_name(p.to.md5.as_ref(), p.to.xxh.as_ref()),
+            size: patch_download(new, p),
+            applies: old_files.get(&p.file_name).is_some_and(|f| {
+                same_hash(
+                    p.from.md5.as_ref(),
+                    p.from.xxh.as_ref(),
+                    f.md5.as_ref(),
+                    f.xxh.as_ref(),
+                )
+            }),
+        })
+        .collect();
+
+    DiffReport {
+        old: String::new(),
+        new: String::new(),
+        old_tag: old.metadata.tag_name.clone(),
+        new_tag: new.metadata.tag_name.clone(),
+        unchanged,
+        update_download: files.iter().map(|f| f.download).sum(),
+        files,
+        patches,
+        config: diff_config(old.config.as_ref(), new.config.as_ref()),
+        full_download,
+        compressed_sizes: !new.sizes.is_empty(),
+    }
+}
+
+fn print_report(report: &DiffReport) {
+    println!(
+        "{} ({}) -> {} ({})",
+        report.old, report.old_tag, report.new, report.new_tag
+    );
+    println!();
+    println!(
+        "{:<8} {:<40} {:>10} {:>10} {:>11} {:>10} PATCH",
+        "CHANGE", "FILE NAME", "OLD SIZE", "NEW SIZE", "DELTA", "DOWNLOAD"
+    );
+    println!("{}", "-".repeat(100));
+    let size = |size: Option<u64>| size.map_or("-".to_string(), |s| format_file_size(s as usize));
+    for file in report.files.iter() {
+        let delta = format_file_size(file.size_delta.unsigned_abs() as usize);
+        let sign = if file.size_delta < 0 { "-" } else { "+" };
+        println!(
+            "{:<8} {:<40} {:>10} {:>10} {:>11} {:>10} {}",
+            file.change.to_string(),
+            truncate_string(&file.file_name, 40),
+            size(file.old_size),
+            size(file.new_size),
+            format!("{sign}{delta}"),
+            format_file_size(file.download as usize),
+            file.patch
+                .as_deref()
+                .map_or("-".to_string(), |p| truncate_string(p, 20))
+        );
+    }
+    println!("{} unchanged files not listed", report.unchanged);
+
+    if !report.patches.is_empty() {
+        println!();
+        println!(
+            "{:<40} {:<32} {:>10} APPLIES",
+            "PATCH FOR", "FROM HASH", "SIZE"
+        );
+        println!("{}", "-".repeat(100));
+        for patch in report.patches.iter() {
+            println!(
+                "{:<40} {:<32} {:>10} {}",
+                truncate_string(&patch.file_name, 40),
+                truncate_string(&patch.from, 32),
+                format_file_size(patch.size as usize),
+                if patch.applies { "yes" } else { "no" }
+            );
+        }
+    }
+
+    if !report.config.is_empty() {
+        println!();
+        println!("{:<32} {:<32} NEW", "CONFIG KEY", "OLD");
+        println!("{}", "-".repeat(100));
+        let value = |v: &Option<Value>| v.as_ref().map_or("-".to_string(), |v| v.to_string());
+        for config in report.config.iter() {
+            println!(
+                "{:<32} {:<32} {}",
+                truncate_string(&config.key, 32),
+                truncate_string(&value(&config.old), 32),
+                value(&config.new)
+            );
+        }
+    }
+
+    println!();
+    let count = |change| report.files.iter().filter(|f| f.change == change).count();
+    println!(
+        "Added: {}, removed: {}, changed: {}, unchanged: {}",
+        count(Change::Added),
+        count(Change::Removed),
+        count(Change::Changed),
+        report.unchanged
+    );
+    let note = if report.compressed_sizes {
+        ""
+    } else {
+        " (uncompressed, no installer given)"
+    };
+    println!(
+        "Estimated download: update {}, full install {}{note}",
+        format_file_size(report.update_download as usize),
+        format_file_size(report.full_download as usize)
+    );
+}
+
+pub async fn diff_cli(args: DiffArgs) -> bool {
+    let mut releases = vec![];
+    for path in [&args.old, &args.new] {
+        match load_release(path).await {
+            Ok(release) => releases.push(release),
+            Err(e) => {
+                eprintln!("Failed to load {}: {e}", path.display());
+                return false;
+            }
+        }
+    }
+    let (old, new) = (&releases[0], &releases[1]);
+    let mut report = diff(old, new);
+    report.old = args.old.display().to_string();
+    report.new = args.new.display().to_string();
+    print_report(&report);
+    if let Some(json_path) = args.json.as_ref() {
+        let json = serde_json::to_string_pretty(&report).unwrap();
+        if let Err(e) = tokio::fs::write(json_path, json).await {
+            eprintln!("Failed to write report {}: {e}", json_path.display());
+            return false;
+        }
+    }
+    true
+}
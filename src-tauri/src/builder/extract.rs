@@ -10,18 +10,19 @@ use crate::{
 };
 
 #[derive(Debug)]
-struct FileInfo {
-    file_type: FileType,
-    hash_name: String,
-    metadata_name: Option<String>,
-    size: usize,
+pub struct FileInfo {
+    pub file_type: FileType,
+    pub hash_name: String,
+    pub metadata_name: Option<String>,
+    pub size: usize,
 }
 
 #[derive(Debug)]
-enum FileType {
+pub enum FileType {
     Config,
     Image,
     Meta,
+    BuildInfo,
     File,
     Patch,
 }
@@ -32,6 +33,7 @@ impl std::fmt::Display for FileType {
             FileType::Config => write!(f, "CONFIG"),
             FileType::Image => write!(f, "IMAGE"),
             FileType::Meta => write!(f, "META"),
+            FileType::BuildInfo => write!(f, "BUILDINFO"),
             FileType::File => write!(f, "FILE"),
             FileType::Patch => write!(f, "PATCH"),
         }
@@ -75,16 +77,13 @@ fn validate_args(args: &ExtractArgs) -> Result<(), String> {
     Ok(())
 }
 
-// 解析metadata功能
-async fn parse_metadata(file: &AsyncMmapFile) -> Result<Option<RepoMetadata>, String> {
+// 读取内嵌文件内容
+pub async fn read_embedded(file: &AsyncMmapFile, name: &str) -> Result<Option<Vec<u8>>, String> {
     let embedded = get_embedded(file).await.map_err(|e| e.to_string())?;
 
-    // 查找 \0META 文件
-    let meta_file = embedded.iter().find(|e| e.name == "\0META");
-
-    if let Some(meta) = meta_file {
+    if let Some(entry) = embedded.iter().find(|e| e.name == name) {
         let mut data = file
-            .range_reader(meta.offset, meta.size)
+            .range_reader(entry.offset, entry.size)
             .map_err(|e| e.to_string())?;
 
         let mut buffer = Vec::new();
@@ -92,28 +91,39 @@ async fn parse_metadata(file: &AsyncMmapFile) -> Result<Option<RepoMetadata>, St
             .await
             .map_err(|e| e.to_string())?;
 
-        let metadata: RepoMetadata = serde_json::from_slice(&buffer)
-            .map_err(|e| format!("Failed to parse metadata: {}", e))?;
-
-        Ok(Some(metadata))
+        Ok(Some(buffer))
     } else {
         Ok(None)
     }
 }
 
+// 解析metadata功能
+pub async fn parse_metadata(file: &AsyncMmapFile) -> Result<Option<RepoMetadata>, String> {
+    // 查找 \0META 文件
+    match read_embedded(file, "\0META").await? {
+        Some(buffer) => {
+            let metadata: RepoMetadata = serde_json::from_slice(&buffer)
+                .map_err(|e| format!("Failed to parse metadata: {}", e))?;
+            Ok(Some(metadata))
+        }
+        None => Ok(None),
+    }
+}
+
 // 文件类型分类
 fn classify_file_type(name: &str) -> FileType {
     match name {
         "\0CONFIG" => FileType::Config,
         "\0IMAGE" => FileType::Image,
         "\0META" => FileType::Meta,
+        "\0BUILDINFO" => FileType::BuildInfo,
         name if name.contains('_') && !name.starts_with('\0') => FileType::Patch,
         _ => FileType::File,
     }
 }
 
 // 构建hash到文件名的映射
-fn build_hash_to_name_map(metadata: &RepoMetadata) -> HashMap<String, String> {
+pub fn build_hash_to_name_map(metadata: &RepoMetadata) -> HashMap<String, String> {
     let mut map = HashMap::new();
 
     // 处理普通文件
@@ -142,7 +152,7 @@ fn build_hash_to_name_map(metadata: &RepoMetadata) -> HashMap<String, String> {
 }
 
 // 收集文件信息
-async fn collect_file_info(file: &AsyncMmapFile) -> Result<Vec<FileInfo>, String> {
+pub async fn collect_file_info(file: &AsyncMmapFile) -> Result<Vec<FileInfo>, String> {
     let embedded = get_embedded(file).await.map_err(|e| e.to_string())?;
     let metadata = parse_metadata(file).await?;
 
@@ -171,7 +181,7 @@ async fn collect_file_info(file: &AsyncMmapFile) -> Result<Vec<FileInfo>, String
 }
 
 // 文件大小格式化
-fn format_file_size(size: usize) -> String {
+pub fn format_file_size(size: usize) -> String {
     const UNITS: &[&str] = &["B", "KB", "MB", "GB"];
     let mut size = size as f64;
     let mut unit_index = 0;
@@ -189,7 +199,7 @@ fn format_file_size(size: usize) -> String {
 }
 
 // 字符串截断
-fn truncate_string(s: &str, max_len: usize) -> String {
+pub fn truncate_string(s: &str, max_len: usize) -> String {
     if s.len() <= max_len {
         s.to_string()
     } else {
@@ -221,9 +231,28 @@ async fn list_files(file: &AsyncMmapFile) -> Result<(), String> {
         );
     }
 
+    if let Some(build_info) = read_embedded(file, "\0BUILDINFO").await? {
+        println!();
+        println!("Build info: {}", String::from_utf8_lossy(&build_info));
+    }
+
     Ok(())
 }
 
+// 条目以 xxh 或 md5 命名，其他哈希（sha256、blake3）通过metadata换算为条目名
+fn entry_name_by_hash(metadata: &RepoMetadata, hash: &str) -> Option<String> {
+    metadata
+        .hashed
+        .as_ref()?
+        .iter()
+        .find(|file| {
+            [&file.md5, &file.xxh, &file.sha256, &file.blake3]
+                .iter()
+                .any(|h| h.as_deref() == Some(hash))
+        })
+        .and_then(|file| file.xxh.as_ref().or(file.md5.as_ref()).cloned())
+}
+
 // 实现通过hash name提取（原有功能）
 async fn extract_by_hash_name(
     file: &AsyncMmapFile,
@@ -231,10 +260,16 @@ async fn extract_by_hash_name(
     names: &[String],
     output_files: &[std::path::PathBuf],
     input_path: &std::path::Path,
+    metadata: Option<&RepoMetadata>,
 ) -> Result<(), String> {
     for (i, cli_name) in names.iter().enumerate() {
         // 替换 '\0' 为实际的空字节
-        let name = cli_name.replace("\\0", "\0");
+        let mut name = cli_name.replace("\\0", "\0");
+        if !embedded.iter().any(|f| f.name == name) {
+            if let Some(entry_name) = metadata.and_then(|meta| entry_name_by_hash(meta, &name)) {
+                name = entry_name;
+            }
+        }
         let embedded_file = embedded
             .iter()
             .find(|f| f.name == name)
@@ -457,7 +492,17 @@ pub async fn extract_cli(args: ExtractArgs) {
                 return;
             }
         };
-        extract_by_hash_name(&mmap, &embedded, &args.name, &args.file, &args.input).await
+        // 没有metadata时只能按条目名提取
+        let metadata = parse_metadata(&mmap).await.ok().flatten();
+        extract_by_hash_name(
+            &mmap,
+            &embedded,
+            &args.name,
+            &args.file,
+            &args.input,
+            metadata.as_ref(),
+        )
+        .await
     };
 
     if let Err(err) = result {
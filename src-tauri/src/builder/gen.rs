@@ -1,21 +1,59 @@
-use std::{collections::HashSet, path::Path};
+use std::{collections::HashSet, path::Path, sync::Arc};
 
-use async_compression::tokio::bufread::ZstdEncoder;
-use hdiff_sys::safe_create_single_patch;
+use hdiff_sys::{
+    safe_create_single_patch, safe_create_single_patch_stream, stream_match_block_size,
+};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use tokio::{io::AsyncWriteExt, task::JoinSet};
+use twox_hash::XxHash3_128;
 
 use crate::{
+    cache::{artifact_key, Artifact, GenCache},
     cli::GenArgs,
-    metadata::{deep_generate_metadata, deep_get_filelist},
+    compress::{train_dictionary, CompressOptions, CompressRules, DICT_FILE_MAX_SIZE},
+    metadata::deep_generate_metadata,
+    old_version::{OldFile, OldVersion},
     utils::{
         hash::run_hash,
-        metadata::{InstallerInfo, Metadata, PatchInfo, PatchItem, RepoMetadata},
+        metadata::{
+            Codec, DictionaryInfo, DirPatchFile, DirPatchInfo, InstallerInfo, Metadata, PatchInfo,
+            PatchItem, RepoMetadata,
+        },
         progressed_read::ReadWithCallback,
     },
 };
 
+// 超过该大小的文件以流式生成补丁，避免把新旧文件整体读入内存
+const STREAM_DIFF_MIN_SIZE: u64 = 512 * 1024 * 1024;
+// 未指定 --max-diff-memory 时流式生成补丁的内存上限
+const STREAM_DIFF_DEFAULT_MEMORY: u64 = 1024 * 1024 * 1024;
+// 多线程补丁与线程数有关，设置 SOURCE_DATE_EPOCH 时固定线程数，不随 CPU 核数变化
+const REPRODUCIBLE_DIFF_THREADS: usize = 4;
+
 pub async fn gen_cli(args: GenArgs) {
+    let rules = match CompressRules::parse(&args.compress) {
+        Ok(rules) => rules,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    if let Some(algorithm) = args
+        .strong_hash
+        .iter()
+        .find(|algorithm| !matches!(algorithm.as_str(), "sha256" | "blake3"))
+    {
+        eprintln!("Unsupported strong hash: {algorithm}, expected sha256 or blake3");
+        std::process::exit(1);
+    }
+    let cache = match GenCache::open(args.cache.as_deref()).await {
+        Ok(cache) => Arc::new(cache),
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
     let pb_style = ProgressStyle::with_template("[{elapsed_precise}] {bar:20.cyan/blue} {msg} ")
         .unwrap()
         .progress_chars("##-");
@@ -30,23 +68,31 @@ pub async fn gen_cli(args: GenArgs) {
     let mut installer = None;
     if let Some(updater) = args.updater.as_ref() {
         println!("Hashing updater...");
-        let hash = run_hash("xxh", updater.to_str().unwrap())
+        let hash = cache
+            .hash_file(updater)
             .await
             .expect("failed to hash updater");
         let size = tokio::fs::metadata(updater)
             .await
             .expect("failed to get updater size")
             .len();
+        let (sha256, blake3) = strong_hashes(updater, &args.strong_hash, Some(&cache)).await;
         installer = Some(InstallerInfo {
             size,
             md5: None,
             xxh: Some(hash),
+            sha256,
+            blake3,
         });
     }
     println!("Generating metadata...");
-    let mut metadata = deep_generate_metadata(&args.input_dir)
+    let mut metadata = deep_generate_metadata(&args.input_dir, &cache)
         .await
         .expect("failed to generate metadata");
+    if !args.strong_hash.is_empty() {
+        println!("Computing strong hashes...");
+        fill_strong_hashes(&args.input_dir, &mut metadata, &args.strong_hash, &cache).await;
+    }
     if let Some(installer) = installer.as_ref() {
         // remove updater from metadata
         metadata.retain(|x| x.xxh.as_ref().unwrap() != installer.xxh.as_ref().unwrap());
@@ -61,11 +107,35 @@ pub async fn gen_cli(args: GenArgs) {
         installer,
         deletes: None,
         packing_info: None,
+        dictionary: None,
+        dir_patches: None,
     };
     let metadata_str = serde_json::to_string(&repometa).expect("failed to serialize metadata");
     tokio::fs::write(&args.output_metadata, metadata_str)
         .await
         .expect("failed to write metadata");
+    let dictionary = if args.dict {
+        println!("Training dictionary...");
+        let dictionary = generate_dictionary(
+            &args.input_dir,
+            &args.output_dir,
+            &metadata,
+            &rules,
+            args.dict_size,
+        )
+        .await;
+        dictionary.map(|(dictionary, info)| {
+            println!(
+                "Dictionary {} ({} bytes)",
+                info.xxh.as_ref().unwrap(),
+                info.size
+            );
+            repometa.dictionary = Some(info);
+            Arc::new(dictionary)
+        })
+    } else {
+        None
+    };
     println!("Compressing files...");
     let multi_pg = MultiProgress::new();
 
@@ -83,6 +153,11 @@ pub async fn gen_cli(args: GenArgs) {
     let mut set = JoinSet::new();
 
     let mut last_item = false;
+    let mut codecs = vec![];
+    let dict_hash = repometa
+        .dictionary
+        .as_ref()
+        .and_then(|dictionary| dictionary.xxh.clone());
 
     // iterate over our downloads vec and
     // spawn a background task for each download (do_stuff)
@@ -103,7 +178,24 @@ pub async fn gen_cli(args: GenArgs) {
         let file = file.clone();
         let output = args.output_dir.clone();
         let input: std::path::PathBuf = args.input_dir.clone();
-        set.spawn(tokio::task::spawn_blocking(|| {
+        let options = rules.options_for(&file.file_name);
+        let raw_threshold = args.raw_threshold.min(100) as u64;
+        let dictionary = dictionary
+            .clone()
+            .filter(|_| file.size <= DICT_FILE_MAX_SIZE && !options.raw);
+        let cache_key = artifact_key(
+            "file",
+            file.xxh.as_ref().unwrap(),
+            &format!(
+                "{options:?},raw_threshold={raw_threshold},dict={}",
+                dictionary
+                    .as_ref()
+                    .and(dict_hash.as_deref())
+                    .unwrap_or("none")
+            ),
+        );
+        let cache = cache.clone();
+        set.spawn(tokio::task::spawn_blocking(move || {
             // create new tokio runtime for each task
             let rt = tokio::runtime::Builder::new_current_thread()
                 .enable_all()
@@ -121,28 +213,74 @@ pub async fn gen_cli(args: GenArgs) {
                     panic!("file has no hash");
                 };
                 let output_path = output.join(hash);
+                if let Some(artifact) = cache.restore(&cache_key, &output_path).await {
+                    if !console::Term::stdout().is_term() {
+                        println!("Cached {display_name:?}");
+                    }
+                    pb_task.finish_with_message(format!("CACHED {display_name:?}"));
+                    return (index, artifact.codec.unwrap_or(Codec::Zstd));
+                }
                 pb_task.set_message(format!("     {display_name:?}"));
                 let task_ = pb_task.clone();
                 let pb_main_ = pb_main_.clone();
-                let reader = tokio::fs::File::open(file_path).await.unwrap();
-                let reader = ReadWithCallback {
+                let reader = tokio::fs::File::open(&file_path).await.unwrap();
+                let mut reader = ReadWithCallback {
                     reader,
                     callback: move |chunk| {
                         task_.inc(chunk as u64);
                         pb_main_.tick();
                     },
                 };
-                let reader = tokio::io::BufReader::new(reader);
-                let mut encoder = ZstdEncoder::with_quality(reader, async_compression::Level::Best);
-                let mut writer = tokio::fs::File::create(output_path).await.unwrap();
-                tokio::io::copy(&mut encoder, &mut writer)
-                    .await
+                let mut writer = tokio::fs::File::create(&output_path).await.unwrap();
+                let mut codec = Codec::Zstd;
+                if options.raw {
+                    codec = Codec::Raw;
+                    tokio::io::copy(&mut reader, &mut writer)
+                        .await
+                        .expect("failed to copy file");
+                } else {
+                    let reader = tokio::io::BufReader::new(reader);
+                    let compressed = match dictionary {
+                        Some(dictionary) => {
+                            codec = Codec::Dict;
+                            let mut encoder = options
+                                .dict_encoder(reader, &dictionary)
+                                .expect("failed to load dictionary");
+                            tokio::io::copy(&mut encoder, &mut writer).await
+                        }
+                        None => {
+                            let mut encoder = options.encoder(reader);
+                            tokio::io::copy(&mut encoder, &mut writer).await
+                        }
+                    }
                     .expect("failed to compress file");
+                    // 压缩收益低于阈值时改为直接存储，安装时也省去解压
+                    if raw_threshold > 0
+                        && file.size > 0
+                        && compressed * 100 > file.size * (100 - raw_threshold)
+                    {
+                        codec = Codec::Raw;
+                        drop(writer);
+                        tokio::fs::copy(&file_path, &output_path)
+                            .await
+                            .expect("failed to copy file");
+                    }
+                }
                 if !console::Term::stdout().is_term() {
-                    println!("Compressed {display_name:?}");
+                    match codec {
+                        Codec::Raw => println!("Stored {display_name:?}"),
+                        Codec::Zstd | Codec::Dict => println!("Compressed {display_name:?}"),
+                    }
                 }
+                let artifact = Artifact {
+                    codec: Some(codec),
+                    size: file.size,
+                    stored: true,
+                };
+                cache.store(&cache_key, Some(&output_path), artifact).await;
                 pb_task.finish_with_message(format!("DONE {display_name:?}"));
-            });
+                (index, codec)
+            })
         }));
 
         // when limit is reached, wait until a running task finishes
@@ -155,10 +293,12 @@ pub async fn gen_cli(args: GenArgs) {
                         eprintln!("Zstd Task Error: {e:?}");
                         std::process::exit(1);
                     }
-                    let res = res.unwrap();
-                    if let Err(e) = res {
-                        eprintln!("Zstd Task Error: {e:?}");
-                        std::process::exit(1);
+                    match res.unwrap() {
+                        Ok(codec) => codecs.push(codec),
+                        Err(e) => {
+                            eprintln!("Zstd Task Error: {e:?}");
+                            std::process::exit(1);
+                        }
                     }
                 }
                 None => {
@@ -169,28 +309,53 @@ pub async fn gen_cli(args: GenArgs) {
         }
     }
     pb_main.finish_with_message("Compression finished");
+    for (index, codec) in codecs {
+        metadata[index].codec = Some(codec);
+    }
+    let raw_count = metadata
+        .iter()
+        .filter(|file| file.codec == Some(Codec::Raw))
+        .count();
+    println!("Stored {raw_count} files without compression");
+    if let Err(e) = cache.save().await {
+        eprintln!("{e}");
+    }
+    repometa.hashed = Some(metadata.clone());
     // compress and copy installer
     if let Some(installer) = repometa.installer.as_ref() {
         let output_path = args.output_dir.join(installer.xxh.as_ref().unwrap());
-        println!("Compressing installer to {output_path:?}");
-        let reader = tokio::fs::File::open(args.updater.as_ref().unwrap())
-            .await
-            .expect("failed to open installer");
-        let reader = tokio::io::BufReader::new(reader);
-        let mut encoder: ZstdEncoder<tokio::io::BufReader<tokio::fs::File>> =
-            ZstdEncoder::with_quality_and_params(
-                reader,
-                async_compression::Level::Best,
-                &[async_compression::zstd::CParameter::nb_workers(
-                    num_cpus::get() as u32,
-                )],
-            );
-        let mut writer = tokio::fs::File::create(output_path)
-            .await
-            .expect("failed to create file");
-        tokio::io::copy(&mut encoder, &mut writer)
-            .await
-            .expect("failed to compress file");
+        let options = CompressOptions {
+            workers: num_cpus::get().max(1) as u32,
+            ..Default::default()
+        };
+        let cache_key = artifact_key(
+            "installer",
+            installer.xxh.as_ref().unwrap(),
+            &format!("{options:?}"),
+        );
+        if cache.restore(&cache_key, &output_path).await.is_some() {
+            println!("Reused cached installer {output_path:?}");
+        } else {
+            println!("Compressing installer to {output_path:?}");
+            let reader = tokio::fs::File::open(args.updater.as_ref().unwrap())
+                .await
+                .expect("failed to open installer");
+            let reader = tokio::io::BufReader::new(reader);
+            let mut encoder = options.encoder(reader);
+            let mut writer = tokio::fs::File::create(&output_path)
+                .await
+                .expect("failed to create file");
+            tokio::io::copy(&mut encoder, &mut writer)
+                .await
+                .expect("failed to compress file");
+            drop(writer);
+            let artifact = Artifact {
+                codec: None,
+                size: installer.size,
+                stored: true,
+            };
+            cache.store(&cache_key, Some(&output_path), artifact).await;
+        }
     }
     // check diffs
     if let Some(diff_vers) = args.diff_vers {
@@ -207,20 +372,42 @@ pub async fn gen_cli(args: GenArgs) {
                 size: installer.size,
                 md5: installer.md5.clone(),
                 xxh: installer.xxh.clone(),
+                sha256: installer.sha256.clone(),
+                blake3: installer.blake3.clone(),
+                codec: None,
             });
         }
         if !diff_vers.is_empty() {
-            let mut ignore = ignore::gitignore::GitignoreBuilder::new("/");
+            let mut ignore = GitignoreBuilder::new("/");
             if let Some(diff_ignore) = args.diff_ignore {
                 for ignore_file in diff_ignore.iter() {
                     ignore.add_line(None, ignore_file).unwrap();
                 }
             }
             let ignore = ignore.build().unwrap();
+            let mut old_versions = Vec::new();
+            for diff_ver in diff_vers.iter() {
+                let old_version = OldVersion::open(Path::new(diff_ver))
+                    .await
+                    .unwrap_or_else(|e| {
+                        eprintln!("Failed to open {diff_ver}: {e}");
+                        std::process::exit(1);
+                    });
+                old_versions.push(Arc::new(old_version));
+            }
+            // 把 CPU 核数平分给并行的补丁任务
+            let diff_threads = args.diff_threads.unwrap_or_else(|| {
+                if std::env::var_os("SOURCE_DATE_EPOCH").is_some() {
+                    return REPRODUCIBLE_DIFF_THREADS;
+                }
+                let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+                (cpus / args.zstd_concurrency.max(1)).max(1)
+            });
             let mut diffs = Vec::new();
+            let mut dir_patches = Vec::new();
             let mut deletes = Vec::new();
             // loop through diff_versions
-            for diff_ver in diff_vers.iter() {
+            for (diff_ver, old_version) in diff_vers.iter().zip(old_versions.iter()) {
                 // loop through current metadata
                 let multi_pg = MultiProgress::new();
 
@@ -247,11 +434,16 @@ pub async fn gen_cli(args: GenArgs) {
                     let input_dir = args.input_dir.clone();
                     let output_dir = args.output_dir.clone();
                     let diff_ver = diff_ver.clone();
+                    let old_version = old_version.clone();
 
                     // spawns a background task immediatly no matter if the future is awaited
                     // https://docs.rs/tokio/latest/tokio/task/struct.JoinSet.html#method.spawn
                     let file = file.clone();
                     let ignore = ignore.clone();
+                    let cache = cache.clone();
+                    let max_diff_memory = args.max_diff_memory.map(|mib| mib * 1024 * 1024);
+                    let legacy_patch = !args.native_patch;
+                    let strong_hash = args.strong_hash.clone();
                     set.spawn(async move {
                         if ignore
                             .matched_path_or_any_parents(&file.file_name, false)
@@ -265,17 +457,18 @@ pub async fn gen_cli(args: GenArgs) {
                             println!("File {:?} too small, skipped", file.file_name);
                             return None;
                         }
-                        // check if file exists in diff_ver
-                        let diff_file = Path::new(&diff_ver).join(&file.file_name);
-                        if !diff_file.exists() {
+                        // check if file exists in diff_ver, and get its hash
+                        let old_file = old_version
+                            .find(&file.file_name, &cache)
+                            .await
+                            .expect("failed to hash diff file");
+                        let Some(old_file) = old_file else {
                             // file not found in diff_ver, skip
                             println!("File {:?} not found in diff_ver, skipped", file.file_name);
                             return None;
-                        }
-                        // file found, hash it
-                        let old_hash = run_hash("xxh", diff_file.to_str().unwrap())
-                            .await
-                            .expect("failed to hash diff file");
+                        };
+                        let old_hash = old_file.xxh.clone();
+                        let diff_file = format!("{diff_ver}/{}", file.file_name);
                         if old_hash == *file.xxh.as_ref().unwrap() {
                             // hash same, skip
                             println!("File {:?} hash same, skipped", file.file_name);
@@ -289,77 +482,195 @@ pub async fn gen_cli(args: GenArgs) {
                         ));
                         let compressed_path =
                             output_dir.join(format!("{}_{}", old_hash, file.xxh.as_ref().unwrap()));
-                        println!("Generating diff for {diff_file:?} to {output_path:?}");
-                        // read old_data and new_data to memory
-                        let old_data = tokio::fs::read(&diff_file)
-                            .await
-                            .expect("failed to read old data");
-                        let new_data = tokio::fs::read(input_dir.join(&file.file_name))
-                            .await
-                            .expect("failed to read new data");
-                        let output_file = std::fs::File::create(&output_path)
-                            .expect("failed to create output file");
-                        tokio::task::spawn_blocking(move || {
-                            // create output file
-                            safe_create_single_patch(&new_data, &old_data, output_file, 7)
-                        })
-                        .await
-                        .expect("failed to create diff")
-                        .expect("failed to create diff");
-                        // compress diff file
-                        let reader = tokio::fs::File::open(&output_path)
-                            .await
-                            .expect("failed to open diff file");
-                        let reader = tokio::io::BufReader::new(reader);
-                        let mut encoder =
-                            ZstdEncoder::with_quality(reader, async_compression::Level::Best);
-                        let mut writer = tokio::fs::File::create(&compressed_path)
-                            .await
-                            .expect("failed to create compressed diff file");
-                        tokio::io::copy(&mut encoder, &mut writer)
-                            .await
-                            .expect("failed to compress diff");
-                        // flush writer
-                        writer.flush().await.expect("failed to flush writer");
-                        // close file
-                        drop(writer);
-                        let diff_original_size = tokio::fs::metadata(&output_path)
-                            .await
-                            .expect("failed to get diff size")
-                            .len();
-                        // delete uncompressed diff
-                        tokio::fs::remove_file(&output_path)
-                            .await
-                            .expect("failed to remove uncompressed diff");
-                        // if diff size is 50%+ of new file size, delete diff and skip
-                        let diff_size = tokio::fs::metadata(&compressed_path)
-                            .await
-                            .expect("failed to get diff size")
-                            .len();
-                        let old_size = tokio::fs::metadata(&diff_file)
+                        let options = CompressOptions::default();
+                        // 大文件整体读入内存会占用过多内存，改为按内存上限流式生成
+                        let match_block_size = (max_diff_memory.is_some()
+                            || file.size.max(old_file.size) > STREAM_DIFF_MIN_SIZE)
+                            .then(|| {
+                                stream_match_block_size(
+                                    old_file.size,
+                                    max_diff_memory.unwrap_or(STREAM_DIFF_DEFAULT_MEMORY),
+                                )
+                            });
+                        // 多线程生成的补丁与线程数有关
+                        let diff_settings = match match_block_size {
+                            Some(block_size) => {
+                                format!("hdiff=stream,block={block_size},threads={diff_threads}")
+                            }
+                            None if diff_threads > 1 => format!("hdiff=7,threads={diff_threads}"),
+                            None => "hdiff=7".to_string(),
+                        };
+                        // 原生格式由 hdiff 直接写出 zstd 压缩的补丁，旧格式先生成补丁再整体压缩
+                        let zstd_level = (!legacy_patch).then_some(options.level);
+                        let compress_settings = match zstd_level {
+                            Some(level) => format!("native,level={level}"),
+                            None => format!("{options:?}"),
+                        };
+                        let cache_key = artifact_key(
+                            "patch",
+                            &format!("{}_{}", old_hash, file.xxh.as_ref().unwrap()),
+                            &format!("{diff_settings},{compress_settings}"),
+                        );
+                        let diff_original_size = match cache
+                            .restore(&cache_key, &compressed_path)
                             .await
-                            .expect("failed to get old size")
-                            .len();
-                        if diff_size > (file.size / 2) {
-                            tokio::fs::remove_file(&compressed_path)
-                                .await
-                                .expect("failed to remove diff");
-                            println!("File {:?} diff too large, skipped", file.file_name);
-                            return None;
-                        }
+                        {
+                            Some(artifact) if !artifact.stored => {
+                                println!("File {:?} diff too large, skipped", file.file_name);
+                                return None;
+                            }
+                            Some(artifact) => {
+                                println!("Reused cached diff for {diff_file:?}");
+                                artifact.size
+                            }
+                            None => {
+                                let diff_path = if legacy_patch {
+                                    &output_path
+                                } else {
+                                    &compressed_path
+                                };
+                                println!("Generating diff for {diff_file:?} to {diff_path:?}");
+                                let output_file = std::fs::File::create(diff_path)
+                                    .expect("failed to create output file");
+                                if let Some(block_size) = match_block_size {
+                                    // 打包的旧文件需要先解压到磁盘才能随机读取
+                                    let temp_path = output_path.with_extension("old");
+                                    let old_path = old_version
+                                        .extract(&file.file_name, &temp_path)
+                                        .await
+                                        .expect("failed to extract old data");
+                                    let new_path = input_dir.join(&file.file_name);
+                                    let diff_old_path = old_path.clone();
+                                    tokio::task::spawn_blocking(move || {
+                                        let new_file = std::fs::File::open(new_path)
+                                            .expect("failed to open new data");
+                                        let old_file = std::fs::File::open(diff_old_path)
+                                            .expect("failed to open old data");
+                                        safe_create_single_patch_stream(
+                                            std::io::BufReader::new(new_file),
+                                            std::io::BufReader::new(old_file),
+                                            output_file,
+                                            block_size,
+                                            diff_threads,
+                                            zstd_level,
+                                        )
+                                    })
+                                    .await
+                                    .expect("failed to create diff")
+                                    .expect("failed to create diff");
+                                    if old_path == temp_path {
+                                        tokio::fs::remove_file(&temp_path)
+                                            .await
+                                            .expect("failed to remove extracted old data");
+                                    }
+                                } else {
+                                    // read old_data and new_data to memory
+                                    let old_data = old_version
+                                        .read(&file.file_name)
+                                        .await
+                                        .expect("failed to read old data");
+                                    let new_data = tokio::fs::read(input_dir.join(&file.file_name))
+                                        .await
+                                        .expect("failed to read new data");
+                                    tokio::task::spawn_blocking(move || {
+                                        safe_create_single_patch(
+                                            &new_data,
+                                            &old_data,
+                                            output_file,
+                                            7,
+                                            diff_threads,
+                                            zstd_level,
+                                        )
+                                    })
+                                    .await
+                                    .expect("failed to create diff")
+                                    .expect("failed to create diff");
+                                }
+                                let diff_original_size = if legacy_patch {
+                                    // compress diff file
+                                    let reader = tokio::fs::File::open(&output_path)
+                                        .await
+                                        .expect("failed to open diff file");
+                                    let reader = tokio::io::BufReader::new(reader);
+                                    let mut encoder = options.encoder(reader);
+                                    let mut writer = tokio::fs::File::create(&compressed_path)
+                                        .await
+                                        .expect("failed to create compressed diff file");
+                                    tokio::io::copy(&mut encoder, &mut writer)
+                                        .await
+                                        .expect("failed to compress diff");
+                                    // flush writer
+                                    writer.flush().await.expect("failed to flush writer");
+                                    // close file
+                                    drop(writer);
+                                    let diff_original_size = tokio::fs::metadata(&output_path)
+                                        .await
+                                        .expect("failed to get diff size")
+                                        .len();
+                                    // delete uncompressed diff
+                                    tokio::fs::remove_file(&output_path)
+                                        .await
+                                        .expect("failed to remove uncompressed diff");
+                                    diff_original_size
+                                } else {
+                                    tokio::fs::metadata(&compressed_path)
+                                        .await
+                                        .expect("failed to get diff size")
+                                        .len()
+                                };
+                                // if diff size is 50%+ of new file size, delete diff and skip
+                                let diff_size = tokio::fs::metadata(&compressed_path)
+                                    .await
+                                    .expect("failed to get diff size")
+                                    .len();
+                                let stored = diff_size <= (file.size / 2);
+                                let artifact = Artifact {
+                                    codec: None,
+                                    size: diff_original_size,
+                                    stored,
+                                };
+                                if !stored {
+                                    cache.store(&cache_key, None, artifact).await;
+                                    tokio::fs::remove_file(&compressed_path)
+                                        .await
+                                        .expect("failed to remove diff");
+                                    println!("File {:?} diff too large, skipped", file.file_name);
+                                    return None;
+                                }
+                                cache
+                                    .store(&cache_key, Some(&compressed_path), artifact)
+                                    .await;
+                                diff_original_size
+                            }
+                        };
+                        let old_size = old_file.size;
+                        let (old_sha256, old_blake3) = old_strong_hashes(
+                            &old_version,
+                            &old_file,
+                            &file.file_name,
+                            &output_path.with_extension("old"),
+                            &strong_hash,
+                        )
+                        .await;
                         Some(PatchInfo {
                             file_name: file.file_name.clone(),
                             size: diff_original_size,
                             from: PatchItem {
                                 md5: None,
                                 xxh: Some(old_hash.clone()),
+                                sha256: old_sha256,
+                                blake3: old_blake3,
                                 size: old_size,
                             },
                             to: PatchItem {
                                 md5: None,
                                 xxh: Some(file.xxh.clone().unwrap()),
+                                sha256: file.sha256.clone(),
+                                blake3: file.blake3.clone(),
                                 size: file.size,
                             },
+                            age: None,
+                            codec: (!legacy_patch).then_some(Codec::Raw),
                         })
                     });
                     while set.len() >= args.zstd_concurrency || last_item {
@@ -381,7 +692,24 @@ pub async fn gen_cli(args: GenArgs) {
                         pb_main.inc(1);
                     }
                 }
-                let diff_filelist = deep_get_filelist(&diff_ver.into())
+                for glob in args.dir_patch.iter() {
+                    println!("Generating dir patch for {glob:?} from {diff_ver}...");
+                    let dir_patch = generate_dir_patch(
+                        glob,
+                        &metadata,
+                        &ignore,
+                        old_version,
+                        &args.input_dir,
+                        &args.output_dir,
+                        &cache,
+                        diff_threads,
+                        &args.strong_hash,
+                    )
+                    .await;
+                    dir_patches.extend(dir_patch);
+                }
+                let diff_filelist = old_version
+                    .file_list()
                     .await
                     .expect("failed to get diff_ver file list");
                 println!("Checking for deleted files in {diff_ver}...");
@@ -394,14 +722,40 @@ pub async fn gen_cli(args: GenArgs) {
                     }
                 }
             }
+            if args.patch_history > 0 {
+                println!("Carrying patches from old versions...");
+                let carried = carry_patches(
+                    &old_versions,
+                    &diffs,
+                    &metadata_with_installer,
+                    &args.output_dir,
+                    args.patch_history,
+                )
+                .await;
+                println!("Carried {} patches", carried.len());
+                diffs.extend(carried);
+            }
+            // 补丁按完成顺序收集，排序后输出才稳定
+            diffs.sort_by(|a, b| {
+                (&a.file_name, &a.from.xxh, &a.to.xxh).cmp(&(&b.file_name, &b.from.xxh, &b.to.xxh))
+            });
+            // 不同旧版本中成员文件都相同时得到同名的目录补丁
+            dir_patches.sort_by(|a, b| a.name.cmp(&b.name));
+            dir_patches.dedup_by(|a, b| a.name == b.name);
+            deletes.sort();
+            deletes.dedup();
             repometa.deletes = Some(deletes);
             // 生成打包优化信息（在移动 diffs 之前）
-            let diff_vers_pathbuf: Vec<std::path::PathBuf> =
-                diff_vers.iter().map(std::path::PathBuf::from).collect();
-            let packing_info =
-                generate_packing_info(&metadata_with_installer, &diffs, &diff_vers_pathbuf).await;
+            let mut packing_info =
+                generate_packing_info(&metadata_with_installer, &diffs, &old_versions, &cache)
+                    .await;
+            // 目录补丁替代的是有变化的小文件，与小 patch 放在一起
+            packing_info[3].extend(dir_patches.iter().map(|patch| patch.name.clone()));
 
             repometa.patches = Some(diffs);
+            if !dir_patches.is_empty() {
+                repometa.dir_patches = Some(dir_patches);
+            }
             repometa.packing_info = Some(packing_info);
 
             // write metadata again
@@ -427,13 +781,21 @@ pub async fn gen_cli(args: GenArgs) {
                 size: installer.size,
                 md5: installer.md5.clone(),
                 xxh: installer.xxh.clone(),
+                sha256: installer.sha256.clone(),
+                blake3: installer.blake3.clone(),
+                codec: None,
             });
         }
 
         let empty_diffs = Vec::new();
-        let empty_diff_vers = Vec::new();
-        let packing_info =
-            generate_packing_info(&metadata_with_installer, &empty_diffs, &empty_diff_vers).await;
+        let empty_old_versions = Vec::new();
+        let packing_info = generate_packing_info(
+            &metadata_with_installer,
+            &empty_diffs,
+            &empty_old_versions,
+            &cache,
+        )
+        .await;
         repometa.packing_info = Some(packing_info);
 
         // write metadata again
@@ -442,13 +804,392 @@ pub async fn gen_cli(args: GenArgs) {
             .await
             .expect("failed to write metadata");
     }
+    if let Err(e) = cache.save().await {
+        eprintln!("{e}");
+    }
     println!("Done");
 }
 
+// 计算 --strong-hash 指定的哈希，返回 (sha256, blake3)
+async fn strong_hashes(
+    path: &Path,
+    algorithms: &[String],
+    cache: Option<&GenCache>,
+) -> (Option<String>, Option<String>) {
+    let mut sha256 = None;
+    let mut blake3 = None;
+    for algorithm in algorithms {
+        let hash = match cache {
+            Some(cache) => cache.hash_file_with(path, algorithm).await,
+            None => run_hash(algorithm, path.to_str().unwrap()).await,
+        }
+        .expect("failed to compute strong hash");
+        if algorithm == "sha256" {
+            sha256 = Some(hash);
+        } else {
+            blake3 = Some(hash);
+        }
+    }
+    (sha256, blake3)
+}
+
+async fn fill_strong_hashes(
+    input_dir: &Path,
+    metadata: &mut [Metadata],
+    algorithms: &[String],
+    cache: &Arc<GenCache>,
+) {
+    let mut set = JoinSet::new();
+    for (index, file) in metadata.iter().enumerate() {
+        let path = input_dir.join(&file.file_name);
+        let algorithms = algorithms.to_vec();
+        let cache = cache.clone();
+        set.spawn(async move { (index, strong_hashes(&path, &algorithms, Some(&cache)).await) });
+    }
+    while let Some(res) = set.join_next().await {
+        let (index, (sha256, blake3)) = res.expect("failed to run hashing thread");
+        metadata[index].sha256 = sha256;
+        metadata[index].blake3 = blake3;
+    }
+}
+
+// 旧文件的强哈希优先取自旧版本 metadata，缺少时解压到 temp 后计算
+async fn old_strong_hashes(
+    old_version: &OldVersion,
+    old_file: &OldFile,
+    file_name: &str,
+    temp: &Path,
+    algorithms: &[String],
+) -> (Option<String>, Option<String>) {
+    if algorithms.is_empty() {
+        return (None, None);
+    }
+    let recorded = algorithms.iter().all(|algorithm| match algorithm.as_str() {
+        "sha256" => old_file.sha256.is_some(),
+        _ => old_file.blake3.is_some(),
+    });
+    if recorded {
+        return (old_file.sha256.clone(), old_file.blake3.clone());
+    }
+    let path = old_version
+        .extract(file_name, temp)
+        .await
+        .expect("failed to extract old data");
+    // 解压出的临时文件用完即删，不写入缓存
+    let hashes = strong_hashes(&path, algorithms, None).await;
+    if path == temp {
+        tokio::fs::remove_file(temp)
+            .await
+            .expect("failed to remove extracted old data");
+    }
+    hashes
+}
+
+// 把匹配 glob 的有变化小文件合成一个目录补丁：旧内容与新内容分别按文件名顺序拼接后生成单个补丁。
+// 超过 1 MiB 的文件有单独的补丁，不放入目录补丁
+#[allow(clippy::too_many_arguments)]
+async fn generate_dir_patch(
+    glob: &str,
+    metadata: &[Metadata],
+    ignore: &Gitignore,
+    old_version: &OldVersion,
+    input_dir: &Path,
+    output_dir: &Path,
+    cache: &GenCache,
+    diff_threads: usize,
+    strong_hash: &[String],
+) -> Option<DirPatchInfo> {
+    let mut matcher = GitignoreBuilder::new("/");
+    matcher
+        .add_line(None, glob)
+        .expect("invalid dir patch glob");
+    let matcher = matcher.build().expect("invalid dir patch glob");
+    let mut files = Vec::new();
+    for file in metadata {
+        if file.size >= 1024 * 1024
+            || !matcher
+                .matched_path_or_any_parents(&file.file_name, false)
+                .is_ignore()
+            || ignore
+                .matched_path_or_any_parents(&file.file_name, false)
+                .is_ignore()
+        {
+            continue;
+        }
+        let old_file = old_version
+            .find(&file.file_name, cache)
+            .await
+            .expect("failed to hash diff file");
+        let Some(old_file) = old_file else {
+            continue;
+        };
+        if old_file.xxh == *file.xxh.as_ref().unwrap() {
+            continue;
+        }
+        let temp_path = output_dir.join(format!("{}.old", old_file.xxh));
+        let (old_sha256, old_blake3) = old_strong_hashes(
+            old_version,
+            &old_file,
+            &file.file_name,
+            &temp_path,
+            strong_hash,
+        )
+        .await;
+        files.push(DirPatchFile {
+            file_name: file.file_name.clone(),
+            from: PatchItem {
+                size: old_file.size,
+                md5: None,
+                xxh: Some(old_file.xxh),
+                sha256: old_sha256,
+                blake3: old_blake3,
+            },
+            to: PatchItem {
+                size: file.size,
+                md5: None,
+                xxh: file.xxh.clone(),
+                sha256: file.sha256.clone(),
+                blake3: file.blake3.clone(),
+            },
+        });
+    }
+    if files.is_empty() {
+        println!("No changed files for dir patch {glob:?}, skipped");
+        return None;
+    }
+    files.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+    // 以成员文件及其新旧哈希命名，相同的成员总是得到相同的补丁
+    let mut hasher = XxHash3_128::new();
+    for file in files.iter() {
+        for part in [
+            file.file_name.as_str(),
+            file.from.xxh.as_deref().unwrap(),
+            file.to.xxh.as_deref().unwrap(),
+        ] {
+            hasher.write(part.as_bytes());
+            hasher.write(&[0]);
+        }
+    }
+    let name = format!("dir_{:x}", hasher.finish_128());
+    let output_path = output_dir.join(&name);
+    let level = CompressOptions::default().level;
+    let diff_settings = if diff_threads > 1 {
+        format!("hdiff=7,threads={diff_threads}")
+    } else {
+        "hdiff=7".to_string()
+    };
+    let cache_key = artifact_key(
+        "dirpatch",
+        &name,
+        &format!("{diff_settings},native,level={level}"),
+    );
+    let size = match cache.restore(&cache_key, &output_path).await {
+        Some(artifact) if !artifact.stored => {
+            println!("Dir patch {glob:?} too large, skipped");
+            return None;
+        }
+        Some(artifact) => {
+            println!("Reused cached dir patch {name}");
+            artifact.size
+        }
+        None => {
+            let mut old_data = Vec::new();
+            let mut new_data = Vec::new();
+            for file in files.iter() {
+                old_data.extend(
+                    old_version
+                        .read(&file.file_name)
+                        .await
+                        .expect("failed to read old data"),
+                );
+                new_data.extend(
+                    tokio::fs::read(input_dir.join(&file.file_name))
+                        .await
+                        .expect("failed to read new data"),
+                );
+            }
+            let output_file =
+                std::fs::File::create(&output_path).expect("failed to create output file");
+            tokio::task::spawn_blocking(move || {
+                safe_create_single_patch(
+                    &new_data,
+                    &old_data,
+                    output_file,
+                    7,
+                    diff_threads,
+                    Some(level),
+                )
+            })
+            .await
+            .expect("failed to create dir patch")
+            .expect("failed to create dir patch");
+            let size = tokio::fs::metadata(&output_path)
+                .await
+                .expect("failed to get dir patch size")
+                .len();
+            // 与单文件补丁相同，超过新文件总大小一半时不如直接下载
+            let stored = size <= files.iter().map(|file| file.to.size).sum::<u64>() / 2;
+            let artifact = Artifact {
+                codec: None,
+                size,
+                stored,
+            };
+            if !stored {
+                cache.store(&cache_key, None, artifact).await;
+                tokio::fs::remove_file(&output_path)
+                    .await
+                    .expect("failed to remove dir patch");
+                println!("Dir patch {glob:?} too large, skipped");
+                return None;
+            }
+            cache.store(&cache_key, Some(&output_path), artifact).await;
+            size
+        }
+    };
+    println!(
+        "Dir patch {name} for {glob:?}: {} files, {size} bytes",
+        files.len()
+    );
+    Some(DirPatchInfo {
+        name,
+        size,
+        codec: Some(Codec::Raw),
+        files,
+    })
+}
+
+// 带入旧版本 metadata 中仍在保留期内的补丁，只保留最终能更新到当前版本的。
+// 补丁的 age 以带入它的旧版本为准，多个旧版本都有时取较大的值
+async fn carry_patches(
+    old_versions: &[Arc<OldVersion>],
+    diffs: &[PatchInfo],
+    current: &[Metadata],
+    output_dir: &Path,
+    patch_history: u32,
+) -> Vec<PatchInfo> {
+    let patch_name = |patch: &PatchInfo| {
+        format!(
+            "{}_{}",
+            patch.from.xxh.as_ref().unwrap(),
+            patch.to.xxh.as_ref().unwrap()
+        )
+    };
+    let generated: HashSet<String> = diffs.iter().map(patch_name).collect();
+    let mut candidates: Vec<(PatchInfo, &Arc<OldVersion>)> = vec![];
+    for old_version in old_versions.iter() {
+        for patch in old_version.patches() {
+            if patch.from.xxh.is_none() || patch.to.xxh.is_none() {
+                continue;
+            }
+            let age = patch.age.unwrap_or(0) + 1;
+            let name = patch_name(patch);
+            if age > patch_history || generated.contains(&name) {
+                continue;
+            }
+            match candidates.iter_mut().find(|(p, _)| patch_name(p) == name) {
+                Some((existing, _)) => existing.age = existing.age.max(Some(age)),
+                None => {
+                    let mut patch = patch.clone();
+                    patch.age = Some(age);
+                    candidates.push((patch, old_version));
+                }
+            }
+        }
+    }
+    // 从当前版本反向查找，能到达当前文件哈希的补丁才有用
+    let mut targets: HashSet<(String, String)> = current
+        .iter()
+        .map(|file| (file.file_name.clone(), file.xxh.clone().unwrap()))
+        .chain(
+            diffs
+                .iter()
+                .map(|patch| (patch.file_name.clone(), patch.from.xxh.clone().unwrap())),
+        )
+        .collect();
+    let mut useful = vec![false; candidates.len()];
+    loop {
+        let mut changed = false;
+        for (index, (patch, _)) in candidates.iter().enumerate() {
+            if useful[index] {
+                continue;
+            }
+            let to = (patch.file_name.clone(), patch.to.xxh.clone().unwrap());
+            if targets.contains(&to) {
+                useful[index] = true;
+                targets.insert((patch.file_name.clone(), patch.from.xxh.clone().unwrap()));
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    let mut carried = vec![];
+    for ((patch, old_version), useful) in candidates.into_iter().zip(useful) {
+        if !useful {
+            continue;
+        }
+        let name = patch_name(&patch);
+        if let Err(e) = old_version.copy_entry(&name, &output_dir.join(&name)).await {
+            println!("Patch {name} for {:?} skipped: {e}", patch.file_name);
+            continue;
+        }
+        carried.push(patch);
+    }
+    carried
+}
+
+// 用小文件训练字典，以哈希命名写入输出目录；样本不足时不使用字典
+async fn generate_dictionary(
+    input_dir: &Path,
+    output_dir: &Path,
+    metadata: &[Metadata],
+    rules: &CompressRules,
+    dict_size: usize,
+) -> Option<(Vec<u8>, DictionaryInfo)> {
+    let mut samples = vec![];
+    for file in metadata.iter() {
+        if file.size > DICT_FILE_MAX_SIZE || rules.options_for(&file.file_name).raw {
+            continue;
+        }
+        let data = tokio::fs::read(input_dir.join(&file.file_name))
+            .await
+            .expect("failed to read dictionary sample");
+        samples.push(data);
+    }
+    let dictionary = tokio::task::spawn_blocking(move || train_dictionary(&samples, dict_size))
+        .await
+        .expect("failed to train dictionary");
+    let dictionary = match dictionary {
+        Ok(dictionary) => dictionary,
+        Err(e) => {
+            eprintln!("Dictionary skipped: {e}");
+            return None;
+        }
+    };
+    let tmp_path = output_dir.join("dictionary.tmp");
+    tokio::fs::write(&tmp_path, &dictionary)
+        .await
+        .expect("failed to write dictionary");
+    let hash = run_hash("xxh", tmp_path.to_str().unwrap())
+        .await
+        .expect("failed to hash dictionary");
+    tokio::fs::rename(&tmp_path, output_dir.join(&hash))
+        .await
+        .expect("failed to write dictionary");
+    let info = DictionaryInfo {
+        size: dictionary.len() as u64,
+        md5: None,
+        xxh: Some(hash),
+    };
+    Some((dictionary, info))
+}
+
 async fn generate_packing_info(
     metadata_with_installer: &[Metadata],
     patches: &[PatchInfo],
-    diff_vers: &[std::path::PathBuf],
+    old_versions: &[Arc<OldVersion>],
+    cache: &GenCache,
 ) -> Vec<Vec<String>> {
     let mut packing_info = vec![
         Vec::new(), // [0] 大文件
@@ -462,7 +1203,7 @@ async fn generate_packing_info(
     let mut changed_hashes = HashSet::new();
     let mut new_hashes = HashSet::new();
 
-    if !diff_vers.is_empty() {
+    if !old_versions.is_empty() {
         // 分析每个文件的变化状态
         for file in metadata_with_installer {
             let hash = file.xxh.as_ref().unwrap();
@@ -471,17 +1212,20 @@ async fn generate_packing_info(
             let mut found_in_old = false;
             let mut hash_changed = false;
 
-            for diff_ver in diff_vers {
-                let old_file_path = diff_ver.join(&file.file_name);
-                if old_file_path.exists() {
-                    found_in_old = true;
-                    let old_hash_result = run_hash("xxh", old_file_path.to_str().unwrap()).await;
-                    if let Ok(old_hash) = old_hash_result {
-                        if old_hash != *hash {
+            for old_version in old_versions {
+                match old_version.find(&file.file_name, cache).await {
+                    Ok(Some(old_file)) => {
+                        found_in_old = true;
+                        if old_file.xxh != *hash {
                             hash_changed = true;
                         }
+                        break;
+                    }
+                    Ok(None) => {}
+                    Err(_) => {
+                        found_in_old = true;
+                        break;
                     }
-                    break;
                 }
             }
 
@@ -0,0 +1,37 @@
+use std::path::Path;
+
+use kachina_pack::{parse_signing_key, SigningKey};
+
+use crate::cli::KeygenArgs;
+
+pub async fn keygen_cli(args: KeygenArgs) -> bool {
+    if args.output.exists() {
+        eprintln!("Refusing to overwrite existing key: {:?}", args.output);
+        return false;
+    }
+    let mut seed = [0u8; 32];
+    if let Err(e) = getrandom::getrandom(&mut seed) {
+        eprintln!("Failed to generate key: {e}");
+        return false;
+    }
+    let key = SigningKey::from_bytes(&seed);
+    if let Err(e) = tokio::fs::write(&args.output, hex::encode(key.to_bytes())).await {
+        eprintln!("Failed to write key {:?} : {:?}", args.output, e);
+        return false;
+    }
+    println!("Private key written to {:?}, keep it secret", args.output);
+    println!(
+        "Public key, add it to \"trustedKeys\" in the config: {}",
+        hex::encode(key.verifying_key().as_bytes())
+    );
+    true
+}
+
+// 读取 keygen 生成的私钥文件
+pub async fn read_sign_key(path: &Path) -> Result<SigningKey, String> {
+    let key = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| format!("Failed to read sign key {:?} : {:?}", path, e))?;
+    parse_signing_key(&key)
+        .ok_or_else(|| "Invalid sign key, expected 64 hex characters".to_string())
+}
@@ -1,6 +1,6 @@
-use anyhow::Context;
 use fmmap::tokio::{AsyncMmapFile, AsyncMmapFileExt, AsyncMmapFileReader};
-use serde::{Deserialize, Serialize};
+use kachina_pack::PackReader;
+use std::io::Cursor;
 use tokio::io::AsyncReadExt;
 use tokio::sync::OnceCell;
 static MMAP_SELF: OnceCell<AsyncMmapFile> = OnceCell::const_new();
@@ -51,86 +51,11 @@ pub async fn mmap() -> &'static AsyncMmapFile {
         .await
 }
 
-async fn search_pattern_for_extract(file: &AsyncMmapFile) -> anyhow::Result<Vec<usize>> {
-    let pattern = "!in\0".to_ascii_uppercase();
-    let pattern: &[u8; 4] = pattern.as_bytes().try_into().unwrap();
-    let mut reader = file.reader(0).context("MMAP_ERR")?;
-    let mut buffer = [0u8; 4096];
-    let mut offset: usize = 0;
-    let mut founds = Vec::new();
-    let mut read = 0;
+pub use kachina_pack::Embedded;
 
-    loop {
-        // move last 4 bytes to the beginning of the buffer
-        if read > 4 {
-            buffer[0] = buffer[read + 4 - 4];
-            buffer[1] = buffer[read + 4 - 3];
-            buffer[2] = buffer[read + 4 - 2];
-            buffer[3] = buffer[read + 4 - 1];
-        }
-        read = reader.read(&mut buffer[4..]).await.context("MMAP_ERR")?;
-        if read == 0 {
-            break;
-        }
-        for i in 0..read + 4 - 1 {
-            if buffer[i] == pattern[0] {
-                if i + 3 > read + 3 {
-                    continue;
-                }
-                if buffer[i + 1] == pattern[1]
-                    && buffer[i + 2] == pattern[2]
-                    && buffer[i + 3] == pattern[3]
-                {
-                    founds.push(offset + i - 4);
-                }
-            }
-        }
-        offset += read;
-    }
-
-    Ok(founds)
-}
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Embedded {
-    pub name: String,
-    pub offset: usize,
-    pub raw_offset: usize,
-    pub size: usize,
-}
 pub async fn get_embedded(file: &AsyncMmapFile) -> anyhow::Result<Vec<Embedded>> {
-    let offsets = search_pattern_for_extract(file).await?;
-    let mut entries = Vec::new();
-    let mut last_offset: usize = 0;
-    for offset in offsets.iter() {
-        if *offset < last_offset {
-            // in case of content includes header
-            continue;
-        }
-        // TLV
-        // header: !IN\0
-        // name length: 2 bytes big endian
-        // name: variable length
-        // content length: 4 bytes big endian
-        // content: variable length
-        let mem_pos_name_length = *offset + 4;
-        let mem_pos_name = mem_pos_name_length + 2;
-        let name_length =
-            u16::from_be_bytes(file.slice(mem_pos_name_length, 2).try_into().unwrap()) as usize;
-        let name = file.slice(mem_pos_name, name_length);
-        let name = String::from_utf8_lossy(name).to_string();
-        let mem_pos_content_length = mem_pos_name + name_length;
-        let content_length =
-            u32::from_be_bytes(file.slice(mem_pos_content_length, 4).try_into().unwrap()) as usize;
-        let mem_pos_content = mem_pos_content_length + 4;
-        entries.push(Embedded {
-            name,
-            offset: mem_pos_content,
-            size: content_length,
-            raw_offset: *offset,
-        });
-        last_offset = mem_pos_content + content_length;
-    }
-    Ok(entries)
+    let reader = PackReader::new(Cursor::new(file.slice(0, file.len()))).await?;
+    Ok(reader.into_entries())
 }
 
 async fn search_pattern(file: &AsyncMmapFile) -> Result<Vec<usize>, String> {
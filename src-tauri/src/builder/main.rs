@@ -2,14 +2,22 @@ use clap::Parser;
 use cli::Command;
 
 mod append;
+mod authenticode;
+mod cache;
 mod cli;
+mod compress;
+mod diff;
 mod extract;
 mod gen;
+mod keygen;
 mod local;
 mod metadata;
+mod old_version;
 mod pack;
+mod repack;
 mod replace_bin;
 mod utils;
+mod verify;
 
 pub fn main() {
     tokio::runtime::Builder::new_multi_thread()
@@ -28,17 +36,41 @@ async fn async_main() {
         panic!("No command provided");
     }
     let command = command.take().unwrap();
+    let mut success = true;
     match command {
         Command::Pack(args) => pack::pack_cli(args).await,
         Command::Gen(args) => gen::gen_cli(args).await,
-        Command::Append(args) => append::append_cli(args).await,
+        Command::Append(args) => {
+            if let Err(e) = append::append_cli(args).await {
+                eprintln!("Append failed: {}", e);
+                success = false;
+            }
+        }
         Command::Extract(args) => extract::extract_cli(args).await,
         Command::ReplaceBin(args) => {
             if let Err(e) = replace_bin::replace_bin_cli(args).await {
                 eprintln!("Replace-bin failed: {}", e);
             }
         }
+        Command::Verify(args) => success = verify::verify_cli(args).await,
+        Command::Keygen(args) => success = keygen::keygen_cli(args).await,
+        Command::Authenticode(args) => {
+            if let Err(e) = authenticode::authenticode_cli(args).await {
+                eprintln!("Authenticode failed: {}", e);
+                success = false;
+            }
+        }
+        Command::Repack(args) => {
+            if let Err(e) = repack::repack_cli(args).await {
+                eprintln!("Repack failed: {}", e);
+                success = false;
+            }
+        }
+        Command::Diff(args) => success = diff::diff_cli(args).await,
     }
     let duration = now.elapsed();
     println!("Finished in {duration:?}");
+    if !success {
+        std::process::exit(1);
+    }
 }
@@ -1,10 +1,14 @@
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use futures::StreamExt;
 
-use crate::utils::{hash::run_hash, metadata::Metadata};
+use crate::{cache::GenCache, utils::metadata::Metadata};
 
-pub async fn deep_generate_metadata(source: &PathBuf) -> Result<Vec<Metadata>, String> {
+pub async fn deep_generate_metadata(
+    source: &PathBuf,
+    cache: &Arc<GenCache>,
+) -> Result<Vec<Metadata>, String> {
     let path = Path::new(&source);
     if !path.exists() {
         return Ok(Vec::new());
@@ -36,7 +40,10 @@ pub async fn deep_generate_metadata(source: &PathBuf) -> Result<Vec<Metadata>, S
                         file_name: fin_path,
                         md5: None,
                         xxh: None,
+                        sha256: None,
+                        blake3: None,
                         size,
+                        codec: None,
                     });
                 }
             }
@@ -52,9 +59,10 @@ pub async fn deep_generate_metadata(source: &PathBuf) -> Result<Vec<Metadata>, S
     for file in files.iter() {
         let source = source.clone();
         let mut file = file.clone();
+        let cache = cache.clone();
         joinset.spawn(async move {
             let real_path = source.join(&file.file_name);
-            let hash = run_hash("xxh", real_path.to_str().unwrap()).await;
+            let hash = cache.hash_file(&real_path).await;
             if hash.is_err() {
                 return Err(hash.err().unwrap());
             }
@@ -76,6 +84,8 @@ pub async fn deep_generate_metadata(source: &PathBuf) -> Result<Vec<Metadata>, S
         let res = res.unwrap();
         finished_hashes.push(res);
     }
+    // 哈希按完成顺序返回，排序后输出才稳定
+    finished_hashes.sort_by(|a, b| a.file_name.cmp(&b.file_name));
     Ok(finished_hashes)
 }
 
@@ -115,5 +125,6 @@ pub async fn deep_get_filelist(source: &PathBuf) -> Result<Vec<String>, String>
             None => break,
         }
     }
+    files.sort();
     Ok(files)
 }
@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use async_compression::tokio::bufread::ZstdDecoder;
+use kachina_pack::{Embedded, PackReader};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, SeekFrom};
+
+use crate::{
+    cache::GenCache,
+    metadata::deep_get_filelist,
+    utils::metadata::{Codec, Metadata, PatchInfo, RepoMetadata},
+};
+
+// 与 DFS hashed 存储一致：目录下的 .metadata.json 与 hashed/
+const HASHED_METADATA: &str = ".metadata.json";
+const HASHED_DIR: &str = "hashed";
+
+pub enum Store {
+    // 打包好的安装器，按索引读取条目
+    Packed {
+        path: PathBuf,
+        entries: HashMap<String, Embedded>,
+    },
+    // 以哈希命名的压缩文件目录
+    Hashed(PathBuf),
+}
+
+impl Store {
+    // 打开以 name 存储的条目，返回未解压的原始数据
+    async fn open(&self, name: &str) -> Result<Box<dyn AsyncRead + Unpin + Send>, String> {
+        match self {
+            Store::Packed { path, entries } => {
+                let entry = entries
+                    .get(name)
+                    .ok_or_else(|| format!("{name} not packed in {path:?}"))?;
+                let mut input = File::open(path).await.map_err(|e| e.to_string())?;
+                input
+                    .seek(SeekFrom::Start(entry.offset as u64))
+                    .await
+                    .map_err(|e| e.to_string())?;
+                Ok(Box::new(input.take(entry.size as u64)))
+            }
+            Store::Hashed(dir) => Ok(Box::new(
+                File::open(dir.join(name))
+                    .await
+                    .map_err(|e| format!("Failed to open {name}: {e}"))?,
+            )),
+        }
+    }
+}
+
+// --diff-vers 指定的旧版本：解压后的目录、打包好的安装器，或 metadata 与 hashed 目录
+pub enum OldVersion {
+    Dir(PathBuf),
+    Release {
+        files: HashMap<String, Metadata>,
+        patches: Vec<PatchInfo>,
+        dictionary: Option<Vec<u8>>,
+        store: Store,
+    },
+}
+
+pub struct OldFile {
+    pub xxh: String,
+    pub size: u64,
+    // 旧版本 metadata 中记录的强哈希，解压后的目录没有
+    pub sha256: Option<String>,
+    pub blake3: Option<String>,
+}
+
+fn hash_key(md5: Option<&String>, xxh: Option<&String>) -> Option<String> {
+    // 与 pack 命名规则保持一致
+    md5.or(xxh).cloned()
+}
+
+impl OldVersion {
+    pub async fn open(path: &Path) -> Result<Self, String> {
+        if path.is_dir() {
+            let metadata_path = path.join(HASHED_METADATA);
+            let hashed_dir = path.join(HASHED_DIR);
+            if metadata_path.is_file() && hashed_dir.is_dir() {
+                return Self::open_hashed(&metadata_path, hashed_dir).await;
+            }
+            return Ok(Self::Dir(path.to_path_buf()));
+        }
+        if path.extension().is_some_and(|ext| ext == "json") {
+            let hashed_dir = path.with_file_name(HASHED_DIR);
+            return Self::open_hashed(path, hashed_dir).await;
+        }
+        Self::open_packed(path).await
+    }
+
+    async fn open_hashed(metadata_path: &Path, dir: PathBuf) -> Result<Self, String> {
+        let data = tokio::fs::read(metadata_path)
+            .await
+            .map_err(|e| format!("Failed to read {metadata_path:?}: {e}"))?;
+        let mut metadata: RepoMetadata = serde_json::from_slice(&data)
+            .map_err(|e| format!("Failed to parse {metadata_path:?}: {e}"))?;
+        let dictionary = match metadata.dictionary.as_ref() {
+            Some(dictionary) => {
+                let key = hash_key(dictionary.md5.as_ref(), dictionary.xxh.as_ref())
+                    .ok_or_else(|| "No hash found for dictionary".to_string())?;
+                let data = tokio::fs::read(dir.join(&key))
+                    .await
+                    .map_err(|e| format!("Failed to read dictionary {key}: {e}"))?;
+                Some(data)
+            }
+            None => None,
+        };
+        Ok(Self::Release {
+            patches: metadata.patches.take().unwrap_or_default(),
+            files: release_files(metadata)?,
+            dictionary,
+            store: Store::Hashed(dir),
+        })
+    }
+
+    async fn open_packed(path: &Path) -> Result<Self, String> {
+        let file = File::open(path)
+            .await
+            .map_err(|e| format!("Failed to open {path:?}: {e}"))?;
+        let mut reader = PackReader::new(file).await.map_err(|e| e.to_string())?;
+        let metadata = reader
+            .metadata()
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("No metadata found in {path:?}"))?;
+        let mut metadata: RepoMetadata = serde_json::from_slice(&metadata)
+            .map_err(|e| format!("Failed to parse metadata of {path:?}: {e}"))?;
+        let dictionary = match metadata.dictionary.as_ref() {
+            Some(dictionary) => {
+                let key = hash_key(dictionary.md5.as_ref(), dictionary.xxh.as_ref())
+                    .ok_or_else(|| "No hash found for dictionary".to_string())?;
+                let entry = reader
+                    .find(&key)
+                    .cloned()
+                    .ok_or_else(|| format!("Dictionary {key} not found in {path:?}"))?;
+                Some(reader.read_entry(&entry).await.map_err(|e| e.to_string())?)
+            }
+            None => None,
+        };
+        let entries = reader
+            .into_entries()
+            .into_iter()
+            .map(|entry| (entry.name.clone(), entry))
+            .collect();
+        Ok(Self::Release {
+            patches: metadata.patches.take().unwrap_or_default(),
+            files: release_files(metadata)?,
+            dictionary,
+            store: Store::Packed {
+                path: path.to_path_buf(),
+                entries,
+            },
+        })
+    }
+
+    pub async fn file_list(&self) -> Result<Vec<String>, String> {
+        match self {
+            Self::Dir(dir) => deep_get_filelist(dir).await,
+            Self::Release { files, .. } => {
+                let mut list: Vec<String> = files.keys().cloned().collect();
+                list.sort();
+                Ok(list)
+            }
+        }
+    }
+
+    pub async fn find(&self, file_name: &str, cache: &GenCache) -> Result<Option<OldFile>, String> {
+        match self {
+            Self::Dir(dir) => {
+                let path = dir.join(file_name);
+                if !path.exists() {
+                    return Ok(None);
+                }
+                let xxh = cache
+                    .hash_file(&path)
+                    .await
+                    .map_err(|e| format!("Failed to hash {path:?}: {e}"))?;
+                let size = tokio::fs::metadata(&path)
+                    .await
+                    .map_err(|e| e.to_string())?
+                    .len();
+                Ok(Some(OldFile {
+                    xxh,
+                    size,
+                    sha256: None,
+                    blake3: None,
+                }))
+            }
+            Self::Release { files, .. } => Ok(files.get(file_name).map(|file| OldFile {
+                xxh: file.xxh.clone().unwrap(),
+                size: file.size,
+                sha256: file.sha256.clone(),
+                blake3: file.blake3.clone(),
+            })),
+        }
+    }
+
+    // 旧版本 metadata 中的补丁，解压后的目录没有
+    pub fn patches(&self) -> &[PatchInfo] {
+        match self {
+            Self::Dir(_) => &[],
+            Self::Release { patches, .. } => patches,
+        }
+    }
+
+    // 原样复制以 name 存储的条目，用于带入旧版本的补丁
+    pub async fn copy_entry(&self, name: &str, target: &Path) -> Result<(), String> {
+        let Self::Release { store, .. } = self else {
+            return Err("not a packed release".to_string());
+        };
+        let mut input = store.open(name).await?;
+        let mut output = File::create(target).await.map_err(|e| e.to_string())?;
+        tokio::io::copy(&mut input, &mut output)
+            .await
+            .map_err(|e| e.to_string())?;
+        output.flush().await.map_err(|e| e.to_string())
+    }
+
+    // 读取旧文件的完整内容，打包的文件按记录的方式解压
+    pub async fn read(&self, file_name: &str) -> Result<Vec<u8>, String> {
+        if let Self::Dir(dir) = self {
+            return tokio::fs::read(dir.join(file_name))
+                .await
+                .map_err(|e| e.to_string());
+        }
+        let (mut decoder, size) = self.decoder(file_name).await?;
+        let mut data = Vec::with_capacity(size as usize);
+        decoder
+            .read_to_end(&mut data)
+            .await
+            .map_err(|e| format!("Failed to decompress {file_name}: {e}"))?;
+        check_size(file_name, size, data.len() as u64)?;
+        Ok(data)
+    }
+
+    // 返回旧文件在磁盘上的路径，打包的文件先解压到 temp，由调用方删除
+    pub async fn extract(&self, file_name: &str, temp: &Path) -> Result<PathBuf, String> {
+        if let Self::Dir(dir) = self {
+            return Ok(dir.join(file_name));
+        }
+        let (mut decoder, size) = self.decoder(file_name).await?;
+        let mut output = File::create(temp).await.map_err(|e| e.to_string())?;
+        let written = tokio::io::copy(&mut decoder, &mut output)
+            .await
+            .map_err(|e| format!("Failed to decompress {file_name}: {e}"))?;
+        output.flush().await.map_err(|e| e.to_string())?;
+        check_size(file_name, size, written)?;
+        Ok(temp.to_path_buf())
+    }
+
+    // 打包文件的解压流与解压后的大小
+    async fn decoder(
+        &self,
+        file_name: &str,
+    ) -> Result<(Box<dyn AsyncRead + Unpin + Send>, u64), String> {
+        let Self::Release {
+            files,
+            dictionary,
+            store,
+            ..
+        } = self
+        else {
+            return Err("not a packed release".to_string());
+        };
+        let file = files
+            .get(file_name)
+            .ok_or_else(|| format!("{file_name} not found in old version"))?;
+        let key = hash_key(file.md5.as_ref(), file.xxh.as_ref()).unwrap();
+        let stream = store.open(&key).await?;
+        let decoder: Box<dyn AsyncRead + Unpin + Send> = match file.codec.unwrap_or(Codec::Zstd) {
+            Codec::Zstd => Box::new(ZstdDecoder::new(BufReader::new(stream))),
+            Codec::Raw => stream,
+            Codec::Dict => {
+                let dictionary = dictionary
+                    .as_deref()
+                    .ok_or_else(|| "dictionary not available".to_string())?;
+                Box::new(
+                    ZstdDecoder::with_dict(BufReader::new(stream), dictionary)
+                        .map_err(|e| format!("invalid dictionary: {e}"))?,
+                )
+            }
+        };
+        Ok((decoder, file.size))
+    }
+}
+
+fn check_size(file_name: &str, expected: u64, actual: u64) -> Result<(), String> {
+    if actual != expected {
+        return Err(format!(
+            "{file_name} size mismatch: expected {expected}, got {actual}"
+        ));
+    }
+    Ok(())
+}
+
+// 补丁以 xxh 命名，旧版本的 metadata 必须带有 xxh
+fn release_files(metadata: RepoMetadata) -> Result<HashMap<String, Metadata>, String> {
+    metadata
+        .hashed
+        .unwrap_or_default()
+        .into_iter()
+        .map(|file| match file.xxh {
+            Some(_) => Ok((file.file_name.clone(), file)),
+            None => Err(format!("No xxh hash found for {}", file.file_name)),
+        })
+        .collect()
+}
@@ -1,13 +1,15 @@
-use std::path::PathBuf;
-use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use kachina_pack::{
+    checksum, read_checksum, read_resources, write_resources, PackContent, PackFile, PackWriter,
+    SigningKey, BUILD_INFO_NAME, INDEX_VERSION_64, INDEX_VERSION_CHECKSUM,
+};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
 
-use crate::{cli::PackArgs, local::get_reader_for_bundle, utils::metadata::RepoMetadata};
-
-pub struct PackFile {
-    pub name: String,
-    pub size: usize,
-    pub data: Box<dyn AsyncRead + Unpin + Send>,
-}
+use crate::{
+    cli::PackArgs, keygen::read_sign_key, local::get_reader_for_bundle,
+    utils::metadata::RepoMetadata,
+};
 
 pub struct PackConfig {
     pub config: serde_json::Value,
@@ -15,6 +17,7 @@ pub struct PackConfig {
     pub image: Option<PackFile>,
     pub files: Vec<PackFile>,
     pub icon_path: Option<PathBuf>,
+    pub sign_key: Option<SigningKey>,
 }
 
 pub async fn pack_cli(args: PackArgs) {
@@ -60,13 +63,31 @@ pub async fn pack_cli(args: PackArgs) {
     } else {
         None
     };
+    let sign_key = if let Some(sign_key) = args.sign_key {
+        match read_sign_key(&sign_key).await {
+            Ok(key) => Some(key),
+            Err(e) => {
+                eprintln!("{e}");
+                return;
+            }
+        }
+    } else {
+        None
+    };
     let image = if let Some(image) = args.image {
         let image_size = tokio::fs::metadata(&image).await;
         if image_size.is_err() {
             eprintln!("Failed to get image size: {:?}", image_size.err());
             return;
         }
-        let image_size = image_size.unwrap().len() as u32;
+        let image_size = image_size.unwrap().len();
+        let checksum = match file_checksum(&image, args.checksum).await {
+            Ok(checksum) => checksum,
+            Err(e) => {
+                eprintln!("Failed to checksum image: {:?}", e);
+                return;
+            }
+        };
         let imagef = tokio::fs::File::open(image).await;
         if imagef.is_err() {
             eprintln!("Failed to open image: {:?}", imagef.err());
@@ -74,8 +95,9 @@ pub async fn pack_cli(args: PackArgs) {
         }
         Some(PackFile {
             name: "\0IMAGE".to_string(),
-            size: image_size as usize,
+            size: image_size,
             data: Box::new(imagef.unwrap()) as Box<dyn AsyncRead + Unpin + Send>,
+            checksum,
         })
     } else {
         None
@@ -98,7 +120,14 @@ pub async fn pack_cli(args: PackArgs) {
                         continue;
                     }
                     let path = data_dir.join(hash);
-                    let size = tokio::fs::metadata(&path).await.unwrap().len() as usize;
+                    let size = tokio::fs::metadata(&path).await.unwrap().len();
+                    let checksum = match file_checksum(&path, args.checksum).await {
+                        Ok(checksum) => checksum,
+                        Err(e) => {
+                            eprintln!("Failed to checksum file {}: {:?}", hash, e);
+                            return;
+                        }
+                    };
                     let f = tokio::fs::File::open(path).await;
                     if f.is_err() {
                         eprintln!("Failed to open file {}: {:?}", hash, f.err());
@@ -109,6 +138,7 @@ pub async fn pack_cli(args: PackArgs) {
                         name: hash.clone(),
                         size,
                         data,
+                        checksum,
                     });
                 }
             }
@@ -135,7 +165,14 @@ pub async fn pack_cli(args: PackArgs) {
                         continue;
                     }
                     let path = data_dir.join(&patch_fn);
-                    let size = tokio::fs::metadata(&path).await.unwrap().len() as usize;
+                    let size = tokio::fs::metadata(&path).await.unwrap().len();
+                    let checksum = match file_checksum(&path, args.checksum).await {
+                        Ok(checksum) => checksum,
+                        Err(e) => {
+                            eprintln!("Failed to checksum file {}: {:?}", patch_fn, e);
+                            return;
+                        }
+                    };
                     let f = tokio::fs::File::open(path).await;
                     if f.is_err() {
                         eprintln!("Failed to open file {}: {:?}", patch_fn, f.err());
@@ -146,6 +183,63 @@ pub async fn pack_cli(args: PackArgs) {
                         name: patch_fn,
                         size,
                         data,
+                        checksum,
+                    });
+                }
+            }
+            if let Some(dir_patches) = metadata.dir_patches.as_ref() {
+                for dir_patch in dir_patches.iter() {
+                    if files.iter().any(|x: &PackFile| x.name == dir_patch.name) {
+                        continue;
+                    }
+                    let path = data_dir.join(&dir_patch.name);
+                    let size = tokio::fs::metadata(&path).await.unwrap().len();
+                    let checksum = match file_checksum(&path, args.checksum).await {
+                        Ok(checksum) => checksum,
+                        Err(e) => {
+                            eprintln!("Failed to checksum dir patch {}: {:?}", dir_patch.name, e);
+                            return;
+                        }
+                    };
+                    let f = tokio::fs::File::open(path).await;
+                    if f.is_err() {
+                        eprintln!("Failed to open dir patch {}: {:?}", dir_patch.name, f.err());
+                        return;
+                    }
+                    let data = Box::new(f.unwrap()) as Box<dyn AsyncRead + Unpin + Send>;
+                    files.push(PackFile {
+                        name: dir_patch.name.clone(),
+                        size,
+                        data,
+                        checksum,
+                    });
+                }
+            }
+            if let Some(dictionary) = metadata.dictionary.as_ref() {
+                let Some(hash) = dictionary.md5.as_ref().or(dictionary.xxh.as_ref()) else {
+                    eprintln!("No hash found for dictionary");
+                    return;
+                };
+                if !files.iter().any(|x: &PackFile| x.name == *hash) {
+                    let path = data_dir.join(hash);
+                    let checksum = match file_checksum(&path, args.checksum).await {
+                        Ok(checksum) => checksum,
+                        Err(e) => {
+                            eprintln!("Failed to checksum dictionary {}: {:?}", hash, e);
+                            return;
+                        }
+                    };
+                    let f = tokio::fs::File::open(path).await;
+                    if f.is_err() {
+                        eprintln!("Failed to open dictionary {}: {:?}", hash, f.err());
+                        return;
+                    }
+                    let data = Box::new(f.unwrap()) as Box<dyn AsyncRead + Unpin + Send>;
+                    files.push(PackFile {
+                        name: hash.clone(),
+                        size: dictionary.size,
+                        data,
+                        checksum,
                     });
                 }
             }
@@ -164,23 +258,52 @@ pub async fn pack_cli(args: PackArgs) {
                 if name.contains('_') {
                     continue;
                 }
-                let size = tokio::fs::metadata(&path).await.unwrap().len() as usize;
+                let size = tokio::fs::metadata(&path).await.unwrap().len();
+                let checksum = match file_checksum(&path, args.checksum).await {
+                    Ok(checksum) => checksum,
+                    Err(e) => {
+                        eprintln!("Failed to checksum file {}: {:?}", name, e);
+                        return;
+                    }
+                };
                 let f = tokio::fs::File::open(path).await;
                 if f.is_err() {
                     eprintln!("Failed to open file {}: {:?}", name, f.err());
                     return;
                 }
                 let data = Box::new(f.unwrap()) as Box<dyn AsyncRead + Unpin + Send>;
-                files.push(PackFile { name, size, data });
+                files.push(PackFile {
+                    name,
+                    size,
+                    data,
+                    checksum,
+                });
             }
         }
     }
+    if args.build_info {
+        let data = match build_info() {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("Failed to generate build info: {e}");
+                return;
+            }
+        };
+        println!("Build info: {}", String::from_utf8_lossy(&data));
+        files.push(PackFile {
+            name: BUILD_INFO_NAME.to_string(),
+            size: data.len() as u64,
+            checksum: args.checksum.then(|| checksum(&data)),
+            data: Box::new(Cursor::new(data)),
+        });
+    }
     let config = PackConfig {
         config,
         metadata,
         image,
         files,
         icon_path: args.icon,
+        sign_key,
     };
     let output = tokio::fs::File::create(args.output).await.unwrap();
     println!(
@@ -194,57 +317,22 @@ pub async fn pack_cli(args: PackArgs) {
 
 pub async fn pack(
     mut base: impl AsyncRead + std::marker::Unpin,
-    mut output: impl AsyncWrite + std::marker::Unpin,
+    output: impl AsyncWrite + std::marker::Unpin,
     mut config: PackConfig,
 ) {
-    println!("Generating exe with version info...");
-    // write base to tmp file
-    let tmppath = std::env::temp_dir().join("kachina_installer_tmp.exe");
-    let mut tmpfile = tokio::fs::File::create(tmppath.clone()).await.unwrap();
-    tokio::io::copy(&mut base, &mut tmpfile).await.unwrap();
-    // close tmp file
-    tmpfile.shutdown().await.unwrap();
-    drop(tmpfile);
-    // open resource file
-    let mut updater = rcedit::ResourceUpdater::new();
-    updater.load(&tmppath).unwrap();
-    let unwrapped_config = config.config.as_object().unwrap();
-    let title = unwrapped_config
-        .get("windowTitle")
-        .unwrap()
-        .as_str()
-        .unwrap();
-    let product = unwrapped_config.get("appName").unwrap().as_str().unwrap();
-    updater
-        .set_version_string("FileDescription", title)
-        .unwrap();
-    updater.set_version_string("ProductName", product).unwrap();
-
-    // Set icon if provided
-    if let Some(icon_path) = &config.icon_path {
-        if icon_path.exists() {
-            if let Err(e) = updater.set_icon(icon_path) {
-                eprintln!("Warning: Failed to set icon: {:?}", e);
-            } else {
-                println!("Icon set successfully: {:?}", icon_path);
-            }
-        } else {
-            eprintln!("Warning: Icon file not found: {:?}", icon_path);
-        }
-    }
-
-    updater.commit().unwrap();
-    drop(updater);
     println!("Reading base...");
     let mut base_data = vec![];
-    // read tmp file
-    let mut tmpfile = tokio::fs::File::open(tmppath.clone()).await.unwrap();
-    tokio::io::copy(&mut tmpfile, &mut base_data).await.unwrap();
-    // close tmp file
-    tmpfile.shutdown().await.unwrap();
-    drop(tmpfile);
-    // remove tmp file
-    tokio::fs::remove_file(tmppath).await.unwrap();
+    base.read_to_end(&mut base_data).await.unwrap();
+    println!("Generating exe with version info...");
+    let base_data =
+        match set_base_resources(&base_data, &config.config, config.icon_path.as_deref()).await {
+            Ok(base_data) => base_data,
+            Err(e) => {
+                eprintln!("{e}");
+                return;
+            }
+        };
+    let base_len = base_data.len() as u64;
 
     // 先克隆 packing_info 用于排序
     let packing_info_clone = config
@@ -253,9 +341,27 @@ pub async fn pack(
         .and_then(|m| m.packing_info.clone());
 
     let metadata_bytes = if let Some(mut metadata) = config.metadata {
-        println!("Writing metadata...");
         // 排除 packing_info，这些信息只用于打包阶段
         metadata.packing_info = None;
+        // 与 gen 的输出顺序无关，相同内容总是得到相同的元数据
+        if let Some(hashed) = metadata.hashed.as_mut() {
+            hashed.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+        }
+        if let Some(patches) = metadata.patches.as_mut() {
+            // 补丁链中多个补丁可能来自同一版本，需要带上 to 才是全序
+            patches.sort_by(|a, b| {
+                (&a.file_name, &a.from.xxh, &a.from.md5, &a.to.xxh, &a.to.md5).cmp(&(
+                    &b.file_name,
+                    &b.from.xxh,
+                    &b.from.md5,
+                    &b.to.xxh,
+                    &b.to.md5,
+                ))
+            });
+        }
+        if let Some(dir_patches) = metadata.dir_patches.as_mut() {
+            dir_patches.sort_by(|a, b| a.name.cmp(&b.name));
+        }
         let mut metadata = serde_json::json!(metadata);
         metadata.sort_all_objects();
         let metadata_bytes = serde_json::to_string(&metadata).unwrap();
@@ -263,36 +369,8 @@ pub async fn pack(
     } else {
         None
     };
-    println!("Generating index...");
     config.config.sort_all_objects();
     let config_bytes = serde_json::to_string(&config.config).unwrap();
-    let mut files = config.files;
-    // name size offset
-    let mut index: Vec<(String, u32, u32)> = vec![];
-    // insert config to index
-    let mut current_offset = 0;
-    index.push((
-        "\0CONFIG".to_string(),
-        config_bytes.len() as u32,
-        get_header_size("\0CONFIG") as u32,
-    ));
-    current_offset += config_bytes.len() + get_header_size("\0CONFIG");
-    // insert image to index
-    if let Some(img) = config.image.as_ref() {
-        let offset = current_offset + get_header_size(&img.name);
-        index.push((img.name.clone(), img.size as u32, offset as u32));
-        current_offset = offset + img.size;
-    }
-    // insert metadata to index
-    if let Some(metadata_bytes) = metadata_bytes.as_ref() {
-        let offset = current_offset + get_header_size("\0META");
-        index.push((
-            "\0META".to_string(),
-            metadata_bytes.len() as u32,
-            offset as u32,
-        ));
-        current_offset = offset + metadata_bytes.len();
-    }
     // 使用打包优化信息进行智能排序
     if let Some(ref packing_info) = packing_info_clone {
         println!("Packing order optimization enabled:");
@@ -302,179 +380,116 @@ pub async fn pack(
         println!("  Small patches: {}", packing_info[3].len());
         println!("  Large patches: {}", packing_info[4].len());
     }
-
+    let mut files = config.files;
     files.sort_by_key(|file| get_file_pack_priority(&file.name, packing_info_clone.as_ref()));
-    for file in files.iter_mut() {
-        let name = file.name.clone();
-        let size = file.size;
-        let offset: usize = current_offset + get_header_size(&name);
-        index.push((name, size as u32, offset as u32));
-        current_offset = offset + size;
-    }
-    let index_len = index_to_bin(&index).len() + get_header_size("\0INDEX");
-    // add index_len to offset
-    for (name, _size, offset) in index.iter_mut() {
-        // index is after config and image
-        if name == "\0CONFIG" || name == "\0IMAGE" {
-            continue;
-        }
-        *offset += index_len as u32;
-    }
-    // write pre-index to pe header
-    let index_pre = if !files.is_empty() {
-        gen_index_header(
-            base_data.len() as u32,
-            (config_bytes.len() + get_header_size("\0CONFIG")) as u32,
-            if let Some(img) = config.image.as_ref() {
-                (img.size + get_header_size(&img.name)) as u32
-            } else {
-                0
-            },
-            index_len as u32,
-            if let Some(metadata_bytes) = metadata_bytes.as_ref() {
-                (metadata_bytes.len() + get_header_size("\0META")) as u32
-            } else {
-                0
-            },
-        )
-    } else {
-        gen_index_header(0, 0, 0, 0, 0)
+    let content = PackContent {
+        config: config_bytes.into_bytes(),
+        image: config.image,
+        metadata: metadata_bytes,
+        files,
     };
-    // replace 'This program cannot be run in DOS mode' in pe header to index_pre
-    let pe_str_offset = base_data.iter().position(|x| *x == 0x54).unwrap();
-    let pe_str = &mut base_data[pe_str_offset..pe_str_offset + index_pre.len()];
-    // check if pe_str is really 'This program cannot be run in DOS mode'
-    let pe_string = std::str::from_utf8_mut(pe_str).unwrap();
-    if pe_string != "This program cannot be run in DOS mode" {
-        eprintln!("Failed to find pe string: {pe_string:?}");
-        return;
-    }
-    pe_str.copy_from_slice(&index_pre);
-    // copy base to output, not closing output file
     println!("Writing base...");
-    output.write_all(&base_data).await.unwrap();
-    // write config
-    println!("Writing config...");
-    let config_bytes = config_bytes.as_bytes();
-    let res = write_header(&mut output, "\0CONFIG", config_bytes.len() as u32).await;
-    if res.is_err() {
-        eprintln!("Failed to write header: {:?}", res.err());
-        return;
-    }
-    let res = output.write_all(config_bytes).await;
-    if res.is_err() {
-        eprintln!("Failed to write config: {:?}", res.err());
-        return;
+    let mut writer = PackWriter::new(output).on_progress(|name| match name {
+        "\0CONFIG" => println!("Writing config..."),
+        "\0IMAGE" => println!("Writing image..."),
+        "\0INDEX" => println!("Writing index..."),
+        "\0META" => println!("Writing metadata..."),
+        "\0SIGN" => println!("Writing signature..."),
+        name => println!("Writing file: {name}"),
+    });
+    if let Some(key) = config.sign_key {
+        writer = writer.sign_with(key);
     }
-    // if theme exists, write theme
-    if let Some(image) = config.image.as_mut() {
-        println!("Writing image...");
-        let res = write_file(&mut output, image).await;
-        if res.is_err() {
-            eprintln!("Failed to write image: {:?}", res.err());
+    let layout = match writer.write(&base_data[..], base_len, content).await {
+        Ok(layout) => layout,
+        Err(e) => {
+            eprintln!("Failed to write installer: {e}");
             return;
         }
+    };
+    match layout.index_version {
+        INDEX_VERSION_64 => println!(
+            "Payload exceeds 4 GiB, used 64-bit index (v{})",
+            layout.index_version
+        ),
+        INDEX_VERSION_CHECKSUM => println!(
+            "Entry checksums enabled, used checksum index (v{})",
+            layout.index_version
+        ),
+        _ => {}
     }
-    if !files.is_empty() {
-        // write index
-        println!("Writing index...");
-        let index_bytes = index_to_bin(&index);
-        write_header(&mut output, "\0INDEX", index_bytes.len() as u32)
-            .await
-            .unwrap();
+    println!("Done");
+}
 
-        output.write_all(&index_bytes).await.unwrap();
-    }
-    // if metadata exists, write metadata
-    if let Some(metadata_bytes) = metadata_bytes {
-        let res = write_header(&mut output, "\0META", metadata_bytes.len() as u32).await;
-        if res.is_err() {
-            eprintln!("Failed to write header: {:?}", res.err());
-            return;
-        }
-        let res = output.write_all(&metadata_bytes).await;
-        if res.is_err() {
-            eprintln!("Failed to write metadata: {:?}", res.err());
-            return;
-        }
+// 将窗口标题、应用名与图标写入 exe 的版本信息资源
+pub async fn set_base_resources(
+    base: &[u8],
+    config: &serde_json::Value,
+    icon_path: Option<&Path>,
+) -> Result<Vec<u8>, String> {
+    let mut resources =
+        read_resources(base).map_err(|e| format!("Failed to read base resources: {e}"))?;
+    let config_str = |key: &str| {
+        config
+            .get(key)
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| format!("Config has no {key}"))
+    };
+    let title = config_str("windowTitle")?;
+    let product = config_str("appName")?;
+    for (key, value) in [("FileDescription", title), ("ProductName", product)] {
+        resources
+            .set_version_string(key, value)
+            .map_err(|e| format!("Failed to set {key}: {e}"))?;
     }
-    // write files
-    for file in files.iter_mut() {
-        println!("Writing file: {}", file.name);
-        let res = write_file(&mut output, file).await;
-        if res.is_err() {
-            eprintln!("Failed to write file {}: {:?}", file.name, res.err());
-            return;
+
+    // Set icon if provided
+    if let Some(icon_path) = icon_path {
+        match tokio::fs::read(icon_path).await {
+            Ok(icon) => {
+                if let Err(e) = resources.set_icon(&icon) {
+                    eprintln!("Warning: Failed to set icon: {:?}", e);
+                } else {
+                    println!("Icon set successfully: {:?}", icon_path);
+                }
+            }
+            Err(_) => eprintln!("Warning: Icon file not found: {:?}", icon_path),
         }
     }
-    // flush
-    println!("Finalizing...");
-    let res = output.flush().await;
-    if res.is_err() {
-        eprintln!("Failed to flush: {:?}", res.err());
-        return;
-    }
-    println!("Done");
-}
 
-pub async fn write_header(
-    output: &mut (impl AsyncWrite + std::marker::Unpin),
-    name: &str,
-    size: u32,
-) -> Result<(), tokio::io::Error> {
-    let header = "!in\0".to_ascii_uppercase();
-    let header = header.as_bytes();
-    let name = name.as_bytes();
-    let size = size.to_be_bytes();
-    output.write_all(header).await?;
-    let namelen = (name.len() as u16).to_be_bytes();
-    output.write_all(&namelen).await?;
-    output.write_all(name).await?;
-    output.write_all(&size).await?;
-    Ok(())
+    write_resources(base, &resources).map_err(|e| format!("Failed to write base resources: {e}"))
 }
 
-pub fn get_header_size(name: &str) -> usize {
-    "!in\0".len() + 2 + name.len() + 4
+// 构建信息：builder 版本、源码提交与构建时间
+// 设置 SOURCE_DATE_EPOCH 时使用它作为构建时间，保证输出可复现
+pub fn build_info() -> Result<Vec<u8>, String> {
+    let timestamp = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch
+            .trim()
+            .parse::<i64>()
+            .map_err(|e| format!("Invalid SOURCE_DATE_EPOCH {epoch:?}: {e}"))?,
+        Err(_) => chrono::Utc::now().timestamp(),
+    };
+    let time = chrono::DateTime::from_timestamp(timestamp, 0)
+        .ok_or_else(|| format!("Invalid build timestamp: {timestamp}"))?;
+    let commit = env!("KACHINA_BUILD_COMMIT");
+    let info = serde_json::json!({
+        "builder": env!("CARGO_PKG_VERSION"),
+        "commit": if commit.is_empty() { None } else { Some(commit) },
+        "timestamp": timestamp,
+        "time": time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+    });
+    serde_json::to_vec(&info).map_err(|e| e.to_string())
 }
 
-pub fn index_to_bin(index: &[(String, u32, u32)]) -> Vec<u8> {
-    let mut data = vec![];
-    // u8: name_len var: name u32: size u32: offset
-    for (name, size, offset) in index.iter() {
-        let name = name.as_bytes();
-        let name_len = name.len() as u8;
-        data.push(name_len);
-        data.extend_from_slice(name);
-        data.extend_from_slice(&size.to_be_bytes());
-        data.extend_from_slice(&offset.to_be_bytes());
+// 打包前先读一遍文件计算校验值，未开启时跳过
+pub async fn file_checksum(path: &Path, enabled: bool) -> std::io::Result<Option<u64>> {
+    if !enabled {
+        return Ok(None);
     }
-    data
-}
-
-pub fn gen_index_header(
-    base_end: u32,
-    config_end: u32,
-    theme_end: u32,
-    index_end: u32,
-    manifest_end: u32,
-) -> Vec<u8> {
-    let mut data = "!KachinaInstaller!".as_bytes().to_vec();
-    data.extend_from_slice(&base_end.to_be_bytes());
-    data.extend_from_slice(&config_end.to_be_bytes());
-    data.extend_from_slice(&theme_end.to_be_bytes());
-    data.extend_from_slice(&index_end.to_be_bytes());
-    data.extend_from_slice(&manifest_end.to_be_bytes());
-    data
-}
-
-pub async fn write_file(
-    output: &mut (impl AsyncWrite + std::marker::Unpin),
-    file: &mut PackFile,
-) -> Result<(), tokio::io::Error> {
-    write_header(output, &file.name, file.size as u32).await?;
-    tokio::io::copy(&mut file.data, output).await?;
-    Ok(())
+    let file = tokio::fs::File::open(path).await?;
+    let (checksum, _) = read_checksum(file).await?;
+    Ok(Some(checksum))
 }
 
 fn get_file_pack_priority(
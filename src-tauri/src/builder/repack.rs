@@ -0,0 +1,205 @@
+use std::io::Cursor;
+
+use kachina_pack::{
+    get_security_directory, IndexEntry, PackContent, PackFile, PackLayout, PackReader, PackWriter,
+    CONFIG_NAME, IMAGE_NAME, INDEX_NAME, INDEX_VERSION_CHECKSUM, META_NAME, SIGNATURE_NAME,
+};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
+
+use crate::cli::RepackArgs;
+use crate::keygen::read_sign_key;
+use crate::pack::{file_checksum, set_base_resources};
+
+// 只替换配置、图片与 exe 资源，元数据与文件数据原样复制
+pub async fn repack_cli(args: RepackArgs) -> Result<(), String> {
+    if args.config.is_none() && args.image.is_none() && args.icon.is_none() {
+        return Err("Nothing to replace, use --config, --image or --icon".to_string());
+    }
+    if args.output == args.input {
+        return Err("Output must not overwrite the input file".to_string());
+    }
+
+    println!("Parsing installer index...");
+    let input = File::open(&args.input).await.map_err(|e| e.to_string())?;
+    let mut reader = PackReader::new(input).await.map_err(|e| e.to_string())?;
+    if reader.certificate().is_some() {
+        return Err(
+            "Authenticode-signed installers can't be repacked, repack the unsigned one and sign it again"
+                .to_string(),
+        );
+    }
+    let old_config = reader
+        .config()
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "No config found in input file".to_string())?;
+    let sign_key = match args.sign_key.as_ref() {
+        Some(path) => Some(read_sign_key(path).await?),
+        None => None,
+    };
+    if sign_key.is_none() && reader.find(SIGNATURE_NAME).is_some() {
+        return Err("Input is signed, --sign-key is required".to_string());
+    }
+
+    // 1. 配置：未指定时保留原配置
+    let (config, config_bytes) = match args.config.as_ref() {
+        Some(path) => {
+            let data = tokio::fs::read(path)
+                .await
+                .map_err(|e| format!("Failed to read config {:?} : {:?}", path, e))?;
+            let mut config: serde_json::Value = serde_json::from_slice(&data)
+                .map_err(|e| format!("Failed to parse config: {e}"))?;
+            config.sort_all_objects();
+            let bytes = serde_json::to_vec(&config).map_err(|e| e.to_string())?;
+            (config, bytes)
+        }
+        None => {
+            let config = serde_json::from_slice(&old_config)
+                .map_err(|e| format!("Failed to parse packed config: {e}"))?;
+            (config, old_config)
+        }
+    };
+
+    // 2. 图片：未指定时保留原图片
+    let with_checksum = reader.index_version() == Some(INDEX_VERSION_CHECKSUM);
+    let image = match args.image.as_ref() {
+        Some(path) => {
+            let size = tokio::fs::metadata(path)
+                .await
+                .map_err(|e| format!("Failed to get image size: {e}"))?
+                .len();
+            let checksum = file_checksum(path, with_checksum)
+                .await
+                .map_err(|e| format!("Failed to checksum image: {e}"))?;
+            let file = File::open(path)
+                .await
+                .map_err(|e| format!("Failed to open image: {e}"))?;
+            Some(PackFile {
+                name: IMAGE_NAME.to_string(),
+                size,
+                data: Box::new(file),
+                checksum,
+            })
+        }
+        None => match reader.find(IMAGE_NAME).cloned() {
+            Some(entry) => {
+                let data = reader.read_entry(&entry).await.map_err(|e| e.to_string())?;
+                Some(PackFile {
+                    name: IMAGE_NAME.to_string(),
+                    size: data.len() as u64,
+                    data: Box::new(Cursor::new(data)),
+                    checksum: entry.checksum,
+                })
+            }
+            None => None,
+        },
+    };
+    let metadata = reader.metadata().await.map_err(|e| e.to_string())?;
+
+    // 3. 文件只参与布局计算，数据之后原样复制
+    let mut old_files: Vec<IndexEntry> = match reader.index().await.map_err(|e| e.to_string())? {
+        Some((_, index)) => index
+            .into_iter()
+            .filter(|e| ![CONFIG_NAME, IMAGE_NAME, META_NAME].contains(&e.name.as_str()))
+            .collect(),
+        None => vec![],
+    };
+    old_files.sort_by_key(|e| e.offset);
+    let files = old_files
+        .iter()
+        .map(|e| PackFile {
+            name: e.name.clone(),
+            size: e.size,
+            data: Box::new(tokio::io::empty()),
+            checksum: e.checksum,
+        })
+        .collect();
+    println!("Found {} files", old_files.len());
+
+    let old_base_end = reader.base_end();
+    let old_leading_end = match reader.header() {
+        Some(header) => header.leading_end(),
+        // 仅有配置的安装包没有索引，其余数据从配置与图片之后开始
+        None => reader
+            .entries()
+            .iter()
+            .filter(|e| {
+                [
+                    CONFIG_NAME,
+                    IMAGE_NAME,
+                    INDEX_NAME,
+                    META_NAME,
+                    SIGNATURE_NAME,
+                ]
+                .contains(&e.name.as_str())
+            })
+            .map(|e| (e.offset + e.size) as u64)
+            .max()
+            .unwrap_or(old_base_end),
+    };
+    let base = reader
+        .read_at(0, old_base_end)
+        .await
+        .map_err(|e| e.to_string())?;
+    let end = match get_security_directory(&base) {
+        // 打包后签过名的安装包，修改后旧签名失效，不再复制
+        Some(dir) if dir.size != 0 && dir.offset >= old_leading_end => dir.offset,
+        _ => reader.file_len(),
+    };
+
+    println!("Generating exe with version info...");
+    let base = set_base_resources(&base, &config, args.icon.as_deref()).await?;
+
+    // 4. 索引中的偏移量相对于 base_end，文件整体平移即可，数据必须与原布局一致
+    let mut content = PackContent {
+        config: config_bytes,
+        image,
+        metadata,
+        files,
+    };
+    let layout = PackLayout::new(base.len() as u64, &content, sign_key.is_some())
+        .map_err(|e| e.to_string())?;
+    let new_files = &layout.index[layout.index.len() - old_files.len()..];
+    let old_start = old_leading_end - old_base_end;
+    let new_start = layout.header.leading_end() - layout.header.base_end as u64;
+    for (old, new) in old_files.iter().zip(new_files) {
+        if old.offset + new_start != new.offset + old_start {
+            return Err(format!(
+                "Entry {} is not where the index expects it, run pack again",
+                old.name
+            ));
+        }
+    }
+    println!("Old index: {:?}", reader.header());
+    println!("New index: {:?}", layout.header);
+
+    println!("Writing new installer...");
+    let mut output = File::create(&args.output)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut writer = PackWriter::new(&mut output);
+    if let Some(key) = sign_key {
+        writer = writer.sign_with(key);
+    }
+    writer
+        .write_leading(base.as_slice(), base.len() as u64, &mut content)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut input = reader.into_inner();
+    input
+        .seek(SeekFrom::Start(old_leading_end))
+        .await
+        .map_err(|e| e.to_string())?;
+    let copied = tokio::io::copy(&mut input.take(end - old_leading_end), &mut output)
+        .await
+        .map_err(|e| e.to_string())?;
+    output.flush().await.map_err(|e| e.to_string())?;
+    println!("Payload: {} bytes copied", copied);
+
+    println!(
+        "Successfully created new installer: {}",
+        args.output.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,733 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+
+use async_compression::tokio::bufread::ZstdDecoder;
+use kachina_pack::{
+    get_header_size, parse_public_key, ChecksumReader, DrainReader, Embedded, IndexHeader,
+    PackReader, CONFIG_NAME, INDEX_HEADER_LEN, INDEX_NAME, META_NAME, SIGNATURE_NAME,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
+use twox_hash::XxHash3_128;
+
+use crate::{
+    cli::VerifyArgs,
+    utils::{
+        hash::run_hash,
+        metadata::{Codec, DirPatchInfo, Metadata, PatchInfo, RepoMetadata},
+    },
+};
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Ok,
+    Skipped,
+    Failed,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::Ok => write!(f, "OK"),
+            Status::Skipped => write!(f, "SKIPPED"),
+            Status::Failed => write!(f, "FAILED"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct EntryReport {
+    name: String,
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_name: Option<String>,
+    status: Status,
+    message: String,
+}
+
+#[derive(Debug, Serialize)]
+struct VerifyReport {
+    input: String,
+    ok: bool,
+    entries: Vec<EntryReport>,
+}
+
+impl VerifyReport {
+    fn push(&mut self, name: &str, kind: &'static str, status: Status, message: String) {
+        self.entries.push(EntryReport {
+            name: name.replace('\0', "\\0"),
+            kind,
+            file_name: None,
+            status,
+            message,
+        });
+    }
+}
+
+// 边解压边计算哈希，不落盘
+struct HashWriter {
+    xxh: XxHash3_128,
+    md5: chksum_md5::MD5,
+    sha256: Sha256,
+    blake3: blake3::Hasher,
+    size: u64,
+}
+
+impl HashWriter {
+    fn new() -> Self {
+        HashWriter {
+            xxh: XxHash3_128::new(),
+            md5: chksum_md5::new(),
+            sha256: Sha256::new(),
+            blake3: blake3::Hasher::new(),
+            size: 0,
+        }
+    }
+
+    // 按元数据中存在的哈希比较，返回错误信息
+    fn check(&self, size: u64, md5: Option<&String>, xxh: Option<&String>) -> Result<(), String> {
+        if self.size != size {
+            return Err(format!("size mismatch: expected {size}, got {}", self.size));
+        }
+        if let Some(xxh) = xxh {
+            let actual = format!("{:x}", self.xxh.finish_128());
+            if actual != *xxh {
+                return Err(format!("xxh mismatch: expected {xxh}, got {actual}"));
+            }
+        }
+        if let Some(md5) = md5 {
+            let actual = self.md5.digest().to_hex_lowercase();
+            if actual != *md5 {
+                return Err(format!("md5 mismatch: expected {md5}, got {actual}"));
+            }
+        }
+        Ok(())
+    }
+
+    // gen --strong-hash 写入的哈希
+    fn check_strong(&self, sha256: Option<&String>, blake3: Option<&String>) -> Result<(), String> {
+        if let Some(sha256) = sha256 {
+            let actual = format!("{:x}", self.sha256.clone().finalize());
+            if actual != *sha256 {
+                return Err(format!("sha256 mismatch: expected {sha256}, got {actual}"));
+            }
+        }
+        if let Some(blake3) = blake3 {
+            let actual = self.blake3.finalize().to_hex().to_string();
+            if actual != *blake3 {
+                return Err(format!("blake3 mismatch: expected {blake3}, got {actual}"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.xxh.write(buf);
+        self.md5.update(buf);
+        self.sha256.update(buf);
+        self.blake3.update(buf);
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn hash_key(md5: Option<&String>, xxh: Option<&String>) -> Option<String> {
+    // 与 pack 命名规则保持一致
+    md5.or(xxh).cloned()
+}
+
+async fn decompress_entry(
+    reader: &mut PackReader<File>,
+    entry: &Embedded,
+    codec: Codec,
+    dictionary: Option<&[u8]>,
+    hasher: &mut HashWriter,
+    keep: bool,
+) -> Result<Vec<u8>, String> {
+    let stream = reader
+        .entry_reader(entry)
+        .await
+        .map_err(|e| format!("read failed: {e}"))?;
+    // 存在 v3 索引校验值时同时校验压缩数据
+    let stream: Box<dyn AsyncRead + Unpin + Send + '_> = match entry.checksum {
+        Some(expected) => Box::new(ChecksumReader::new(
+            stream,
+            &entry.name,
+            expected,
+            entry.size as u64,
+        )),
+        None => Box::new(stream),
+    };
+    // 解压到帧尾后继续读完压缩数据，使校验值得到检查
+    let mut decoder: Box<dyn AsyncRead + Unpin + Send + '_> = match codec {
+        Codec::Zstd => Box::new(DrainReader::new(
+            ZstdDecoder::new(BufReader::new(stream)),
+            ZstdDecoder::get_mut,
+        )),
+        Codec::Raw => stream,
+        Codec::Dict => {
+            let dictionary = dictionary.ok_or_else(|| "dictionary not available".to_string())?;
+            Box::new(DrainReader::new(
+                ZstdDecoder::with_dict(BufReader::new(stream), dictionary)
+                    .map_err(|e| format!("invalid dictionary: {e}"))?,
+                ZstdDecoder::get_mut,
+            ))
+        }
+    };
+    let mut buffer = vec![0u8; 256 * 1024];
+    let mut kept = vec![];
+    loop {
+        let read = decoder
+            .read(&mut buffer)
+            .await
+            .map_err(|e| format!("zstd decompression failed: {e}"))?;
+        if read == 0 {
+            break;
+        }
+        hasher.write_all(&buffer[..read]).unwrap();
+        if keep {
+            kept.extend_from_slice(&buffer[..read]);
+        }
+    }
+    Ok(kept)
+}
+
+async fn verify_file(
+    reader: &mut PackReader<File>,
+    entry: &Embedded,
+    file: &Metadata,
+    dictionary: Option<&[u8]>,
+) -> Result<String, String> {
+    let mut hasher = HashWriter::new();
+    let codec = file.codec.unwrap_or(Codec::Zstd);
+    decompress_entry(reader, entry, codec, dictionary, &mut hasher, false).await?;
+    hasher.check(file.size, file.md5.as_ref(), file.xxh.as_ref())?;
+    hasher.check_strong(file.sha256.as_ref(), file.blake3.as_ref())?;
+    Ok(format!("{} bytes", hasher.size))
+}
+
+async fn verify_patch(
+    reader: &mut PackReader<File>,
+    entry: &Embedded,
+    patch: &PatchInfo,
+    old_dir: Option<&Path>,
+) -> Result<(Status, String), String> {
+    let mut diff_hasher = HashWriter::new();
+    let codec = patch.codec.unwrap_or(Codec::Zstd);
+    let diff = decompress_entry(reader, entry, codec, None, &mut diff_hasher, true).await?;
+    if diff_hasher.size != patch.size {
+        return Err(format!(
+            "diff size mismatch: expected {}, got {}",
+            patch.size, diff_hasher.size
+        ));
+    }
+    let Some(old_dir) = old_dir else {
+        return Ok((
+            Status::Skipped,
+            "diff decompressed, no old version to apply".to_string(),
+        ));
+    };
+    let old_path = old_dir.join(&patch.file_name);
+    if !old_path.exists() {
+        return Ok((
+            Status::Skipped,
+            format!("old file not found: {}", old_path.display()),
+        ));
+    }
+    let old_path_str = old_path.to_str().unwrap();
+    let old_hash = if let Some(xxh) = patch.from.xxh.as_ref() {
+        run_hash("xxh", old_path_str).await.map(|h| (h, xxh))
+    } else if let Some(md5) = patch.from.md5.as_ref() {
+        run_hash("md5", old_path_str).await.map(|h| (h, md5))
+    } else {
+        return Err("no source hash in metadata".to_string());
+    };
+    match old_hash {
+        Ok((actual, expected)) if actual == *expected => {}
+        Ok(_) => {
+            return Ok((
+                Status::Skipped,
+                "old file does not match patch source".to_string(),
+            ))
+        }
+        Err(e) => return Err(format!("failed to hash old file: {e}")),
+    }
+    let old_file = std::fs::File::open(&old_path).map_err(|e| e.to_string())?;
+    let old_size = old_file.metadata().map_err(|e| e.to_string())?.len() as usize;
+    let new_size = patch.to.size as usize;
+    let (res, hasher) = tokio::task::spawn_blocking(move || {
+        let mut hasher = HashWriter::new();
+        let diff_size = diff.len();
+        let res = hpatch_sys::safe_patch_single_stream(
+            &mut hasher,
+            std::io::Cursor::new(diff),
+            diff_size,
+            old_file,
+            old_size,
+            new_size,
+        );
+        (res, hasher)
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    if res == hpatch_sys::PATCH_SIZE_MISMATCH {
+        return Err(format!("patch does not produce {new_size} bytes"));
+    }
+    if res != 1 {
+        return Err(format!("hpatch failed: {res}"));
+    }
+    hasher.check(patch.to.size, patch.to.md5.as_ref(), patch.to.xxh.as_ref())?;
+    hasher.check_strong(patch.to.sha256.as_ref(), patch.to.blake3.as_ref())?;
+    Ok((Status::Ok, format!("patched to {} bytes", hasher.size)))
+}
+
+async fn verify_dir_patch(
+    reader: &mut PackReader<File>,
+    entry: &Embedded,
+    dir_patch: &DirPatchInfo,
+    old_dir: Option<&Path>,
+) -> Result<(Status, String), String> {
+    let mut diff_hasher = HashWriter::new();
+    let codec = dir_patch.codec.unwrap_or(Codec::Zstd);
+    let diff = decompress_entry(reader, entry, codec, None, &mut diff_hasher, true).await?;
+    if diff_hasher.size != dir_patch.size {
+        return Err(format!(
+            "diff size mismatch: expected {}, got {}",
+            dir_patch.size, diff_hasher.size
+        ));
+    }
+    let Some(old_dir) = old_dir else {
+        return Ok((
+            Status::Skipped,
+            "diff decompressed, no old version to apply".to_string(),
+        ));
+    };
+    // 旧成员文件都与补丁来源一致时才能应用
+    let mut old_data = vec![];
+    for file in dir_patch.files.iter() {
+        let old_path = old_dir.join(&file.file_name);
+        let Ok(data) = tokio::fs::read(&old_path).await else {
+            return Ok((
+                Status::Skipped,
+                format!("old file not found: {}", old_path.display()),
+            ));
+        };
+        let mut hasher = HashWriter::new();
+        hasher.write_all(&data).unwrap();
+        if hasher
+            .check(
+                file.from.size,
+                file.from.md5.as_ref(),
+                file.from.xxh.as_ref(),
+            )
+            .is_err()
+        {
+            return Ok((
+                Status::Skipped,
+                format!("{} does not match patch source", file.file_name),
+            ));
+        }
+        old_data.extend(data);
+    }
+    let new_size = dir_patch.files.iter().map(|file| file.to.size).sum::<u64>() as usize;
+    let (res, new_data) = tokio::task::spawn_blocking(move || {
+        let mut new_data = Vec::with_capacity(new_size);
+        let diff_size = diff.len();
+        let old_size = old_data.len();
+        let res = hpatch_sys::safe_patch_single_stream(
+            &mut new_data,
+            std::io::Cursor::new(diff),
+            diff_size,
+            std::io::Cursor::new(old_data),
+            old_size,
+            new_size,
+        );
+        (res, new_data)
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    if res != 1 {
+        return Err(format!("hpatch failed: {res}"));
+    }
+    if new_data.len() != new_size {
+        return Err(format!(
+            "patched size mismatch: expected {new_size}, got {}",
+            new_data.len()
+        ));
+    }
+    let mut rest = new_data.as_slice();
+    for file in dir_patch.files.iter() {
+        let (data, next) = rest.split_at(file.to.size as usize);
+        let mut hasher = HashWriter::new();
+        hasher.write_all(data).unwrap();
+        hasher
+            .check(file.to.size, file.to.md5.as_ref(), file.to.xxh.as_ref())
+            .and_then(|_| hasher.check_strong(file.to.sha256.as_ref(), file.to.blake3.as_ref()))
+            .map_err(|e| format!("{}: {e}", file.file_name))?;
+        rest = next;
+    }
+    Ok((
+        Status::Ok,
+        format!("patched {} files", dir_patch.files.len()),
+    ))
+}
+
+// 检查头部与索引是否一致
+async fn verify_layout(reader: &mut PackReader<File>, report: &mut VerifyReport) {
+    let head_len = reader.file_len().min(256);
+    let head = reader.read_at(0, head_len).await.unwrap_or_default();
+    let mut raw_header = IndexHeader::parse(&head);
+    if let Some(cert) = reader.certificate().copied() {
+        // Authenticode 签名的安装包，头部在证书表中
+        let head = reader
+            .read_at(cert.content_offset(), INDEX_HEADER_LEN as u64)
+            .await
+            .unwrap_or_default();
+        raw_header = IndexHeader::parse(&head);
+    }
+    let has_index = reader.find(INDEX_NAME).is_some();
+    match (raw_header, reader.header()) {
+        (Some(header), Some(_)) => report.push(
+            "!KachinaInstaller!",
+            "header",
+            Status::Ok,
+            format!(
+                "base {} config {} image {} index {} meta {}",
+                header.base_end,
+                header.config_len,
+                header.image_len,
+                header.index_len,
+                header.meta_len
+            ),
+        ),
+        (Some(_), None) => report.push(
+            "!KachinaInstaller!",
+            "header",
+            Status::Failed,
+            "header does not match the packed entries".to_string(),
+        ),
+        (None, _) if has_index => report.push(
+            "!KachinaInstaller!",
+            "header",
+            Status::Failed,
+            "missing or cleared header in a pack with index".to_string(),
+        ),
+        (None, _) => report.push(
+            "!KachinaInstaller!",
+            "header",
+            Status::Skipped,
+            "no header, config-only pack".to_string(),
+        ),
+    }
+
+    let index = match reader.index().await {
+        Ok(Some(index)) => index,
+        Ok(None) => return,
+        Err(e) => {
+            report.push(INDEX_NAME, "index", Status::Failed, e.to_string());
+            return;
+        }
+    };
+    let (version, index) = index;
+    let base_end = reader.base_end();
+    let mut failed = 0;
+    for item in index.iter() {
+        let offset = base_end + item.offset;
+        let raw_offset = offset.checked_sub(get_header_size(&item.name, item.size) as u64);
+        let entry = match raw_offset {
+            Some(raw_offset) => reader.read_tlv(raw_offset).await.ok().flatten(),
+            None => None,
+        };
+        let matched = entry.is_some_and(|entry| {
+            entry.name == item.name
+                && entry.offset as u64 == offset
+                && entry.size as u64 == item.size
+        });
+        if !matched {
+            failed += 1;
+            report.push(
+                &item.name,
+                "index",
+                Status::Failed,
+                format!(
+                    "no matching entry at offset {offset} with size {}",
+                    item.size
+                ),
+            );
+        }
+    }
+    if failed == 0 {
+        report.push(
+            INDEX_NAME,
+            "index",
+            Status::Ok,
+            format!("v{version}, {} entries", index.len()),
+        );
+    }
+}
+
+// 配置了 trustedKeys 时，签名必须由其中的公钥签发
+async fn verify_signature(
+    reader: &mut PackReader<File>,
+    config: Option<&serde_json::Value>,
+    report: &mut VerifyReport,
+) {
+    let mut trusted = vec![];
+    for key in config
+        .and_then(|c| c["trustedKeys"].as_array())
+        .into_iter()
+        .flatten()
+    {
+        match key.as_str().and_then(parse_public_key) {
+            Some(key) => trusted.push(key),
+            None => report.push(
+                CONFIG_NAME,
+                "config",
+                Status::Failed,
+                format!("invalid trusted key: {key}"),
+            ),
+        }
+    }
+    let signed = reader.find(SIGNATURE_NAME).is_some();
+    if trusted.is_empty() {
+        if signed {
+            report.push(
+                SIGNATURE_NAME,
+                "signature",
+                Status::Skipped,
+                "signed, but the config has no trustedKeys".to_string(),
+            );
+        }
+        return;
+    }
+    let (status, message) = match reader.verify_signature(&trusted).await {
+        Ok(Ok(())) => (Status::Ok, "signed by a trusted key".to_string()),
+        Ok(Err(e)) => (Status::Failed, e.to_string()),
+        Err(e) => (Status::Failed, format!("read failed: {e}")),
+    };
+    report.push(SIGNATURE_NAME, "signature", status, message);
+}
+
+async fn verify(args: &VerifyArgs) -> Result<VerifyReport, String> {
+    let file = File::open(&args.input)
+        .await
+        .map_err(|e| format!("Failed to open input file {}: {e}", args.input.display()))?;
+    let mut reader = PackReader::new(file).await.map_err(|e| e.to_string())?;
+    let mut report = VerifyReport {
+        input: args.input.display().to_string(),
+        ok: true,
+        entries: vec![],
+    };
+    if reader.find(CONFIG_NAME).is_none() {
+        report.push(
+            CONFIG_NAME,
+            "config",
+            Status::Failed,
+            "no packed config found".to_string(),
+        );
+        report.ok = false;
+        return Ok(report);
+    }
+    verify_layout(&mut reader, &mut report).await;
+
+    let config = reader.config().await.map_err(|e| e.to_string())?;
+    let config = match serde_json::from_slice::<serde_json::Value>(&config.unwrap_or_default()) {
+        Ok(config) => {
+            report.push(CONFIG_NAME, "config", Status::Ok, String::new());
+            Some(config)
+        }
+        Err(e) => {
+            report.push(CONFIG_NAME, "config", Status::Failed, e.to_string());
+            None
+        }
+    };
+    verify_signature(&mut reader, config.as_ref(), &mut report).await;
+    let metadata = match reader.metadata().await.map_err(|e| e.to_string())? {
+        Some(meta) => match serde_json::from_slice::<RepoMetadata>(&meta) {
+            Ok(meta) => {
+                report.push(META_NAME, "meta", Status::Ok, meta.tag_name.clone());
+                Some(meta)
+            }
+            Err(e) => {
+                report.push(META_NAME, "meta", Status::Failed, e.to_string());
+                None
+            }
+        },
+        None => None,
+    };
+
+    let mut hashed = HashMap::new();
+    let mut patches = HashMap::new();
+    let mut dir_patches = HashMap::new();
+    if let Some(metadata) = metadata.as_ref() {
+        for file in metadata.hashed.iter().flatten() {
+            if let Some(key) = hash_key(file.md5.as_ref(), file.xxh.as_ref()) {
+                hashed.insert(key, file);
+            }
+        }
+        for patch in metadata.patches.iter().flatten() {
+            let from = hash_key(patch.from.md5.as_ref(), patch.from.xxh.as_ref());
+            let to = hash_key(patch.to.md5.as_ref(), patch.to.xxh.as_ref());
+            if let (Some(from), Some(to)) = (from, to) {
+                patches.insert(format!("{from}_{to}"), patch);
+            }
+        }
+        for dir_patch in metadata.dir_patches.iter().flatten() {
+            dir_patches.insert(dir_patch.name.clone(), dir_patch);
+        }
+    }
+    let indexed: HashSet<String> = match reader.index().await {
+        Ok(Some((_, index))) => index.into_iter().map(|e| e.name).collect(),
+        _ => HashSet::new(),
+    };
+
+    // 字典原样存储，先校验并读出，供之后的文件解压
+    let dict_info = metadata.as_ref().and_then(|m| m.dictionary.as_ref());
+    let dict_key = dict_info.and_then(|d| hash_key(d.md5.as_ref(), d.xxh.as_ref()));
+    let mut dictionary = None;
+    if let (Some(info), Some(key)) = (dict_info, dict_key.as_ref()) {
+        match reader.find(key).cloned() {
+            Some(entry) => {
+                let mut hasher = HashWriter::new();
+                let result =
+                    decompress_entry(&mut reader, &entry, Codec::Raw, None, &mut hasher, true)
+                        .await
+                        .and_then(|data| {
+                            hasher.check(info.size, info.md5.as_ref(), info.xxh.as_ref())?;
+                            Ok(data)
+                        });
+                match result {
+                    Ok(data) => {
+                        report.push(key, "dict", Status::Ok, format!("{} bytes", data.len()));
+                        dictionary = Some(data);
+                    }
+                    Err(e) => report.push(key, "dict", Status::Failed, e),
+                }
+            }
+            None if !indexed.is_empty() => report.push(
+                key,
+                "dict",
+                Status::Failed,
+                "dictionary is missing from the pack".to_string(),
+            ),
+            None => {}
+        }
+    }
+
+    let entries = reader.entries().to_vec();
+    for entry in entries.iter() {
+        if entry.name.starts_with('\0') || dict_key.as_ref() == Some(&entry.name) {
+            continue;
+        }
+        let (kind, file_name, result) = if let Some(file) = hashed.get(&entry.name) {
+            let result = verify_file(&mut reader, entry, file, dictionary.as_deref())
+                .await
+                .map(|msg| (Status::Ok, msg));
+            ("file", Some(file.file_name.clone()), result)
+        } else if let Some(patch) = patches.get(&entry.name) {
+            let result = verify_patch(&mut reader, entry, patch, args.old_dir.as_deref()).await;
+            ("patch", Some(patch.file_name.clone()), result)
+        } else if let Some(dir_patch) = dir_patches.get(&entry.name) {
+            let result =
+                verify_dir_patch(&mut reader, entry, dir_patch, args.old_dir.as_deref()).await;
+            ("dirpatch", None, result)
+        } else if indexed.contains(&entry.name) {
+            // 无元数据时只检查能否解压
+            let mut hasher = HashWriter::new();
+            let result =
+                decompress_entry(&mut reader, entry, Codec::Zstd, None, &mut hasher, false)
+                    .await
+                    .map(|_| {
+                        (
+                            Status::Ok,
+                            format!("{} bytes, not in metadata", hasher.size),
+                        )
+                    });
+            ("file", None, result)
+        } else {
+            // append 追加的运行库等文件，不在索引中
+            let result = Ok((Status::Skipped, "appended, not in index".to_string()));
+            ("other", None, result)
+        };
+        let (status, message) = result.unwrap_or_else(|e| (Status::Failed, e));
+        report.push(&entry.name, kind, status, message);
+        report.entries.last_mut().unwrap().file_name = file_name;
+    }
+
+    // 有索引时，元数据中的文件必须都已打包
+    if !indexed.is_empty() {
+        for (key, file) in hashed.iter() {
+            if reader.find(key).is_none() {
+                report.push(
+                    key,
+                    "file",
+                    Status::Failed,
+                    format!("{} is missing from the pack", file.file_name),
+                );
+            }
+        }
+    }
+
+    report.ok = report.entries.iter().all(|e| e.status != Status::Failed);
+    Ok(report)
+}
+
+fn print_report(report: &VerifyReport) {
+    println!(
+        "{:<8} {:<8} {:<32} {:<20} MESSAGE",
+        "STATUS", "KIND", "NAME", "FILE NAME"
+    );
+    println!("{}", "-".repeat(100));
+    for entry in report.entries.iter() {
+        println!(
+            "{:<8} {:<8} {:<32} {:<20} {}",
+            entry.status.to_string(),
+            entry.kind,
+            entry.name,
+            entry.file_name.as_deref().unwrap_or("-"),
+            entry.message
+        );
+    }
+    let failed = report
+        .entries
+        .iter()
+        .filter(|e| e.status == Status::Failed)
+        .count();
+    if report.ok {
+        println!("Verify passed: {} entries checked", report.entries.len());
+    } else {
+        println!(
+            "Verify failed: {failed} of {} entries",
+            report.entries.len()
+        );
+    }
+}
+
+pub async fn verify_cli(args: VerifyArgs) -> bool {
+    let report = match verify(&args).await {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Failed to verify: {e}");
+            return false;
+        }
+    };
+    print_report(&report);
+    if let Some(json_path) = args.json.as_ref() {
+        let json = serde_json::to_string_pretty(&report).unwrap();
+        if let Err(e) = tokio::fs::write(json_path, json).await {
+            eprintln!("Failed to write report {}: {e}", json_path.display());
+            return false;
+        }
+    }
+    report.ok
+}
@@ -39,15 +39,15 @@ pub struct Dfs2Metadata {
 pub struct Dfs2Data {
     pub index: std::collections::HashMap<String, Dfs2FileInfo>,
     pub metadata: serde_json::Value,
-    pub installer_end: u32,
+    pub installer_end: u64,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Dfs2FileInfo {
     pub name: String,
-    pub offset: u32,
-    pub raw_offset: u32,
-    pub size: u32,
+    pub offset: u64,
+    pub raw_offset: u64,
+    pub size: u64,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -104,10 +104,10 @@ pub struct InsightItem {
     pub url: String,
     pub ttfb: u32, // 首字节时间(ms)
     pub time: u32, // 纯下载时间(ms) = 总时间 - TTFB
-    pub size: u32, // 实际下载字节数
+    pub size: u64, // 实际下载字节数
     pub error: Option<String>,
     #[serde(default)]
-    pub range: Vec<(u32, u32)>, // HTTP Range请求范围
+    pub range: Vec<(u64, u64)>, // HTTP Range请求范围
     #[serde(default)]
     pub mode: Option<String>, // 安装模式
 }
@@ -3,20 +3,23 @@ use bytes::Bytes;
 use fmmap::tokio::AsyncMmapFileExt;
 use futures::Stream;
 use futures::TryStreamExt;
+use kachina_pack::{ChecksumReader, DrainReader};
 use serde::Serialize;
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     os::windows::fs::MetadataExt,
     path::{Path, PathBuf},
     pin::Pin,
     sync::{
         atomic::{AtomicU64, Ordering},
-        Arc, Mutex,
+        Arc, Mutex, RwLock,
     },
     task::{Context as TaskContext, Poll},
     time::{Duration, Instant},
 };
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf};
+use tokio::io::{
+    AsyncBufRead, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf,
+};
 
 use crate::{
     dfs::InsightItem,
@@ -54,7 +57,7 @@ pub struct ClassifiedNetworkError {
     pub original_error: Box<dyn std::error::Error + Send + Sync>,
     pub context: String,
     pub url: String,
-    pub range: Vec<(u32, u32)>,
+    pub range: Vec<(u64, u64)>,
 }
 
 impl ClassifiedNetworkError {
@@ -62,7 +65,7 @@ impl ClassifiedNetworkError {
         error_type: NetworkErrorType,
         original_error: Box<dyn std::error::Error + Send + Sync>,
         url: String,
-        range: Vec<(u32, u32)>,
+        range: Vec<(u64, u64)>,
     ) -> Self {
         let context = match &error_type {
             NetworkErrorType::ConnectionReset => "ERR_CONNECTION_RESET",
@@ -172,7 +175,7 @@ pub struct NetworkInsightStream<S> {
     network_bytes: Arc<AtomicU64>,
     response_received_time: Instant,
     url: String,            // 新增：保存URL用于错误处理
-    range: Vec<(u32, u32)>, // 新增：保存Range用于错误处理
+    range: Vec<(u64, u64)>, // 新增：保存Range用于错误处理
 
     // Download stall detection fields
     content_length: Option<u64>,           // Total file size
@@ -204,7 +207,7 @@ impl<S: AsyncRead + Unpin> AsyncRead for NetworkInsightStream<S> {
 
                     // 更新insight（使用try_lock避免阻塞）
                     if let Ok(mut insight) = self.insight.try_lock() {
-                        insight.size = total_bytes as u32;
+                        insight.size = total_bytes;
                         insight.time = self.response_received_time.elapsed().as_millis() as u32;
                     }
 
@@ -214,7 +217,7 @@ impl<S: AsyncRead + Unpin> AsyncRead for NetworkInsightStream<S> {
                         if let Ok(mut insight) = self.insight.try_lock() {
                             insight.error = Some(classified_error.context.clone());
                             insight.time = self.response_received_time.elapsed().as_millis() as u32;
-                            insight.size = self.network_bytes.load(Ordering::Relaxed) as u32;
+                            insight.size = self.network_bytes.load(Ordering::Relaxed);
                         }
                         return Poll::Ready(Err(classified_error.into()));
                     }
@@ -239,7 +242,7 @@ impl<S: AsyncRead + Unpin> AsyncRead for NetworkInsightStream<S> {
                     if let Ok(mut insight) = self.insight.try_lock() {
                         insight.error = Some(classified_error.context.clone());
                         insight.time = self.response_received_time.elapsed().as_millis() as u32;
-                        insight.size = self.network_bytes.load(Ordering::Relaxed) as u32;
+                        insight.size = self.network_bytes.load(Ordering::Relaxed);
                     }
 
                     // 返回分类后的网络错误
@@ -249,7 +252,7 @@ impl<S: AsyncRead + Unpin> AsyncRead for NetworkInsightStream<S> {
                     if let Ok(mut insight) = self.insight.try_lock() {
                         insight.error = Some(e.to_string());
                         insight.time = self.response_received_time.elapsed().as_millis() as u32;
-                        insight.size = self.network_bytes.load(Ordering::Relaxed) as u32;
+                        insight.size = self.network_bytes.load(Ordering::Relaxed);
                     }
                     Poll::Ready(Err(e))
                 }
@@ -280,7 +283,7 @@ where
 
                 // 更新insight
                 if let Ok(mut insight) = self.insight.try_lock() {
-                    insight.size = total_bytes as u32;
+                    insight.size = total_bytes;
                     insight.time = self.response_received_time.elapsed().as_millis() as u32;
                 }
 
@@ -316,7 +319,7 @@ where
                         insight.error = Some(io_error.to_string());
                     }
                     insight.time = self.response_received_time.elapsed().as_millis() as u32;
-                    insight.size = self.network_bytes.load(Ordering::Relaxed) as u32;
+                    insight.size = self.network_bytes.load(Ordering::Relaxed);
                 }
                 // 错误继续向上传播，在被转换为 AsyncRead 时会得到正确处理
             }
@@ -324,7 +327,7 @@ where
                 // 流结束，最终更新时间
                 if let Ok(mut insight) = self.insight.try_lock() {
                     insight.time = self.response_received_time.elapsed().as_millis() as u32;
-                    insight.size = self.network_bytes.load(Ordering::Relaxed) as u32;
+                    insight.size = self.network_bytes.load(Ordering::Relaxed);
                 }
             }
             _ => {}
@@ -337,7 +340,7 @@ impl<S> NetworkInsightStream<S> {
     pub fn new(
         stream: S,
         url: String,
-        range: Vec<(u32, u32)>,
+        range: Vec<(u64, u64)>,
         request_start_time: Instant,
         response_received_time: Instant,
     ) -> Self {
@@ -354,7 +357,7 @@ impl<S> NetworkInsightStream<S> {
     pub fn new_with_detection(
         stream: S,
         url: String,
-        range: Vec<(u32, u32)>,
+        range: Vec<(u64, u64)>,
         request_start_time: Instant,
         response_received_time: Instant,
         content_length: Option<u64>,
@@ -470,7 +473,7 @@ impl<S> NetworkInsightStream<S> {
                 url: "unknown".to_string(),
                 ttfb: 0,
                 time: 0,
-                size: self.network_bytes.load(Ordering::Relaxed) as u32,
+                size: self.network_bytes.load(Ordering::Relaxed),
                 error: Some("Failed to lock insight".to_string()),
                 range: vec![],
                 mode: None,
@@ -616,11 +619,53 @@ pub async fn ensure_dir(path: String) -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+lazy_static::lazy_static! {
+    // 字典压缩的文件解压时使用，由 LoadDictionary 按条目名加载
+    static ref ZSTD_DICTIONARIES: RwLock<HashMap<String, Arc<Vec<u8>>>> =
+        RwLock::new(HashMap::new());
+}
+
+pub fn set_zstd_dictionary(name: String, data: Vec<u8>) {
+    ZSTD_DICTIONARIES
+        .write()
+        .unwrap()
+        .insert(name, Arc::new(data));
+}
+
+/// `dict` names a dictionary loaded with `set_zstd_dictionary`, for entries
+/// compressed with one.
+pub fn zstd_decoder(
+    reader: impl AsyncBufRead + Unpin + Send + 'static,
+    dict: Option<&str>,
+) -> Result<Box<dyn AsyncRead + Unpin + Send>, anyhow::Error> {
+    let decoder = match dict {
+        Some(dict) => {
+            let dictionary = ZSTD_DICTIONARIES
+                .read()
+                .unwrap()
+                .get(dict)
+                .cloned()
+                .context("DICT_NOT_LOADED")?;
+            TokioZstdDecoder::with_dict(reader, &dictionary).context("DICT_INVALID")?
+        }
+        None => TokioZstdDecoder::new(reader),
+    };
+    // read past the end of the frame so a ChecksumReader below gets to check
+    Ok(Box::new(DrainReader::new(
+        decoder,
+        TokioZstdDecoder::get_mut,
+    )))
+}
+
+/// `checksum` is the entry name and the checksum of its stored bytes, checked
+/// below the decoder so a damaged response fails before it is fully written.
 pub async fn create_http_stream(
     url: &str,
     offset: usize,
     size: usize,
     skip_decompress: bool,
+    dict: Option<&str>,
+    checksum: Option<(&str, u64)>,
 ) -> Result<
     (
         Box<dyn AsyncRead + Unpin + Send>,
@@ -656,7 +701,7 @@ pub async fn create_http_stream(
                 size: 0,
                 error: Some(format!("{:#}", e)),
                 range: if has_range {
-                    vec![(offset as u32, (offset + size - 1) as u32)]
+                    vec![(offset as u64, (offset + size - 1) as u64)]
                 } else {
                     vec![]
                 },
@@ -676,7 +721,7 @@ pub async fn create_http_stream(
             size: 0,
             error: Some(format!("HTTP status error: {}", code)),
             range: if has_range {
-                vec![(offset as u32, (offset + size - 1) as u32)]
+                vec![(offset as u64, (offset + size - 1) as u64)]
             } else {
                 vec![]
             },
@@ -704,7 +749,7 @@ pub async fn create_http_stream(
         reader,
         crate::utils::url::sanitize_url_for_logging(url),
         if has_range {
-            vec![(offset as u32, (offset + size - 1) as u32)]
+            vec![(offset as u64, (offset + size - 1) as u64)]
         } else {
             vec![]
         },
@@ -714,25 +759,41 @@ pub async fn create_http_stream(
     );
 
     let insight_handle = insight_stream.get_insight_handle();
+    let stream: Box<dyn AsyncRead + Unpin + Send> = match checksum {
+        Some((name, expected)) => {
+            let expected_size = if has_range {
+                size as u64
+            } else {
+                content_length
+            };
+            Box::new(ChecksumReader::new(
+                insight_stream,
+                name,
+                expected,
+                expected_size,
+            ))
+        }
+        None => Box::new(insight_stream),
+    };
 
     if skip_decompress {
-        Ok((Box::new(insight_stream), content_length, insight_handle))
+        Ok((stream, content_length, insight_handle))
     } else {
         // 在NetworkInsightStream外层套一个BufReader，然后再解压缩
-        let buf_reader = BufReader::new(insight_stream);
-        let decompressed = TokioZstdDecoder::new(buf_reader);
+        let buf_reader = BufReader::new(stream);
+        let decompressed = zstd_decoder(buf_reader, dict)?;
         // ✅ 关键：即使被解压缩包装，insight_handle仍然可用！
-        Ok((Box::new(decompressed), content_length, insight_handle))
+        Ok((decompressed, content_length, insight_handle))
     }
 }
 
-fn parse_range_string(range: &str) -> Vec<(u32, u32)> {
+fn parse_range_string(range: &str) -> Vec<(u64, u64)> {
     range
         .split(',')
         .filter_map(|part| {
             let mut split = part.trim().split('-');
-            let start = split.next()?.parse::<u32>().ok()?;
-            let end = split.next()?.parse::<u32>().ok()?;
+            let start = split.next()?.parse::<u64>().ok()?;
+            let end = split.next()?.parse::<u64>().ok()?;
             Some((start, end))
         })
         .collect()
@@ -835,14 +896,24 @@ pub async fn create_local_stream(
     offset: usize,
     size: usize,
     skip_decompress: bool,
+    dict: Option<&str>,
+    checksum: Option<(&str, u64)>,
 ) -> Result<Box<dyn tokio::io::AsyncRead + Unpin + std::marker::Send>, anyhow::Error> {
     let mmap_file = mmap().await;
     let reader = mmap_file.range_reader(offset, size).context("MMAP_ERR")?;
+    let reader: Box<dyn AsyncBufRead + Unpin + Send> = match checksum {
+        Some((name, expected)) => Box::new(BufReader::new(ChecksumReader::new(
+            reader,
+            name,
+            expected,
+            size as u64,
+        ))),
+        None => Box::new(reader),
+    };
     if skip_decompress {
         return Ok(Box::new(reader));
     }
-    let decoder = TokioZstdDecoder::new(reader);
-    Ok(Box::new(decoder))
+    zstd_decoder(reader, dict)
 }
 
 pub async fn prepare_target(target: &str) -> Result<Option<PathBuf>, anyhow::Error> {
@@ -875,6 +946,19 @@ pub async fn prepare_target(target: &str) -> Result<Option<PathBuf>, anyhow::Err
     Ok(override_path)
 }
 
+/// Undo `prepare_target` when the new file was not installed, so the running
+/// exe is back at `target` and no longer deleted on exit.
+pub async fn restore_target(target: &str, override_path: &Path) -> Result<(), anyhow::Error> {
+    tokio::fs::rename(override_path, target)
+        .await
+        .context("RESTORE_EXE_ERR")?;
+    let mut delete_on_exit = DELETE_SELF_ON_EXIT_PATH.write().unwrap();
+    if delete_on_exit.as_deref() == Some(&*override_path.to_string_lossy()) {
+        delete_on_exit.take();
+    }
+    Ok(())
+}
+
 pub async fn create_target_file(target: &str) -> Result<impl AsyncWrite, anyhow::Error> {
     let target_file = tokio::fs::File::create(target)
         .await
@@ -942,6 +1026,7 @@ pub async fn progressed_hpatch<R, F>(
     source: R,
     target: &str,
     diff_size: usize,
+    new_size: usize,
     on_progress: F,
     override_old_path: Option<PathBuf>,
     mut insight: Option<InsightItem>,
@@ -989,6 +1074,7 @@ where
             diff_size,
             old_target_file,
             target_size.file_size() as usize,
+            new_size,
         )
     })
     .await
@@ -1029,6 +1115,12 @@ where
         tokio::fs::remove_file(new_target)
             .await
             .context("REMOVE_NEW_TARGET_ERR")?;
+        if res == hpatch_sys::PATCH_SIZE_MISMATCH {
+            return Err(anyhow::Error::new(std::io::Error::other(format!(
+                "Patch for {target_ori} does not produce {new_size} bytes"
+            ))))
+            .context("PATCH_SIZE_MISMATCH_ERR");
+        }
         return Err(anyhow::Error::new(std::io::Error::other(format!(
             "Patch failed with code {res}"
         ))))
@@ -1037,37 +1129,208 @@ where
     // 更新网络下载统计信息
     if let Some(ref mut insight) = insight {
         insight.time = download_start.elapsed().as_millis() as u32;
-        insight.size = diff_size as u32;
+        insight.size = diff_size as u64;
     }
 
     Ok((diff_size, insight))
 }
 
-pub async fn verify_hash(
-    target: &str,
-    md5: Option<String>,
-    xxh: Option<String>,
-) -> Result<(), anyhow::Error> {
-    let alg = if md5.is_some() {
-        "md5"
-    } else if xxh.is_some() {
-        "xxh"
-    } else {
+// 按顺序拼接多个文件，作为目录补丁的旧数据随机读取
+struct ConcatReader {
+    // (文件, 在拼接流中的起始位置, 大小)
+    files: Vec<(std::fs::File, u64, u64)>,
+    pos: u64,
+    size: u64,
+}
+
+impl std::io::Read for ConcatReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        use std::io::Seek;
+        let Some((file, start, size)) = self
+            .files
+            .iter_mut()
+            .find(|(_, start, size)| self.pos < *start + *size)
+        else {
+            return Ok(0);
+        };
+        let offset = self.pos - *start;
+        let len = ((*size - offset) as usize).min(buf.len());
+        file.seek(std::io::SeekFrom::Start(offset))?;
+        let read = file.read(&mut buf[..len])?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl std::io::Seek for ConcatReader {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            std::io::SeekFrom::Start(pos) => Some(pos),
+            std::io::SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            std::io::SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        self.pos = pos.ok_or_else(|| std::io::Error::other("invalid seek"))?;
+        Ok(self.pos)
+    }
+}
+
+// 把补丁输出按成员大小依次写入各个文件
+struct SplitWriter {
+    files: std::collections::VecDeque<(std::io::BufWriter<std::fs::File>, u64)>,
+}
+
+impl std::io::Write for SplitWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        while let Some((file, remaining)) = self.files.front_mut() {
+            if *remaining > 0 {
+                let len = (*remaining as usize).min(buf.len());
+                let written = file.write(&buf[..len])?;
+                *remaining -= written as u64;
+                return Ok(written);
+            }
+            file.flush()?;
+            self.files.pop_front();
+        }
+        Err(std::io::Error::other("patched data exceeds target size"))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        for (file, _) in self.files.iter_mut() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+// 应用目录补丁：old 为旧文件及其大小，new 为新文件的输出路径及其大小，都按补丁中的成员顺序排列。
+// 成功时新文件全部写入 new 中的路径，由调用方校验后替换；失败时删除已写入的文件
+pub async fn progressed_dir_hpatch<R, F>(
+    source: R,
+    old: Vec<(PathBuf, u64)>,
+    new: Vec<(PathBuf, u64)>,
+    diff_size: usize,
+    on_progress: F,
+) -> Result<usize, anyhow::Error>
+where
+    R: AsyncRead + std::marker::Unpin + Send + 'static,
+    F: Fn(usize) + Send + 'static,
+{
+    let mut downloaded = 0;
+    let decoder = ReadWithCallback {
+        reader: source,
+        callback: move |chunk| {
+            downloaded += chunk;
+            on_progress(downloaded);
+        },
+    };
+    let mut old_files = Vec::with_capacity(old.len());
+    let mut old_size = 0;
+    for (path, size) in old {
+        let file = std::fs::File::open(&path).context("OPEN_TARGET_ERR")?;
+        let actual = file.metadata().context("GET_TARGET_SIZE_ERR")?.len();
+        if actual != size {
+            return Err(anyhow::Error::new(std::io::Error::other(format!(
+                "File {} size mismatch: expected {size}, got {actual}",
+                path.display()
+            ))))
+            .context("DIR_PATCH_SOURCE_ERR");
+        }
+        old_files.push((file, old_size, size));
+        old_size += size;
+    }
+    let old_reader = ConcatReader {
+        files: old_files,
+        pos: 0,
+        size: old_size,
+    };
+    let new_size: u64 = new.iter().map(|(_, size)| size).sum();
+    let mut new_files = std::collections::VecDeque::with_capacity(new.len());
+    let mut result = Ok(());
+    for (path, size) in new.iter() {
+        match std::fs::File::create(path) {
+            Ok(file) => new_files.push_back((std::io::BufWriter::new(file), *size)),
+            Err(e) => {
+                result = Err(anyhow::Error::new(e).context("CREATE_NEW_TARGET_ERR"));
+                break;
+            }
+        }
+    }
+    if result.is_ok() {
+        let diff_file = tokio_util::io::SyncIoBridge::new(decoder);
+        let res = tokio::task::spawn_blocking(move || {
+            let mut writer = SplitWriter { files: new_files };
+            let res = hpatch_sys::safe_patch_single_stream(
+                &mut writer,
+                diff_file,
+                diff_size,
+                old_reader,
+                old_size as usize,
+                new_size as usize,
+            );
+            // 补丁输出不足时，剩余的成员文件没有写满
+            match std::io::Write::flush(&mut writer) {
+                Ok(()) if writer.files.iter().all(|(_, remaining)| *remaining == 0) => res,
+                _ => 0,
+            }
+        })
+        .await
+        .context("RUN_HPATCH_ERR");
+        result = match res {
+            Ok(1) => Ok(()),
+            Ok(hpatch_sys::PATCH_SIZE_MISMATCH) => Err(anyhow::Error::new(std::io::Error::other(
+                format!("Dir patch does not produce {new_size} bytes"),
+            ))
+            .context("PATCH_SIZE_MISMATCH_ERR")),
+            Ok(res) => Err(anyhow::Error::new(std::io::Error::other(format!(
+                "Patch failed with code {res}"
+            )))
+            .context("PATCH_FAILED_ERR")),
+            Err(e) => Err(e),
+        };
+    }
+    if let Err(e) = result {
+        for (path, _) in new.iter() {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+        return Err(e);
+    }
+    Ok(diff_size)
+}
+
+// 文件的各种哈希，校验时只使用其中最强的一个
+#[derive(serde::Deserialize, Serialize, Clone, Debug, Default)]
+pub struct FileHashes {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub md5: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xxh: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blake3: Option<String>,
+}
+
+impl FileHashes {
+    pub fn strongest(&self) -> Option<(&'static str, &str)> {
+        // 按强度从高到低
+        [
+            ("blake3", &self.blake3),
+            ("sha256", &self.sha256),
+            ("md5", &self.md5),
+            ("xxh", &self.xxh),
+        ]
+        .into_iter()
+        .find_map(|(alg, hash)| hash.as_deref().map(|hash| (alg, hash)))
+    }
+}
+
+pub async fn verify_hash(target: &str, hashes: &FileHashes) -> Result<(), anyhow::Error> {
+    let Some((alg, expected)) = hashes.strongest() else {
         return Err(
             anyhow::Error::new(std::io::Error::other("No hash algorithm specified"))
                 .context("NO_HASH_ALGO_ERR"),
         );
     };
-    let expected = if let Some(md5) = md5 {
-        md5
-    } else if let Some(xxh) = xxh {
-        xxh
-    } else {
-        return Err(
-            anyhow::Error::new(std::io::Error::other("No hash data provided"))
-                .context("NO_HASH_DATA_ERR"),
-        );
-    };
     let hash = run_hash(alg, target).await.context("HASH_CHECK_ERR")?;
     if hash != expected {
         return Err(anyhow::Error::new(std::io::Error::other(format!(
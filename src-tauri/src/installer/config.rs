@@ -1,6 +1,9 @@
 use crate::{
     cli::arg::InstallArgs,
-    local::{get_config_from_embedded, get_embedded, mmap, Embedded},
+    local::{
+        get_config_from_embedded, get_embedded, get_trusted_keys, mmap, verify_embedded_signature,
+        Embedded,
+    },
     utils::{
         error::{return_ta_result, TAResult},
         uac::check_elevated,
@@ -8,9 +11,10 @@ use crate::{
     APP_BOOT_SIGNAL,
 };
 use anyhow::Context;
+use kachina_pack::{PackReader, VerifyingKey, SIGNATURE_ERR};
 use serde::Serialize;
 use serde_json::Value;
-use std::{collections::BTreeMap, path::Path};
+use std::{collections::BTreeMap, io::Cursor, path::Path};
 use tauri::State;
 
 #[derive(Serialize, Debug, Clone)]
@@ -42,14 +46,24 @@ pub async fn get_config_pre(
     let mut embedded_image = None;
     if scan_exe {
         let file = mmap().await;
-        if let Ok(embedded_files_res) = get_embedded(file).await {
-            if let Ok(res) = get_config_from_embedded(&embedded_files_res).await {
-                embedded_config = res.0;
-                enbedded_metadata = res.1;
-                embedded_index = res.2;
-                embedded_image = res.3;
+        // a pack with repeated reserved entries is rejected rather than read
+        // as if nothing was embedded
+        let embedded_files_res = get_embedded(file).await?;
+        if let Ok(res) = get_config_from_embedded(&embedded_files_res).await {
+            embedded_config = res.0;
+            enbedded_metadata = res.1;
+            embedded_index = res.2;
+            embedded_image = res.3;
+        }
+        embedded_files = Some(embedded_files_res);
+        // installed copies only keep the config, there is nothing else to trust
+        if enbedded_metadata.is_some() || embedded_index.is_some() {
+            if let Some(config) = embedded_config.as_ref() {
+                let trusted = get_trusted_keys(config)?;
+                if !trusted.is_empty() {
+                    verify_embedded_signature(&trusted).await?;
+                }
             }
-            embedded_files = Some(embedded_files_res);
         }
         #[cfg(debug_assertions)]
         {
@@ -153,6 +167,88 @@ impl InstallerConfig {
     }
 }
 
+async fn get_remote_trusted_keys() -> TAResult<Vec<VerifyingKey>> {
+    let embedded = get_embedded(mmap().await).await?;
+    let (config, ..) = get_config_from_embedded(&embedded).await?;
+    match config.as_ref() {
+        Some(config) => Ok(get_trusted_keys(config)?),
+        None => Ok(vec![]),
+    }
+}
+
+/// Config, metadata and index of a fetched pack header, the frontend uses only
+/// these so it never sees an entry that was not verified.
+#[derive(Serialize, Debug)]
+pub struct PackHeader {
+    pub config: Value,
+    pub metadata: Option<Value>,
+    pub index: Option<Vec<Embedded>>,
+}
+
+/// Parse a fetched pack header, the entries from `\0CONFIG` to `\0SIGN` that
+/// start at `base_end`, and check it against the trusted keys of the embedded
+/// config.
+#[tauri::command]
+pub async fn read_pack_header(data: Vec<u8>, base_end: usize) -> TAResult<PackHeader> {
+    let trusted = get_remote_trusted_keys().await?;
+    // rejects duplicate entries and anything after the last one
+    let mut reader = PackReader::walk(Cursor::new(data.as_slice()), 0)
+        .await
+        .context("REMOTE_INDEX_ERR")?;
+    if !trusted.is_empty() {
+        reader
+            .verify_signature(&trusted)
+            .await
+            .context("REMOTE_INDEX_ERR")?
+            .context(SIGNATURE_ERR)?;
+    }
+    let config = reader
+        .config()
+        .await
+        .context("REMOTE_INDEX_ERR")?
+        .ok_or_else(|| anyhow::anyhow!("No config"))
+        .context("REMOTE_INDEX_ERR")?;
+    let config = serde_json::from_slice(&config).context("REMOTE_INDEX_ERR")?;
+    let metadata = match reader.metadata().await.context("REMOTE_INDEX_ERR")? {
+        Some(metadata) => Some(serde_json::from_slice(&metadata).context("REMOTE_INDEX_ERR")?),
+        None => None,
+    };
+    let index = reader
+        .index()
+        .await
+        .context("REMOTE_INDEX_ERR")?
+        .map(|(_, entries)| {
+            entries
+                .into_iter()
+                .map(|entry| Embedded {
+                    name: entry.name,
+                    offset: base_end + entry.offset as usize,
+                    raw_offset: 0,
+                    size: entry.size as usize,
+                    checksum: entry.checksum,
+                })
+                .collect()
+        });
+    Ok(PackHeader {
+        config,
+        metadata,
+        index,
+    })
+}
+
+/// DFS2 and plugin sources serve metadata parsed on the server, without the
+/// header it was signed in, so they are refused once keys are configured.
+#[tauri::command]
+pub async fn check_parsed_metadata_source(kind: String) -> TAResult<()> {
+    if get_remote_trusted_keys().await?.is_empty() {
+        return Ok(());
+    }
+    return_ta_result(
+        format!("{kind} metadata has no signature to verify, use a direct or dfs source"),
+        SIGNATURE_ERR,
+    )
+}
+
 #[tauri::command]
 pub async fn get_installer_config(
     args: State<'_, InstallArgs>,
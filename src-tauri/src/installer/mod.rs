@@ -7,6 +7,8 @@ use windows::Win32::{
 use crate::utils::{
     dir::in_private_folder,
     error::{IntoTAResult, TAResult},
+    metadata::PatchInfo,
+    patch_graph::cheapest_patch_chain,
 };
 use anyhow::{Context, Result};
 
@@ -313,6 +315,36 @@ pub async fn confirm_dialog(title: String, message: String, window: WebviewWindo
     matches!(ret, rfd::MessageDialogResult::Yes)
 }
 
+#[derive(serde::Deserialize)]
+pub struct PatchChainQuery {
+    file_name: String,
+    from: String,
+    to: String,
+    size: u64,
+}
+
+// 为每个文件选出最省流量的补丁链，返回补丁在 patches 中的下标，完整下载更小时为 null
+#[tauri::command]
+pub fn plan_patch_chains(
+    patches: Vec<PatchInfo>,
+    files: Vec<PatchChainQuery>,
+    hash_key: String,
+) -> Vec<Option<Vec<usize>>> {
+    files
+        .iter()
+        .map(|file| {
+            cheapest_patch_chain(
+                &patches,
+                &file.file_name,
+                &file.from,
+                &file.to,
+                file.size,
+                &hash_key,
+            )
+        })
+        .collect()
+}
+
 #[tauri::command]
 pub fn log(data: String) {
     tracing::info!("{}", data);
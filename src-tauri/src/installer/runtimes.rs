@@ -79,7 +79,7 @@ pub async fn install_dotnet(
         .context("CREATE_TARGET_FILE_ERR")?;
     let (mut stream, len) = if offset.is_some() || size.is_some() {
         // runtime packed, just extract and run
-        let stream = create_local_stream(offset.unwrap(), size.unwrap(), true)
+        let stream = create_local_stream(offset.unwrap(), size.unwrap(), true, None, None)
             .await
             .context("RUNTIME_EXTRACT_ERR")?;
         tracing::info!(
@@ -110,7 +110,7 @@ pub async fn install_dotnet(
         }
         // get real download url
         let url = runtime.1.replace("$", &vernum);
-        let (stream, len, _insight) = create_http_stream(&url, 0, 0, true)
+        let (stream, len, _insight) = create_http_stream(&url, 0, 0, true, None, None)
             .await
             .context("RUNTIME_DOWNLOAD_ERR")?;
         (stream, len.try_into().unwrap_or(0))
@@ -182,7 +182,7 @@ pub async fn install_vcredist(
         .join(format!("Kachina.RuntimePackage.{tag}.exe"));
     let (mut stream, len) = if offset.is_some() || size.is_some() {
         // runtime packed, just extract and run
-        let stream = create_local_stream(offset.unwrap(), size.unwrap(), true)
+        let stream = create_local_stream(offset.unwrap(), size.unwrap(), true, None, None)
             .await
             .context("RUNTIME_EXTRACT_ERR")?;
         tracing::info!(
@@ -193,7 +193,7 @@ pub async fn install_vcredist(
         );
         (stream, size.unwrap())
     } else {
-        let (stream, len, _insight) = create_http_stream(url, 0, 0, true)
+        let (stream, len, _insight) = create_http_stream(url, 0, 0, true, None, None)
             .await
             .context("RUNTIME_DOWNLOAD_ERR")?;
         (stream, len.try_into().unwrap_or(0))
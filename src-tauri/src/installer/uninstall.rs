@@ -271,6 +271,7 @@ pub async fn clear_index_mark(path: &PathBuf) -> anyhow::Result<()> {
 
     // check ! and K
     let mark_pos = buffer.windows(2).position(|w| w == b"!K".as_ref());
+    let mut cleared = false;
     if let Some(mark_pos) = mark_pos {
         // check if equals !KachinaInstaller!
         let mark_str = "!KachinaInstaller!";
@@ -288,8 +289,16 @@ pub async fn clear_index_mark(path: &PathBuf) -> anyhow::Result<()> {
                 .write_all(&zero)
                 .await
                 .context("SELF_UPDATE_ERR")?;
+            cleared = true;
         }
     }
+    if !cleared {
+        // Authenticode-signed packs keep the header in the certificate table,
+        // fit it to the copied entries so the signature stays valid
+        kachina_pack::clear_certificate_header(&mut output_file)
+            .await
+            .context("SELF_UPDATE_ERR")?;
+    }
     // close file
     output_file.flush().await.context("SELF_UPDATE_ERR")?;
     output_file.sync_all().await.context("SELF_UPDATE_ERR")?;
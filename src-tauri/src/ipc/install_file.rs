@@ -2,17 +2,21 @@ use crate::{
     dfs::InsightItem,
     fs::{
         create_http_stream, create_local_stream, create_multi_http_stream, create_target_file,
-        prepare_target, progressed_copy, progressed_hpatch, verify_hash,
+        prepare_target, progressed_copy, progressed_dir_hpatch, progressed_hpatch, restore_target,
+        set_zstd_dictionary, verify_hash, zstd_decoder, FileHashes,
+    },
+    utils::{
+        error::{IntoTAResult, TAResult},
+        hash::run_hash,
     },
-    utils::error::{IntoTAResult, TAResult},
 };
 
-use anyhow::Result;
-use async_compression::tokio::bufread::ZstdDecoder as TokioZstdDecoder;
+use anyhow::{Context, Result};
 use futures::TryStreamExt;
+use kachina_pack::ChecksumReader;
 use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
-use tokio::io::{AsyncReadExt, BufReader};
+use tokio::io::{AsyncBufRead, AsyncReadExt, BufReader};
 use tracing::{info, warn};
 
 fn default_as_false() -> bool {
@@ -46,6 +50,8 @@ fn should_decompress_chunk(args: &InstallFileArgs) -> bool {
                 skip_decompress, ..
             } => !skip_decompress,
         },
+        // Patch chains and dir patches are never installed as a chunk
+        InstallFileMode::PatchChain { .. } | InstallFileMode::DirPatch { .. } => true,
     }
 }
 
@@ -53,6 +59,9 @@ fn should_decompress_chunk(args: &InstallFileArgs) -> bool {
 pub struct InstallResult {
     pub bytes_transferred: usize,
     pub insight: Option<InsightItem>,
+    // 补丁源文件不符，已改为完整下载
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub source_mismatch: bool,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
@@ -64,41 +73,141 @@ enum InstallFileSource {
         size: usize,
         #[serde(default = "default_as_false")]
         skip_decompress: bool,
+        #[serde(default)]
+        checksum: Option<String>,
+        // 使用字典压缩时为字典的条目名
+        #[serde(default)]
+        dict: Option<String>,
     },
     Local {
         offset: usize,
         size: usize,
         #[serde(default = "default_as_false")]
         skip_decompress: bool,
+        #[serde(default)]
+        checksum: Option<String>,
+        // 使用字典压缩时为字典的条目名
+        #[serde(default)]
+        dict: Option<String>,
     },
 }
 
+// Checksum of the stored bytes from a v3 index, sent as hex by the frontend
+fn parse_checksum(checksum: Option<&String>) -> Result<Option<u64>> {
+    checksum
+        .map(|c| u64::from_str_radix(c, 16).context("INVALID_CHECKSUM"))
+        .transpose()
+}
+
+// Helper function to extract the entry checksum from InstallFileArgs
+fn get_chunk_checksum(args: &InstallFileArgs) -> Result<Option<u64>> {
+    let source = match &args.mode {
+        InstallFileMode::Direct { source } | InstallFileMode::Patch { source, .. } => source,
+        InstallFileMode::HybridPatch { diff, .. } => diff,
+        InstallFileMode::PatchChain { .. } | InstallFileMode::DirPatch { .. } => return Ok(None),
+    };
+    match source {
+        InstallFileSource::Url { checksum, .. } | InstallFileSource::Local { checksum, .. } => {
+            parse_checksum(checksum.as_ref())
+        }
+    }
+}
+
+// Helper function to extract the dictionary name from InstallFileArgs
+fn get_chunk_dict(args: &InstallFileArgs) -> Option<&str> {
+    let source = match &args.mode {
+        InstallFileMode::Direct { source } | InstallFileMode::Patch { source, .. } => source,
+        InstallFileMode::HybridPatch { diff, .. } => diff,
+        InstallFileMode::PatchChain { .. } | InstallFileMode::DirPatch { .. } => return None,
+    };
+    match source {
+        InstallFileSource::Url { dict, .. } | InstallFileSource::Local { dict, .. } => {
+            dict.as_deref()
+        }
+    }
+}
+
+// Reject a damaged chunk before anything is written to disk
+fn verify_chunk_checksum(args: &InstallFileArgs, data: &[u8]) -> Result<()> {
+    if let Some(expected) = get_chunk_checksum(args)? {
+        kachina_pack::verify_checksum(&args.target, data, expected)?;
+    }
+    Ok(())
+}
+
 #[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
 #[serde(tag = "type")]
 enum InstallFileMode {
     Direct {
         source: InstallFileSource,
     },
+    // new_size 为补丁输出的文件大小，与补丁不符时报错而不是写出截断的文件
+    // from 为补丁源文件的哈希，不符时改用 fallback 完整下载
     Patch {
         source: InstallFileSource,
         diff_size: usize,
+        new_size: usize,
+        #[serde(default)]
+        from: FileHashes,
+        #[serde(default)]
+        fallback: Option<InstallFileSource>,
     },
     HybridPatch {
         diff: InstallFileSource,
         source: InstallFileSource,
+        new_size: usize,
+        #[serde(default)]
+        from: FileHashes,
+        #[serde(default)]
+        fallback: Option<InstallFileSource>,
+    },
+    // 依次应用多个补丁，跨越多个版本更新
+    PatchChain {
+        steps: Vec<PatchStep>,
+    },
+    // 一个补丁更新一组文件，target 为安装目录，哈希逐个成员校验
+    DirPatch {
+        source: InstallFileSource,
+        diff_size: usize,
+        files: Vec<DirPatchFile>,
     },
 }
 
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+struct PatchStep {
+    source: InstallFileSource,
+    diff_size: usize,
+    new_size: usize,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+struct DirPatchFile {
+    target: String,
+    old_size: u64,
+    #[serde(default)]
+    from: FileHashes,
+    size: u64,
+    #[serde(flatten)]
+    hashes: FileHashes,
+}
+
 #[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
 pub struct InstallFileArgs {
     mode: InstallFileMode,
     target: String,
-    md5: Option<String>,
-    xxh: Option<String>,
+    #[serde(flatten)]
+    hashes: FileHashes,
     clear_installer_index_mark: Option<bool>,
 }
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct LoadDictionaryArgs {
+    name: String,
+    source: InstallFileSource,
+}
 async fn create_stream_by_source(
     source: InstallFileSource,
+    target: &str,
 ) -> Result<(
     Box<dyn tokio::io::AsyncRead + Unpin + std::marker::Send>,
     Option<Arc<Mutex<InsightItem>>>,
@@ -109,21 +218,224 @@ async fn create_stream_by_source(
             offset,
             size,
             skip_decompress,
+            checksum,
+            dict,
         } => {
-            let (stream, _content_length, insight_handle) =
-                create_http_stream(&url, offset, size, skip_decompress).await?;
+            let checksum = parse_checksum(checksum.as_ref())?;
+            let (stream, _content_length, insight_handle) = create_http_stream(
+                &url,
+                offset,
+                size,
+                skip_decompress,
+                dict.as_deref(),
+                checksum.map(|c| (target, c)),
+            )
+            .await?;
             Ok((stream, Some(insight_handle)))
         }
         InstallFileSource::Local {
             offset,
             size,
             skip_decompress,
-        } => Ok((
-            create_local_stream(offset, size, skip_decompress).await?,
-            None,
-        )),
+            checksum,
+            dict,
+        } => {
+            let checksum = parse_checksum(checksum.as_ref())?;
+            Ok((
+                create_local_stream(
+                    offset,
+                    size,
+                    skip_decompress,
+                    dict.as_deref(),
+                    checksum.map(|c| (target, c)),
+                )
+                .await?,
+                None,
+            ))
+        }
     }
 }
+
+// 字典需要在安装使用它的文件之前加载，安装文件时按条目名引用
+pub async fn ipc_load_dictionary(args: LoadDictionaryArgs) -> TAResult<serde_json::Value> {
+    let (mut stream, _insight_handle) = create_stream_by_source(args.source, &args.name).await?;
+    let mut data = Vec::new();
+    stream
+        .read_to_end(&mut data)
+        .await
+        .context("DICT_READ_ERR")?;
+    let size = data.len();
+    set_zstd_dictionary(args.name, data);
+    Ok(serde_json::json!(size))
+}
+
+// 完整下载文件，补丁源文件不符时也用于回退
+async fn install_direct(
+    source: InstallFileSource,
+    target: &str,
+    hashes: &FileHashes,
+    clear_index_mark: bool,
+    progress_noti: impl Fn(usize),
+) -> TAResult<InstallResult> {
+    let (stream, insight_handle) = create_stream_by_source(source, target).await?;
+    let bytes_transferred =
+        match crate::fs::progressed_copy(stream, create_target_file(target).await?, progress_noti)
+            .await
+        {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                if let Some(handle) = &insight_handle {
+                    if let Ok(mut insight) = handle.lock() {
+                        insight.error = Some(e.to_string());
+                    }
+                    return Err(crate::utils::error::TACommandError::with_insight_handle(
+                        e,
+                        handle.clone(),
+                    ));
+                } else {
+                    return Err(crate::utils::error::TACommandError::new(e));
+                }
+            }
+        };
+
+    // 获取最终的insight
+    let final_insight = if let Some(handle) = insight_handle {
+        if let Ok(insight) = handle.lock() {
+            Some(insight.clone())
+        } else {
+            None
+        }
+    } else {
+        None
+    };
+
+    if hashes.strongest().is_some() {
+        // 如果需要清理installer索引标记，先清理再进行hash校验
+        if clear_index_mark {
+            info!("Clearing installer index mark for: {}", target);
+            if let Err(e) =
+                crate::installer::uninstall::clear_index_mark(&std::path::PathBuf::from(target))
+                    .await
+                    .into_ta_result()
+            {
+                warn!("Failed to clear index mark: {:?}", e);
+                return Err(e);
+            }
+            info!("Index mark cleared successfully");
+        }
+        verify_hash(target, hashes).await?;
+    }
+
+    Ok(InstallResult {
+        bytes_transferred,
+        insight: final_insight,
+        source_mismatch: false,
+    })
+}
+
+// 未提供源文件哈希时不做校验，源文件缺失视为不符
+async fn patch_source_matches(path: &str, from: &FileHashes) -> Result<bool> {
+    let Some((alg, expected)) = from.strongest() else {
+        return Ok(true);
+    };
+    if !tokio::fs::try_exists(path).await.unwrap_or(false) {
+        return Ok(false);
+    }
+    let hash = run_hash(alg, path).await.context("HASH_CHECK_ERR")?;
+    Ok(hash == expected)
+}
+
+fn source_mismatch_error(path: &str) -> anyhow::Error {
+    anyhow::Error::new(std::io::Error::other(format!(
+        "Patch source {path} does not match the expected hash"
+    )))
+    .context("SOURCE_MISMATCH_ERR")
+}
+
+async fn install_fallback(
+    fallback: Option<InstallFileSource>,
+    target: &str,
+    hashes: &FileHashes,
+    clear_index_mark: bool,
+    progress_noti: impl Fn(usize),
+) -> TAResult<InstallResult> {
+    let Some(fallback) = fallback else {
+        return Err(source_mismatch_error(target).into());
+    };
+    warn!(
+        "Patch source of {} mismatch, falling back to full download",
+        target
+    );
+    let mut result =
+        install_direct(fallback, target, hashes, clear_index_mark, progress_noti).await?;
+    result.source_mismatch = true;
+    Ok(result)
+}
+
+// 补丁写入 .patching，全部成员校验通过后才逐个替换
+async fn apply_dir_patch(
+    source: InstallFileSource,
+    target: &str,
+    diff_size: usize,
+    files: &[DirPatchFile],
+    overrides: &[Option<std::path::PathBuf>],
+    new: &[(std::path::PathBuf, u64)],
+    progress_noti: impl Fn(usize) + Send + 'static,
+) -> Result<(usize, Option<Arc<Mutex<InsightItem>>>)> {
+    let old = files
+        .iter()
+        .zip(overrides)
+        .map(|(file, override_old_path)| {
+            let old_path = override_old_path
+                .clone()
+                .unwrap_or_else(|| std::path::PathBuf::from(&file.target));
+            (old_path, file.old_size)
+        })
+        .collect();
+    let (stream, insight_handle) = create_stream_by_source(source, target).await?;
+    let bytes_transferred =
+        progressed_dir_hpatch(stream, old, new.to_vec(), diff_size, progress_noti).await?;
+    for ((file, (patched, _)), override_old_path) in files.iter().zip(new).zip(overrides) {
+        if override_old_path.is_some() {
+            info!("Clearing installer index mark for: {}", file.target);
+            if let Err(e) = crate::installer::uninstall::clear_index_mark(patched).await {
+                warn!("Failed to clear index mark: {:?}", e);
+                return Err(e);
+            }
+        }
+        verify_hash(&patched.to_string_lossy(), &file.hashes).await?;
+    }
+    for (file, (patched, _)) in files.iter().zip(new) {
+        tokio::fs::rename(patched, &file.target)
+            .await
+            .context("RENAME_NEW_TARGET_ERR")?;
+    }
+    Ok((bytes_transferred, insight_handle))
+}
+
+// 失败后前端会逐个下载成员文件，先清理剩下的 .patching 并把改名的自身放回原处；
+// 已替换的成员是校验过的新文件，保持不变
+async fn rollback_dir_patch(
+    files: &[DirPatchFile],
+    overrides: &[Option<std::path::PathBuf>],
+    new: &[(std::path::PathBuf, u64)],
+) {
+    for (patched, _) in new {
+        let _ = tokio::fs::remove_file(patched).await;
+    }
+    for (file, override_old_path) in files.iter().zip(overrides) {
+        let Some(override_old_path) = override_old_path else {
+            continue;
+        };
+        if tokio::fs::try_exists(&file.target).await.unwrap_or(false) {
+            continue;
+        }
+        if let Err(e) = restore_target(&file.target, override_old_path).await {
+            warn!("Failed to restore {}: {:?}", file.target, e);
+        }
+    }
+}
+
 pub async fn ipc_install_file(
     args: InstallFileArgs,
     notify: impl Fn(serde_json::Value) + std::marker::Send + 'static,
@@ -135,72 +447,45 @@ pub async fn ipc_install_file(
     };
     match args.mode {
         InstallFileMode::Direct { source } => {
-            let (stream, insight_handle) = create_stream_by_source(source).await?;
-            let bytes_transferred = match crate::fs::progressed_copy(
-                stream,
-                create_target_file(&target).await?,
+            let result = install_direct(
+                source,
+                &target,
+                &args.hashes,
+                args.clear_installer_index_mark.unwrap_or(false) || override_old_path.is_some(),
                 progress_noti,
             )
-            .await
-            {
-                Ok(bytes) => bytes,
-                Err(e) => {
-                    if let Some(handle) = &insight_handle {
-                        if let Ok(mut insight) = handle.lock() {
-                            insight.error = Some(e.to_string());
-                        }
-                        return Err(crate::utils::error::TACommandError::with_insight_handle(
-                            e,
-                            handle.clone(),
-                        ));
-                    } else {
-                        return Err(crate::utils::error::TACommandError::new(e));
-                    }
-                }
-            };
-
-            // 获取最终的insight
-            let final_insight = if let Some(handle) = insight_handle {
-                if let Ok(insight) = handle.lock() {
-                    Some(insight.clone())
-                } else {
-                    None
-                }
-            } else {
-                None
-            };
-
-            if args.md5.is_some() || args.xxh.is_some() {
-                // 如果需要清理installer索引标记，先清理再进行hash校验
-                if args.clear_installer_index_mark.unwrap_or(false) || override_old_path.is_some() {
-                    info!("Clearing installer index mark for: {}", target);
-                    if let Err(e) = crate::installer::uninstall::clear_index_mark(
-                        &std::path::PathBuf::from(&target),
-                    )
-                    .await
-                    .into_ta_result()
-                    {
-                        warn!("Failed to clear index mark: {:?}", e);
-                        return Err(e);
-                    }
-                    info!("Index mark cleared successfully");
-                }
-                verify_hash(&target, args.md5, args.xxh).await?;
-            }
-
-            let result = InstallResult {
-                bytes_transferred,
-                insight: final_insight,
-            };
+            .await?;
             serde_json::to_value(result).into_ta_result()
         }
-        InstallFileMode::Patch { source, diff_size } => {
+        InstallFileMode::Patch {
+            source,
+            diff_size,
+            new_size,
+            from,
+            fallback,
+        } => {
             let is_self_update = override_old_path.is_some();
-            let (stream, insight_handle) = create_stream_by_source(source).await?;
+            let old_path = match &override_old_path {
+                Some(path) => path.to_string_lossy().to_string(),
+                None => target.clone(),
+            };
+            if !patch_source_matches(&old_path, &from).await? {
+                let result = install_fallback(
+                    fallback,
+                    &target,
+                    &args.hashes,
+                    args.clear_installer_index_mark.unwrap_or(false) || is_self_update,
+                    progress_noti,
+                )
+                .await?;
+                return serde_json::to_value(result).into_ta_result();
+            }
+            let (stream, insight_handle) = create_stream_by_source(source, &target).await?;
             let (bytes_transferred, _) = progressed_hpatch(
                 stream,
                 &target,
                 diff_size,
+                new_size,
                 progress_noti,
                 override_old_path,
                 None, // 传入None，因为现在insight由handle管理
@@ -218,7 +503,7 @@ pub async fn ipc_install_file(
                 None
             };
 
-            if args.md5.is_some() || args.xxh.is_some() {
+            if args.hashes.strongest().is_some() {
                 // 如果需要清理installer索引标记，先清理再进行hash校验
                 if args.clear_installer_index_mark.unwrap_or(false) || is_self_update {
                     info!("Clearing installer index mark for: {}", target);
@@ -233,29 +518,47 @@ pub async fn ipc_install_file(
                     }
                     info!("Index mark cleared successfully");
                 }
-                verify_hash(&target, args.md5, args.xxh).await?;
+                verify_hash(&target, &args.hashes).await?;
             }
 
             let result = InstallResult {
                 bytes_transferred,
                 insight: final_insight,
+                source_mismatch: false,
             };
             serde_json::to_value(result).into_ta_result()
         }
-        InstallFileMode::HybridPatch { diff, source } => {
+        InstallFileMode::HybridPatch {
+            diff,
+            source,
+            new_size,
+            from,
+            fallback,
+        } => {
             // first extract source (local file, no insight needed)
-            let (source_stream, _) = create_stream_by_source(source).await?;
+            let (source_stream, _) = create_stream_by_source(source, &target).await?;
             let target_fs = create_target_file(&target).await?;
-            let _source_bytes = progressed_copy(source_stream, target_fs, progress_noti).await?;
+            let _source_bytes = progressed_copy(source_stream, target_fs, &progress_noti).await?;
+            if !patch_source_matches(&target, &from).await? {
+                let result = install_fallback(
+                    fallback,
+                    &target,
+                    &args.hashes,
+                    args.clear_installer_index_mark.unwrap_or(false) || override_old_path.is_some(),
+                    progress_noti,
+                )
+                .await?;
+                return serde_json::to_value(result).into_ta_result();
+            }
 
             // then apply patch (only consider diff as URL)
             let size: usize = match diff {
                 InstallFileSource::Url { size, .. } => size,
                 InstallFileSource::Local { size, .. } => size,
             };
-            let (diff_stream, insight_handle) = create_stream_by_source(diff).await?;
+            let (diff_stream, insight_handle) = create_stream_by_source(diff, &target).await?;
             let (diff_bytes, _) =
-                progressed_hpatch(diff_stream, &target, size, |_| {}, None, None).await?;
+                progressed_hpatch(diff_stream, &target, size, new_size, |_| {}, None, None).await?;
 
             // 获取最终的insight
             let final_insight = if let Some(handle) = insight_handle {
@@ -268,7 +571,7 @@ pub async fn ipc_install_file(
                 None
             };
 
-            if args.md5.is_some() || args.xxh.is_some() {
+            if args.hashes.strongest().is_some() {
                 // 如果需要清理installer索引标记，先清理再进行hash校验
                 if args.clear_installer_index_mark.unwrap_or(false) || override_old_path.is_some() {
                     info!("Clearing installer index mark for: {}", target);
@@ -283,12 +586,143 @@ pub async fn ipc_install_file(
                     }
                     info!("Index mark cleared successfully");
                 }
-                verify_hash(&target, args.md5, args.xxh).await?;
+                verify_hash(&target, &args.hashes).await?;
             }
 
             let result = InstallResult {
                 bytes_transferred: diff_bytes, // 只统计diff文件的网络传输
                 insight: final_insight,        // 只统计diff文件的网络统计
+                source_mismatch: false,
+            };
+            serde_json::to_value(result).into_ta_result()
+        }
+        InstallFileMode::PatchChain { steps } => {
+            let is_self_update = override_old_path.is_some();
+            let progress_noti = Arc::new(Mutex::new(progress_noti));
+            let mut override_old_path = override_old_path;
+            let mut bytes_transferred = 0;
+            let mut final_insight = None;
+            for step in steps {
+                let (stream, insight_handle) =
+                    create_stream_by_source(step.source, &target).await?;
+                let progress = progress_noti.clone();
+                let base = bytes_transferred;
+                // 自更新时只有第一步从改名后的旧文件读取，之后都基于上一步的结果
+                let (step_bytes, _) = progressed_hpatch(
+                    stream,
+                    &target,
+                    step.diff_size,
+                    step.new_size,
+                    move |downloaded| {
+                        if let Ok(noti) = progress.lock() {
+                            (*noti)(base + downloaded);
+                        }
+                    },
+                    override_old_path.take(),
+                    None,
+                )
+                .await?;
+                bytes_transferred += step_bytes;
+
+                // 只保留最后一步的insight
+                if let Some(handle) = insight_handle {
+                    if let Ok(insight) = handle.lock() {
+                        final_insight = Some(insight.clone());
+                    }
+                }
+            }
+
+            if args.hashes.strongest().is_some() {
+                // 如果需要清理installer索引标记，先清理再进行hash校验
+                if args.clear_installer_index_mark.unwrap_or(false) || is_self_update {
+                    info!("Clearing installer index mark for: {}", target);
+                    if let Err(e) = crate::installer::uninstall::clear_index_mark(
+                        &std::path::PathBuf::from(&target),
+                    )
+                    .await
+                    .into_ta_result()
+                    {
+                        warn!("Failed to clear index mark: {:?}", e);
+                        return Err(e);
+                    }
+                    info!("Index mark cleared successfully");
+                }
+                verify_hash(&target, &args.hashes).await?;
+            }
+
+            let result = InstallResult {
+                bytes_transferred,
+                insight: final_insight,
+                source_mismatch: false,
+            };
+            serde_json::to_value(result).into_ta_result()
+        }
+        InstallFileMode::DirPatch {
+            source,
+            diff_size,
+            files,
+        } => {
+            // 先校验全部源文件，不符时不动任何文件，由前端改为逐个下载
+            for file in files.iter() {
+                if !patch_source_matches(&file.target, &file.from).await? {
+                    return Err(source_mismatch_error(&file.target).into());
+                }
+            }
+            // 与单文件补丁相同，占用中的自身先改名，再从改名后的文件读取
+            let mut overrides = Vec::with_capacity(files.len());
+            let new: Vec<_> = files
+                .iter()
+                .map(|file| {
+                    (
+                        std::path::PathBuf::from(format!("{}.patching", file.target)),
+                        file.size,
+                    )
+                })
+                .collect();
+            let mut result = Ok((0, None));
+            for file in files.iter() {
+                match prepare_target(&file.target).await {
+                    Ok(override_old_path) => overrides.push(override_old_path),
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
+                }
+            }
+            if result.is_ok() {
+                result = apply_dir_patch(
+                    source,
+                    &target,
+                    diff_size,
+                    &files,
+                    &overrides,
+                    &new,
+                    progress_noti,
+                )
+                .await;
+            }
+            let (bytes_transferred, insight_handle) = match result {
+                Ok(result) => result,
+                Err(e) => {
+                    rollback_dir_patch(&files, &overrides, &new).await;
+                    return Err(e.into());
+                }
+            };
+
+            let final_insight = if let Some(handle) = insight_handle {
+                if let Ok(insight) = handle.lock() {
+                    Some(insight.clone())
+                } else {
+                    None
+                }
+            } else {
+                None
+            };
+
+            let result = InstallResult {
+                bytes_transferred,
+                insight: final_insight,
+                source_mismatch: false,
             };
             serde_json::to_value(result).into_ta_result()
         }
@@ -312,7 +746,7 @@ where
         InstallFileMode::Direct { .. } => {
             let res =
                 progressed_copy(reader, create_target_file(&target).await?, progress_noti).await?;
-            if args.md5.is_some() || args.xxh.is_some() {
+            if args.hashes.strongest().is_some() {
                 // 如果需要清理installer索引标记，先清理再进行hash校验
                 if args.clear_installer_index_mark.unwrap_or(false) || override_old_path.is_some() {
                     info!("Clearing installer index mark for: {}", target);
@@ -326,21 +760,41 @@ where
                     }
                     info!("Index mark cleared successfully");
                 }
-                verify_hash(&target, args.md5, args.xxh).await?;
+                verify_hash(&target, &args.hashes).await?;
             }
             Ok(serde_json::json!(res))
         }
-        InstallFileMode::Patch { diff_size, .. } => {
+        InstallFileMode::Patch {
+            diff_size,
+            new_size,
+            from,
+            ..
+        } => {
             // copy to local buffer using progressed_copy
             let mut buffer: Vec<u8> = vec![0; diff_size];
             progressed_copy(reader, &mut buffer, progress_noti).await?;
             let reader = std::io::Cursor::new(buffer);
             let is_self_update = override_old_path.is_some();
-            let res =
-                progressed_hpatch(reader, &target, diff_size, |_| {}, override_old_path, None)
-                    .await?
-                    .0;
-            if args.md5.is_some() || args.xxh.is_some() {
+            // 分块流中无法改为完整下载，交给前端按失败重试
+            let old_path = match &override_old_path {
+                Some(path) => path.to_string_lossy().to_string(),
+                None => target.clone(),
+            };
+            if !patch_source_matches(&old_path, &from).await? {
+                return Err(source_mismatch_error(&old_path));
+            }
+            let res = progressed_hpatch(
+                reader,
+                &target,
+                diff_size,
+                new_size,
+                |_| {},
+                override_old_path,
+                None,
+            )
+            .await?
+            .0;
+            if args.hashes.strongest().is_some() {
                 // 如果需要清理installer索引标记，先清理再进行hash校验
                 if args.clear_installer_index_mark.unwrap_or(false) || is_self_update {
                     info!("Clearing installer index mark for: {}", target);
@@ -354,7 +808,7 @@ where
                     }
                     info!("Index mark cleared successfully");
                 }
-                verify_hash(&target, args.md5, args.xxh).await?;
+                verify_hash(&target, &args.hashes).await?;
             }
             Ok(serde_json::json!(res))
         }
@@ -364,6 +818,12 @@ where
                 "Hybrid patch is not supported in this function"
             ))
         }
+        InstallFileMode::PatchChain { .. } => Err(anyhow::anyhow!(
+            "Patch chain is not supported in this function"
+        )),
+        InstallFileMode::DirPatch { .. } => Err(anyhow::anyhow!(
+            "Dir patch is not supported in this function"
+        )),
     }
 }
 
@@ -501,15 +961,27 @@ pub async fn ipc_install_multipart_stream(
                 field_data.extend_from_slice(&chunk_bytes);
             }
 
+            if let Err(e) = verify_chunk_checksum(chunk, &field_data) {
+                mult_res.push(Err(crate::utils::error::TACommandError::new(e)));
+                chunk_index += 1;
+                continue;
+            }
+
             // Create reader from collected field data
             let reader = std::io::Cursor::new(field_data);
 
             // 根据参数决定是否解压缩并安装chunk (disable timeout in install_file_by_reader)
             let chunk_result = if should_decompress {
-                let mut decompressed_reader = TokioZstdDecoder::new(reader);
-                install_file_by_reader(chunk.clone(), &mut decompressed_reader, chunk_notify)
+                match zstd_decoder(reader, get_chunk_dict(chunk)) {
+                    Ok(mut decompressed_reader) => install_file_by_reader(
+                        chunk.clone(),
+                        &mut decompressed_reader,
+                        chunk_notify,
+                    )
                     .await
-                    .into_ta_result()
+                    .into_ta_result(),
+                    Err(e) => Err(crate::utils::error::TACommandError::new(e)),
+                }
             } else {
                 let mut raw_reader = reader;
                 install_file_by_reader(chunk.clone(), &mut raw_reader, chunk_notify)
@@ -529,7 +1001,7 @@ pub async fn ipc_install_multipart_stream(
                 url: args.url.clone(),
                 ttfb: 0,
                 time: 0,
-                size: content_length as u32,
+                size: content_length,
                 error: Some("Failed to get insight".to_string()),
                 range: vec![],
                 mode: None,
@@ -554,6 +1026,16 @@ pub async fn ipc_install_multipart_stream(
                 // proceed with the first chunk
                 let stream = http_stream.map_err(std::io::Error::other);
                 let reader = tokio_util::io::StreamReader::new(stream);
+                let reader: Box<dyn AsyncBufRead + Unpin + Send> =
+                    match get_chunk_checksum(first_chunk)? {
+                        Some(expected) => Box::new(BufReader::new(ChecksumReader::new(
+                            reader,
+                            &first_chunk.target,
+                            expected,
+                            source_size as u64,
+                        ))),
+                        None => Box::new(reader),
+                    };
 
                 // Create enhanced notification callback for the first chunk
                 let chunk_notify = {
@@ -569,7 +1051,8 @@ pub async fn ipc_install_multipart_stream(
 
                 // 根据参数决定是否解压缩
                 let res = if should_decompress {
-                    let mut decompressed_reader = TokioZstdDecoder::new(reader);
+                    let mut decompressed_reader =
+                        zstd_decoder(reader, get_chunk_dict(first_chunk))?;
                     install_file_by_reader(
                         first_chunk.clone(),
                         &mut decompressed_reader,
@@ -592,7 +1075,7 @@ pub async fn ipc_install_multipart_stream(
                         url: args.url.clone(),
                         ttfb: 0,
                         time: 0,
-                        size: content_length as u32,
+                        size: content_length,
                         error: Some("Failed to get insight".to_string()),
                         range: vec![],
                         mode: None,
@@ -629,6 +1112,8 @@ fn get_chunk_size(args: &InstallFileArgs) -> usize {
         InstallFileMode::HybridPatch { diff, .. } => match diff {
             InstallFileSource::Url { size, .. } | InstallFileSource::Local { size, .. } => *size,
         },
+        InstallFileMode::PatchChain { steps } => steps.iter().map(|step| step.diff_size).sum(),
+        InstallFileMode::DirPatch { diff_size, .. } => *diff_size,
     }
 }
 
@@ -650,6 +1135,7 @@ fn get_chunk_position(args: &InstallFileArgs) -> usize {
                 *offset
             }
         },
+        InstallFileMode::PatchChain { .. } | InstallFileMode::DirPatch { .. } => 0,
     }
 }
 
@@ -750,19 +1236,24 @@ pub async fn ipc_install_multichunk_stream(
             )
         })?;
 
+        let checksum_result = verify_chunk_checksum(&chunk_info.args, &chunk_buffer);
         let chunk_reader = std::io::Cursor::new(chunk_buffer);
 
         // Process chunk directly without timeout monitoring (NetworkInsightStream handles it)
-        let chunk_result = if should_decompress {
+        let chunk_result = if let Err(e) = checksum_result {
+            Err(crate::utils::error::TACommandError::new(e))
+        } else if should_decompress {
             let buf_reader = BufReader::new(chunk_reader);
-            let mut decompressed_reader = TokioZstdDecoder::new(buf_reader);
-            install_file_by_reader(
-                chunk_info.args.clone(),
-                &mut decompressed_reader,
-                chunk_notify,
-            )
-            .await
-            .into_ta_result()
+            match zstd_decoder(buf_reader, get_chunk_dict(&chunk_info.args)) {
+                Ok(mut decompressed_reader) => install_file_by_reader(
+                    chunk_info.args.clone(),
+                    &mut decompressed_reader,
+                    chunk_notify,
+                )
+                .await
+                .into_ta_result(),
+                Err(e) => Err(crate::utils::error::TACommandError::new(e)),
+            }
         } else {
             let mut raw_reader = chunk_reader;
             install_file_by_reader(chunk_info.args.clone(), &mut raw_reader, chunk_notify)
@@ -4,6 +4,7 @@ use crate::utils::error::TAResult;
 pub enum IpcOperation {
     Ping,
     InstallFile(super::install_file::InstallFileArgs),
+    LoadDictionary(super::install_file::LoadDictionaryArgs),
     InstallMultipartStream(super::install_file::InstallMultiStreamArgs),
     InstallMultichunkStream(super::install_file::InstallMultiStreamArgs),
     CreateLnk(crate::installer::lnk::CreateLnkArgs),
@@ -47,6 +48,7 @@ pub async fn run_opr(
     let op_name = match &op {
         IpcOperation::Ping => "Ping",
         IpcOperation::InstallFile(_) => "InstallFile",
+        IpcOperation::LoadDictionary(_) => "LoadDictionary",
         IpcOperation::InstallMultipartStream(_) => "InstallMultipartStream",
         IpcOperation::InstallMultichunkStream(_) => "InstallMultichunkStream",
         IpcOperation::CreateLnk(_) => "CreateLnk",
@@ -73,6 +75,7 @@ pub async fn run_opr(
         IpcOperation::InstallFile(args) => {
             super::install_file::ipc_install_file(args, notify).await
         }
+        IpcOperation::LoadDictionary(args) => super::install_file::ipc_load_dictionary(args).await,
         IpcOperation::InstallMultipartStream(args) => {
             super::install_file::ipc_install_multipart_stream(args, notify).await
         }
@@ -1,9 +1,12 @@
 use anyhow::Context;
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use fmmap::tokio::{AsyncMmapFile, AsyncMmapFileExt, AsyncMmapFileReader};
-use serde::{Deserialize, Serialize};
+use kachina_pack::{
+    parse_index, parse_public_key, PackReader, VerifyingKey, CONFIG_NAME, IMAGE_NAME, INDEX_NAME,
+    META_NAME, SIGNATURE_ERR,
+};
 use serde_json::Value;
-use tokio::io::AsyncReadExt;
+use std::io::Cursor;
 use tokio::sync::OnceCell;
 
 use crate::utils::error::return_anyhow_result;
@@ -30,88 +33,17 @@ pub async fn mmap() -> &'static AsyncMmapFile {
         .await
 }
 
-async fn search_pattern(file: &'static AsyncMmapFile) -> anyhow::Result<Vec<usize>> {
-    let pattern = "!in\0".to_ascii_uppercase();
-    let pattern: &[u8; 4] = pattern.as_bytes().try_into().unwrap();
-    let mut reader = file.reader(0).context("MMAP_ERR")?;
-    let mut buffer = [0u8; 4096];
-    let mut offset: usize = 0;
-    let mut founds = Vec::new();
-    let mut read = 0;
+pub use kachina_pack::Embedded;
 
-    loop {
-        // move last 4 bytes to the beginning of the buffer
-        if read > 4 {
-            buffer[0] = buffer[read + 4 - 4];
-            buffer[1] = buffer[read + 4 - 3];
-            buffer[2] = buffer[read + 4 - 2];
-            buffer[3] = buffer[read + 4 - 1];
-        }
-        read = reader.read(&mut buffer[4..]).await.context("MMAP_ERR")?;
-        if read == 0 {
-            break;
-        }
-        for i in 0..read + 4 - 1 {
-            if buffer[i] == pattern[0] {
-                if i + 3 > read + 3 {
-                    continue;
-                }
-                if buffer[i + 1] == pattern[1]
-                    && buffer[i + 2] == pattern[2]
-                    && buffer[i + 3] == pattern[3]
-                {
-                    founds.push(offset + i - 4);
-                }
-            }
-        }
-        offset += read;
-    }
-
-    Ok(founds)
-}
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Embedded {
-    pub name: String,
-    pub offset: usize,
-    pub raw_offset: usize,
-    pub size: usize,
-}
 pub async fn get_embedded(file: &'static AsyncMmapFile) -> anyhow::Result<Vec<Embedded>> {
-    let offsets = search_pattern(file).await?;
-    let mut entries = Vec::new();
-    let mut last_offset: usize = 0;
-    for offset in offsets.iter() {
-        if *offset < last_offset {
-            // in case of content includes header
-            continue;
-        }
-        // TLV
-        // header: !IN\0
-        // name length: 2 bytes big endian
-        // name: variable length
-        // content length: 4 bytes big endian
-        // content: variable length
-        let mem_pos_name_length = *offset + 4;
-        let mem_pos_name = mem_pos_name_length + 2;
-        let name_length =
-            u16::from_be_bytes(file.slice(mem_pos_name_length, 2).try_into().unwrap()) as usize;
-        let name = file.slice(mem_pos_name, name_length);
-        let name = String::from_utf8_lossy(name).to_string();
-        let mem_pos_content_length = mem_pos_name + name_length;
-        let content_length =
-            u32::from_be_bytes(file.slice(mem_pos_content_length, 4).try_into().unwrap()) as usize;
-        let mem_pos_content = mem_pos_content_length + 4;
-        entries.push(Embedded {
-            name,
-            offset: mem_pos_content,
-            size: content_length,
-            raw_offset: *offset,
-        });
-        last_offset = mem_pos_content + content_length;
-    }
-    Ok(entries)
+    let reader = PackReader::new(Cursor::new(file.slice(0, file.len())))
+        .await
+        .context("MMAP_ERR")?;
+    Ok(reader.into_entries())
 }
 
+/// Takes the first entry of each name like `PackReader::verify_signature`, so
+/// only the entries it checked are used.
 pub async fn get_config_from_embedded(
     embedded: &[Embedded],
 ) -> anyhow::Result<(
@@ -121,65 +53,66 @@ pub async fn get_config_from_embedded(
     Option<String>,
 )> {
     let file = mmap().await;
-    let mut config = None;
-    let mut metadata = None;
-    let mut index: Option<Vec<Embedded>> = None;
     let start_offset = if embedded.is_empty() {
         0
     } else {
         embedded[0].raw_offset
     };
-    for entry in embedded.iter() {
-        if entry.name == "\0CONFIG" {
-            let content = file.slice(entry.offset, entry.size);
+    let find = |name: &str| {
+        embedded
+            .iter()
+            .find(|e| e.name == name)
+            .map(|e| file.slice(e.offset, e.size))
+    };
+    let config = match find(CONFIG_NAME) {
+        Some(content) => {
             let content = String::from_utf8_lossy(content);
-            config = Some(serde_json::from_str(&content).context("LOCAL_CONFIG_ERR")?);
-        } else if entry.name == "\0META" {
-            let content = file.slice(entry.offset, entry.size);
+            Some(serde_json::from_str(&content).context("LOCAL_CONFIG_ERR")?)
+        }
+        None => None,
+    };
+    let metadata = match find(META_NAME) {
+        Some(content) => {
             let content = String::from_utf8_lossy(content);
-            metadata = Some(serde_json::from_str(&content).context("LOCAL_CONFIG_ERR")?);
-        } else if entry.name == "\0INDEX" {
-            // u8: name_len var: name u32: size u32: offset
-            let content: &[u8] = file.slice(entry.offset, entry.size);
-            let mut index_entries = Vec::new();
-            let mut offset = 0;
-            while offset < content.len() {
-                let name_len = content[offset] as usize;
-                offset += 1;
-                let name = String::from_utf8_lossy(&content[offset..offset + name_len]).to_string();
-                offset += name_len;
-                let size =
-                    u32::from_be_bytes(content[offset..offset + 4].try_into().unwrap()) as usize;
-                offset += 4;
-                let file_offset =
-                    u32::from_be_bytes(content[offset..offset + 4].try_into().unwrap()) as usize;
-                offset += 4;
-                index_entries.push(Embedded {
-                    raw_offset: start_offset + file_offset - get_header_size(&name),
-                    name,
-                    offset: start_offset + file_offset,
-                    size,
-                });
-            }
-            index = Some(index_entries);
+            Some(serde_json::from_str(&content).context("LOCAL_CONFIG_ERR")?)
         }
-    }
+        None => None,
+    };
+    let index = find(INDEX_NAME).and_then(|content| parse_index(content, start_offset));
 
     // Process \0IMAGE if it exists
-    let mut image_base64 = None;
-    for entry in embedded.iter() {
-        if entry.name == "\0IMAGE" {
-            let content: &[u8] = file.slice(entry.offset, entry.size);
-            image_base64 = Some(BASE64.encode(content));
-            break;
-        }
-    }
+    let image_base64 = find(IMAGE_NAME).map(|content| BASE64.encode(content));
 
     Ok((config, metadata, index, image_base64))
 }
 
-pub fn get_header_size(name: &str) -> usize {
-    "!in\0".len() + 2 + name.len() + 4
+/// Public keys listed in `trustedKeys`, signatures are only enforced when
+/// there is at least one.
+pub fn get_trusted_keys(config: &Value) -> anyhow::Result<Vec<VerifyingKey>> {
+    let Some(keys) = config["trustedKeys"].as_array() else {
+        return Ok(vec![]);
+    };
+    keys.iter()
+        .map(|key| {
+            key.as_str()
+                .and_then(parse_public_key)
+                .ok_or_else(|| anyhow::anyhow!("Invalid trusted key: {key}"))
+                .context(SIGNATURE_ERR)
+        })
+        .collect()
+}
+
+/// Check the embedded `\0SIGN` against the trusted keys of the embedded config.
+pub async fn verify_embedded_signature(trusted: &[VerifyingKey]) -> anyhow::Result<()> {
+    let file = mmap().await;
+    let mut reader = PackReader::new(Cursor::new(file.slice(0, file.len())))
+        .await
+        .context("MMAP_ERR")?;
+    reader
+        .verify_signature(trusted)
+        .await
+        .context("MMAP_ERR")?
+        .context(SIGNATURE_ERR)
 }
 
 pub async fn get_base_with_config() -> anyhow::Result<AsyncMmapFileReader<'static>> {
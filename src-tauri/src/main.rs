@@ -246,12 +246,15 @@ async fn tauri_main(args: InstallArgs) {
             installer::launch,
             installer::launch_and_exit,
             installer::config::get_installer_config,
+            installer::config::read_pack_header,
+            installer::config::check_parsed_metadata_source,
             installer::lnk::get_dirs,
             installer::registry::read_uninstall_metadata,
             installer::select_dir,
             installer::error_dialog,
             installer::confirm_dialog,
             installer::get_exe_version,
+            installer::plan_patch_chains,
             // wincred
             utils::wincred::wincred_write,
             utils::wincred::wincred_read,
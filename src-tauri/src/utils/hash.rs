@@ -3,6 +3,37 @@ use std::{io::Read, path::Path};
 
 const XXH_BUFFER_SIZE: usize = 256 * 1024;
 
+// 在阻塞线程中分块读取文件计算哈希
+async fn hash_by_reading<H: Send + 'static>(
+    path: &str,
+    mut hasher: H,
+    update: fn(&mut H, &[u8]),
+    finish: fn(H) -> String,
+) -> Result<String> {
+    let path = path.to_string();
+    let res = tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(false)
+            .open(&path)
+            .context("OPEN_TARGET_ERR")?;
+
+        let mut buffer = vec![0u8; XXH_BUFFER_SIZE];
+        loop {
+            let read = file.read(&mut buffer).context("READ_FILE_ERR")?;
+            if read == 0 {
+                break;
+            }
+            update(&mut hasher, &buffer[..read]);
+        }
+        Ok::<String, anyhow::Error>(finish(hasher))
+    })
+    .await
+    .context("HASH_THREAD_ERR")?
+    .context("HASH_COMPLETE_ERR")?;
+    Ok(res)
+}
+
 pub async fn run_hash(hash_algorithm: &str, path: &str) -> Result<String> {
     if hash_algorithm == "md5" {
         let md5 = chksum_md5::async_chksum(Path::new(path))
@@ -10,26 +41,30 @@ pub async fn run_hash(hash_algorithm: &str, path: &str) -> Result<String> {
             .context("HASH_COMPLETE_ERR")?;
         Ok(md5.to_hex_lowercase())
     } else if hash_algorithm == "xxh" {
+        use twox_hash::XxHash3_128;
+        hash_by_reading(
+            path,
+            XxHash3_128::new(),
+            |hasher, data| hasher.write(data),
+            |hasher| format!("{:x}", hasher.finish_128()),
+        )
+        .await
+    } else if hash_algorithm == "sha256" {
+        use sha2::{Digest, Sha256};
+        hash_by_reading(
+            path,
+            Sha256::new(),
+            |hasher, data| hasher.update(data),
+            |hasher| format!("{:x}", hasher.finalize()),
+        )
+        .await
+    } else if hash_algorithm == "blake3" {
+        // 内存映射后多线程计算，大文件明显快于逐块读取
         let path = path.to_string();
         let res = tokio::task::spawn_blocking(move || {
-            use twox_hash::XxHash3_128;
-            let mut hasher = XxHash3_128::new();
-            let mut file = std::fs::OpenOptions::new()
-                .read(true)
-                .write(false)
-                .open(&path)
-                .context("OPEN_TARGET_ERR")?;
-
-            let mut buffer = vec![0u8; XXH_BUFFER_SIZE];
-            loop {
-                let read = file.read(&mut buffer).context("READ_FILE_ERR")?;
-                if read == 0 {
-                    break;
-                }
-                hasher.write(&buffer[..read]);
-            }
-            let hash = hasher.finish_128();
-            Ok::<String, anyhow::Error>(format!("{hash:x}"))
+            let mut hasher = blake3::Hasher::new();
+            hasher.update_mmap_rayon(&path).context("READ_FILE_ERR")?;
+            Ok::<String, anyhow::Error>(hasher.finalize().to_hex().to_string())
         })
         .await
         .context("HASH_THREAD_ERR")?
@@ -1,5 +1,15 @@
 use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    Zstd,
+    // 压缩收益太小的文件直接存储，安装时跳过解压
+    Raw,
+    // 使用 RepoMetadata.dictionary 中的字典压缩
+    Dict,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Metadata {
     pub file_name: String,
@@ -8,23 +18,41 @@ pub struct Metadata {
     pub md5: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub xxh: Option<String>,
+    // 由 gen --strong-hash 写入，安装器校验时优先使用
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blake3: Option<String>,
+    // 未指定时为 zstd
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codec: Option<Codec>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PatchItem {
     pub size: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub md5: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub xxh: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blake3: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PatchInfo {
     pub file_name: String,
     pub size: u64,
     pub from: PatchItem,
     pub to: PatchItem,
+    // 从之前版本保留下来的补丁，值为其生成后又发布了几个版本；当前版本生成的补丁为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub age: Option<u32>,
+    // 为 Raw 时补丁由 hdiff 以 zstd 插件压缩，存储时不再整体压缩；为空时整体以 zstd 压缩
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codec: Option<Codec>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -32,6 +60,39 @@ pub struct InstallerInfo {
     pub size: u64,
     pub md5: Option<String>,
     pub xxh: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blake3: Option<String>,
+}
+
+// zstd 字典，作为普通条目以哈希命名打包，本身不压缩
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DictionaryInfo {
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub md5: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub xxh: Option<String>,
+}
+
+// 目录补丁的成员文件，新旧内容分别按成员顺序拼接后生成一个补丁
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DirPatchFile {
+    pub file_name: String,
+    pub from: PatchItem,
+    pub to: PatchItem,
+}
+
+// 一次更新一组小文件的补丁，以 name 为条目名打包
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DirPatchInfo {
+    pub name: String,
+    pub size: u64,
+    // 与 PatchInfo 相同，为 Raw 时补丁由 hdiff 以 zstd 插件压缩
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codec: Option<Codec>,
+    pub files: Vec<DirPatchFile>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -50,4 +111,8 @@ pub struct RepoMetadata {
     pub deletes: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub packing_info: Option<Vec<Vec<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dictionary: Option<DictionaryInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir_patches: Option<Vec<DirPatchInfo>>,
 }
@@ -5,6 +5,7 @@ pub mod gui;
 pub mod hash;
 pub mod icon;
 pub mod metadata;
+pub mod patch_graph;
 pub mod progressed_read;
 pub mod sentry;
 pub mod uac;
@@ -0,0 +1,131 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use super::metadata::{PatchInfo, PatchItem};
+
+fn item_hash<'a>(item: &'a PatchItem, hash_key: &str) -> Option<&'a str> {
+    match hash_key {
+        "md5" => item.md5.as_deref(),
+        "sha256" => item.sha256.as_deref(),
+        "blake3" => item.blake3.as_deref(),
+        _ => item.xxh.as_deref(),
+    }
+}
+
+/// Finds the patch chain with the smallest total diff size that turns the
+/// `from` version of a file into `to`. Returns indices into `patches` in
+/// apply order, or `None` when no chain is smaller than a full download.
+pub fn cheapest_patch_chain(
+    patches: &[PatchInfo],
+    file_name: &str,
+    from: &str,
+    to: &str,
+    full_size: u64,
+    hash_key: &str,
+) -> Option<Vec<usize>> {
+    let edges: Vec<(usize, &str, &str, u64)> = patches
+        .iter()
+        .enumerate()
+        .filter(|(_, patch)| patch.file_name == file_name)
+        .filter_map(|(index, patch)| {
+            let from = item_hash(&patch.from, hash_key)?;
+            let to = item_hash(&patch.to, hash_key)?;
+            Some((index, from, to, patch.size))
+        })
+        .collect();
+    let mut cost: HashMap<&str, u64> = HashMap::from([(from, 0)]);
+    let mut previous: HashMap<&str, usize> = HashMap::new();
+    let mut queue = BinaryHeap::from([Reverse((0u64, from))]);
+    while let Some(Reverse((current, node))) = queue.pop() {
+        if node == to {
+            break;
+        }
+        if cost.get(node).is_some_and(|&best| current > best) {
+            continue;
+        }
+        for &(index, edge_from, edge_to, size) in edges.iter() {
+            if edge_from != node {
+                continue;
+            }
+            let next = current + size;
+            // 不比完整下载小的链没有意义
+            if next >= full_size || matches!(cost.get(edge_to), Some(&best) if best <= next) {
+                continue;
+            }
+            cost.insert(edge_to, next);
+            previous.insert(edge_to, index);
+            queue.push(Reverse((next, edge_to)));
+        }
+    }
+    cost.get(to)?;
+    let mut chain = vec![];
+    let mut node = to;
+    while node != from {
+        let index = previous[node];
+        chain.push(index);
+        node = item_hash(&patches[index].from, hash_key)?;
+    }
+    chain.reverse();
+    Some(chain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(from: &str, to: &str, size: u64) -> PatchInfo {
+        let item = |xxh: &str| PatchItem {
+            size: 100,
+            md5: None,
+            xxh: Some(xxh.to_string()),
+            sha256: None,
+            blake3: None,
+        };
+        PatchInfo {
+            file_name: "game.pak".to_string(),
+            size,
+            from: item(from),
+            to: item(to),
+            age: None,
+            codec: None,
+        }
+    }
+
+    #[test]
+    fn test_cheapest_patch_chain() {
+        let patches = vec![
+            patch("v1", "v2", 10),
+            patch("v2", "v4", 20),
+            patch("v1", "v3", 5),
+            patch("v3", "v4", 5),
+            patch("v2", "v3", 1),
+        ];
+        // 直接补丁不存在时走多步
+        assert_eq!(
+            cheapest_patch_chain(&patches, "game.pak", "v1", "v4", 100, "xxh"),
+            Some(vec![2, 3])
+        );
+        assert_eq!(
+            cheapest_patch_chain(&patches, "game.pak", "v2", "v4", 100, "xxh"),
+            Some(vec![4, 3])
+        );
+        // 补丁链不比完整下载小
+        assert_eq!(
+            cheapest_patch_chain(&patches, "game.pak", "v1", "v4", 10, "xxh"),
+            None
+        );
+        // 没有可用的补丁
+        assert_eq!(
+            cheapest_patch_chain(&patches, "game.pak", "v0", "v4", 100, "xxh"),
+            None
+        );
+        assert_eq!(
+            cheapest_patch_chain(&patches, "other.pak", "v1", "v4", 100, "xxh"),
+            None
+        );
+        assert_eq!(
+            cheapest_patch_chain(&patches, "game.pak", "v1", "v4", 100, "md5"),
+            None
+        );
+    }
+}
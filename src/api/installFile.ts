@@ -1,21 +1,52 @@
 type InstallFileSource =
-  | { url: string; offset: number; size: number; skip_decompress?: boolean }
-  | { offset: number; size: number; skip_decompress?: boolean };
+  | {
+      url: string;
+      offset: number;
+      size: number;
+      skip_decompress?: boolean;
+      checksum?: string;
+      dict?: string;
+    }
+  | {
+      offset: number;
+      size: number;
+      skip_decompress?: boolean;
+      checksum?: string;
+      dict?: string;
+    };
+
+// 文件的哈希，后端校验时使用其中最强的一个
+type FileHashes = {
+  md5?: string;
+  xxh?: string;
+  sha256?: string;
+  blake3?: string;
+};
+
+// 补丁源文件的哈希，不符时改用 fallback 完整下载
+export type PatchSourceCheck = {
+  from?: FileHashes;
+  fallback?: InstallFileSource;
+};
 
 type InstallFileMode =
   | { type: 'Direct'; source: InstallFileSource }
-  | { type: 'Patch'; source: InstallFileSource; diff_size: number }
-  | {
+  | ({
+      type: 'Patch';
+      source: InstallFileSource;
+      diff_size: number;
+      new_size: number;
+    } & PatchSourceCheck)
+  | ({
       type: 'HybridPatch';
       diff: InstallFileSource;
       source: InstallFileSource;
-    };
+      new_size: number;
+    } & PatchSourceCheck);
 
-interface InstallFileArgs {
+interface InstallFileArgs extends FileHashes {
   mode: InstallFileMode;
   target: string;
-  xxh?: string;
-  md5?: string;
   clear_installer_index_mark?: boolean;
   type: 'InstallFile';
 }
@@ -23,27 +54,26 @@ interface InstallFileArgs {
 /**
  * @param source - 文件来源（Url 字符串或 Local 对象）
  * @param target - 目标路径
- * @param diff_size - Patch 模式需要的 diff_size
+ * @param patch - Patch 模式需要的补丁大小、输出文件大小与源文件校验
  */
 export function InstallFile(
   source: InstallFileSource & { skip_hash?: boolean },
   target: string,
-  hash: {
-    xxh?: string;
-    md5?: string;
-  },
-  diff_size?: number,
+  hash: FileHashes,
+  patch?: { diff_size: number; new_size: number } & PatchSourceCheck,
   clearInstallerIndexMark?: boolean,
 ): InstallFileArgs {
   let mode: InstallFileMode;
-  if (!diff_size) {
+  if (!patch) {
     mode = { type: 'Direct', source };
   } else {
-    mode = { type: 'Patch', source, diff_size };
+    mode = { type: 'Patch', source, ...patch };
   }
   if (source.skip_hash) {
     delete hash.xxh;
     delete hash.md5;
+    delete hash.sha256;
+    delete hash.blake3;
   }
   return {
     mode,
@@ -58,20 +88,110 @@ export function hybridPatch(
   source: { offset: number; size: number },
   diff: { url: string; offset: number; size: number },
   target: string,
-  hash: {
-    xxh?: string;
-    md5?: string;
-  },
+  hash: FileHashes,
+  new_size: number,
+  check?: PatchSourceCheck,
 ): InstallFileArgs {
   const mode: InstallFileMode = {
     type: 'HybridPatch',
     diff,
     source,
+    new_size,
+    ...check,
   };
 
   return { mode, target, type: 'InstallFile', ...hash };
 }
 
+type PatchStep = {
+  source: InstallFileSource;
+  diff_size: number;
+  new_size: number;
+};
+
+interface InstallPatchChainArgs extends FileHashes {
+  mode: { type: 'PatchChain'; steps: PatchStep[] };
+  target: string;
+  clear_installer_index_mark?: boolean;
+  type: 'InstallFile';
+}
+
+/**
+ * @param steps - 按应用顺序排列的补丁，每一步的输出作为下一步的输入
+ * @param target - 目标路径
+ */
+export function InstallPatchChain(
+  steps: PatchStep[],
+  target: string,
+  hash: FileHashes,
+  clearInstallerIndexMark?: boolean,
+): InstallPatchChainArgs {
+  return {
+    mode: { type: 'PatchChain', steps },
+    target,
+    type: 'InstallFile',
+    ...hash,
+    clear_installer_index_mark: clearInstallerIndexMark,
+  };
+}
+
+type DirPatchFile = FileHashes & {
+  target: string;
+  old_size: number;
+  size: number;
+  from?: FileHashes;
+};
+
+interface InstallDirPatchArgs {
+  mode: {
+    type: 'DirPatch';
+    source: InstallFileSource;
+    diff_size: number;
+    files: DirPatchFile[];
+  };
+  target: string;
+  type: 'InstallFile';
+}
+
+/**
+ * @param source - 目录补丁来源
+ * @param files - 成员文件，顺序与生成补丁时一致，全部校验通过后才替换
+ * @param target - 安装目录
+ */
+export function InstallDirPatch(
+  source: InstallFileSource,
+  diff_size: number,
+  files: DirPatchFile[],
+  target: string,
+): InstallDirPatchArgs {
+  return {
+    mode: { type: 'DirPatch', source, diff_size, files },
+    target,
+    type: 'InstallFile',
+  };
+}
+
+interface LoadDictionaryArgs {
+  name: string;
+  source: InstallFileSource;
+  type: 'LoadDictionary';
+}
+
+/**
+ * @param name - 字典名称，之后的文件通过 dict 字段引用
+ * @param source - 字典来源，字典不经压缩存储
+ */
+export function LoadDictionary(
+  name: string,
+  source: InstallFileSource,
+): LoadDictionaryArgs {
+  return {
+    name,
+    source: { ...source, skip_decompress: true },
+    type: 'LoadDictionary',
+  };
+}
+
 interface InstallMultipartStreamArgs {
   url: string;
   range: string;
@@ -3,6 +3,7 @@ import { invoke, listen } from '../tauri';
 import { v4 as uuid } from 'uuid';
 import { addNetworkInsight } from '../networkInsights';
 import {
+  DfsMetadataPatchInfo,
   InsightItem,
   InvokeDeepReaddirWithMetadataRes,
   InvokeGetDfsMetadataRes,
@@ -387,6 +388,15 @@ export async function sendInsight(url: string, event?: string, data?: unknown) {
   return (localStorage.evCache = text || '');
 }
 
+// 由安装器计算每个文件最省流量的补丁链，返回 patches 中的下标，null 表示完整下载
+export async function ipcPlanPatchChains(
+  patches: DfsMetadataPatchInfo[],
+  files: { file_name: string; from: string; to: string; size: number }[],
+  hashKey: string,
+): Promise<(number[] | null)[]> {
+  return invoke('plan_patch_chains', { patches, files, hashKey });
+}
+
 export async function ipcIsFolderEmpty(
   path: string,
   file = '',
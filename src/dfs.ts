@@ -1,4 +1,11 @@
-import { hybridPatch, InstallFile } from './api/installFile';
+import {
+  hybridPatch,
+  InstallDirPatch,
+  InstallFile,
+  InstallPatchChain,
+  LoadDictionary,
+  PatchSourceCheck,
+} from './api/installFile';
 import { ipc, log, warn, addInsightWithMode } from './api/ipc';
 import { invoke } from './tauri';
 import { clearNetworkInsights } from './networkInsights';
@@ -8,11 +15,16 @@ import {
   Dfs2BatchChunkResponse,
   Dfs2Metadata,
   Dfs2SessionResponse,
+  DfsMetadataCheckHashType,
+  DfsMetadataDirPatchInfo,
+  DfsMetadataHashInfo,
   DfsMetadataHashType,
+  DfsMetadataPatchInfo,
   DfsUpdateTask,
   Embedded,
   FileWithPosition,
   InsightItem,
+  InstallResult,
   InvokeGetDfsMetadataRes,
   InvokeGetDfsRes,
   MergedGroupInfo,
@@ -22,6 +34,21 @@ import {
 } from './types';
 
 const connectableOrigins = new Set();
+// 已加载到安装进程中的 zstd 字典名称
+let loadedDictionary: string | undefined;
+
+// 传给后端的全部哈希，由后端选出最强的一个校验
+const fileHashes = (item: Omit<DfsMetadataHashInfo, 'file_name'>) => ({
+  md5: item.md5,
+  xxh: item.xxh,
+  sha256: item.sha256,
+  blake3: item.blake3,
+});
+
+const codecSource = (codec?: 'zstd' | 'raw' | 'dict') => ({
+  skip_decompress: codec === 'raw',
+  dict: codec === 'dict' ? loadedDictionary : undefined,
+});
 
 export const dfsIndexCache = new Map<
   string,
@@ -112,6 +139,8 @@ export const getDfsMetadata = async (
 
       const cleanUrl = pluginManager.getCleanUrl(source);
       if (!cleanUrl) throw new Error('Invalid plugin URL: ' + source);
+      // 插件返回的元数据没有可校验的签名，配置了 trustedKeys 时不可用
+      await invoke('check_parsed_metadata_source', { kind: 'Plugin' });
       const dfs2Data = await plugin.getMetadata(cleanUrl);
 
       // 转换为现有格式并缓存
@@ -122,6 +151,7 @@ export const getDfsMetadata = async (
           offset: info.offset,
           raw_offset: info.raw_offset,
           size: info.size,
+          checksum: info.checksum,
         });
       });
 
@@ -149,6 +179,8 @@ export const getDfsMetadata = async (
       return dfsIndexCache.get(source)?.metadata as InvokeGetDfsMetadataRes;
     }
 
+    // 服务端解析的元数据没有可校验的签名，配置了 trustedKeys 时不可用
+    await invoke('check_parsed_metadata_source', { kind: 'DFS2' });
     const dfs2Metadata = await invoke<Dfs2Metadata>('get_dfs2_metadata', {
       apiUrl: url,
     });
@@ -167,6 +199,7 @@ export const getDfsMetadata = async (
         offset: info.offset,
         raw_offset: info.raw_offset,
         size: info.size,
+        checksum: info.checksum,
       });
     });
 
@@ -197,6 +230,59 @@ export const getDfsMetadata = async (
   }
   throw new Error('Get metadata failed');
 };
+function parseIndexHeader(data: number[]) {
+  let bufStr = '';
+  for (let i = 0; i < data.length; i++) {
+    bufStr += String.fromCharCode(data[i]);
+  }
+  const header_offset = bufStr.indexOf('!KachinaInstaller!');
+  if (header_offset === -1) {
+    return undefined;
+  }
+  const index_offset = header_offset + 18;
+  const dataView = new DataView(new Uint8Array(data).buffer);
+  return {
+    index_start: dataView.getUint32(index_offset, false),
+    config_sz: dataView.getUint32(index_offset + 4, false),
+    theme_sz: dataView.getUint32(index_offset + 8, false),
+    index_sz: dataView.getUint32(index_offset + 12, false),
+    metadata_sz: dataView.getUint32(index_offset + 16, false),
+  };
+}
+// Authenticode 签名的安装包不改动 DOS stub，头部在证书表的私有条目中
+async function getCertificateHeader(binurl: string) {
+  const pe_head: [number, number[]] = await invoke('get_http_with_range', {
+    url: binurl,
+    offset: 0,
+    size: 4096,
+  });
+  const view = new DataView(new Uint8Array(pe_head[1]).buffer);
+  const optional = view.getUint32(0x3c, true) + 24;
+  // PE32+ 的数据目录比 PE32 靠后 16 字节
+  const pe64 = view.getUint16(optional, true) === 0x20b;
+  const security = optional + (pe64 ? 112 : 96) + 4 * 8;
+  const table_start = view.getUint32(security, true);
+  const table_end = table_start + view.getUint32(security + 4, true);
+  let pos = table_start;
+  while (table_start && pos + 8 <= table_end) {
+    // 8 字节 WIN_CERTIFICATE 头 + 38 字节索引头
+    const entry: [number, number[]] = await invoke('get_http_with_range', {
+      url: binurl,
+      offset: pos,
+      size: 8 + 38,
+    });
+    const entry_view = new DataView(new Uint8Array(entry[1]).buffer);
+    const length = entry_view.getUint32(0, true);
+    if (entry_view.getUint16(6, true) === 0x4b49) {
+      return parseIndexHeader(entry[1].slice(8));
+    }
+    if (length < 8) {
+      break;
+    }
+    pos += Math.ceil(length / 8) * 8;
+  }
+  return undefined;
+}
 export async function refreshDfsIndex(
   source: string,
   apiurl: string,
@@ -210,117 +296,33 @@ export async function refreshDfsIndex(
     offset: 0,
     size: 256,
   });
-  let bufStr = '';
-  for (let i = 0; i < pre_index[1].length; i++) {
-    bufStr += String.fromCharCode(pre_index[1][i]);
-  }
-  const header_offset = bufStr.indexOf('!KachinaInstaller!');
-  if (header_offset === -1) {
+  const header =
+    parseIndexHeader(pre_index[1]) ?? (await getCertificateHeader(binurl));
+  if (!header) {
     throw new Error('Invalid remote index');
   }
-  const index_offset = header_offset + 18;
-  const dataView = new DataView(new Uint8Array(pre_index[1]).buffer);
-  const index_start = dataView.getUint32(index_offset, false);
-  const config_sz = dataView.getUint32(index_offset + 4, false);
-  const theme_sz = dataView.getUint32(index_offset + 8, false);
-  const index_sz = dataView.getUint32(index_offset + 12, false);
-  const metadata_sz = dataView.getUint32(index_offset + 16, false);
+  const { index_start, config_sz, theme_sz, index_sz, metadata_sz } = header;
   const data_end = index_start + index_sz + config_sz + theme_sz + metadata_sz;
   const index: [number, number[]] = await invoke('get_http_with_range', {
     url: binurl,
     offset: index_start,
     size: data_end - index_start,
   });
-  const index_data = new Uint8Array(index[1]);
-  const index_view = new DataView(index_data.buffer);
-  const segments: {
-    config?: ProjectConfig;
-    metadata?: InvokeGetDfsMetadataRes;
-    theme?: string;
-    index?: Map<string, Embedded>;
-  } = {};
-  let offset = 0;
-  while (offset < index_data.length) {
-    const str =
-      String.fromCharCode(index_data[offset]) +
-      String.fromCharCode(index_data[offset + 1]) +
-      String.fromCharCode(index_data[offset + 2]) +
-      String.fromCharCode(index_data[offset + 3]);
-    if (str !== '!in\0'.toUpperCase()) {
-      offset++;
-      continue;
-    }
-    log('found segment', offset);
-    try {
-      // 4byte magic, 2byte name_len, dyn name, 4byte size, dyn data
-      offset += 4;
-      const name_len = index_view.getUint16(offset, false);
-      log('name_len', name_len);
-      offset += 2;
-      const name = new TextDecoder().decode(
-        index_data.slice(offset, offset + name_len),
-      );
-      log('name', name);
-      offset += name_len;
-      const size = index_view.getUint32(offset, false);
-      offset += 4;
-      const data = index_data.slice(offset, offset + size);
-      offset += size;
-      switch (name) {
-        case '\0CONFIG':
-          segments.config = JSON.parse(new TextDecoder().decode(data));
-          break;
-        case '\0META':
-          segments.metadata = JSON.parse(new TextDecoder().decode(data));
-          break;
-        case '\0THEME':
-          segments.theme = new TextDecoder().decode(data);
-          break;
-        case '\0INDEX': {
-          const index_view = new DataView(data.buffer);
-          const index = new Map<string, Embedded>();
-          let idx_offset = 0;
-          while (idx_offset < data.length) {
-            const name_len = data[idx_offset];
-            idx_offset++;
-            const name = new TextDecoder().decode(
-              data.slice(idx_offset, idx_offset + name_len),
-            );
-            idx_offset += name_len;
-            const size = index_view.getUint32(idx_offset, false);
-            idx_offset += 4;
-            const offset = index_view.getUint32(idx_offset, false);
-            idx_offset += 4;
-            index.set(name, {
-              name,
-              offset: index_start + offset,
-              raw_offset: 0,
-              size,
-            });
-          }
-          segments.index = index;
-          break;
-        }
-        default:
-          log('Unknown segment', name);
-          break;
-      }
-    } catch {
-      break;
-    }
-  }
+  // 由后端解析并校验签名（配置了 trustedKeys 时），只使用校验过的条目
+  const segments = await invoke<{
+    config: ProjectConfig;
+    metadata: InvokeGetDfsMetadataRes | null;
+    index: Embedded[] | null;
+  }>('read_pack_header', { data: index[1], baseEnd: index_start });
   if (!segments.index) {
     throw new Error('No index');
   }
   if (!segments.metadata) {
     throw new Error('No metadata');
   }
-  if (!segments.config) {
-    throw new Error('No config');
-  }
   log(segments);
   dfsIndexCache.set(source, {
-    index: segments.index,
+    index: new Map(segments.index.map((entry) => [entry.name, entry])),
     metadata: segments.metadata,
     installer_end: index_start + config_sz + theme_sz,
   });
@@ -351,6 +353,8 @@ export const getDfsUrl = async (
   size: number;
   skip_decompress?: boolean;
   skip_hash?: boolean;
+  checksum?: string;
+  dict?: string;
 }> => {
   const { remote, storage, url, plugin } = getDfsSourceType(source);
 
@@ -384,6 +388,7 @@ export const getDfsUrl = async (
       url: cdnUrl,
       offset: file.offset,
       size: file.size,
+      checksum: file.checksum,
     };
   } else {
     // DFS1: Use existing logic
@@ -429,6 +434,7 @@ export const getDfsUrl = async (
         url: full_file_url,
         offset: file.offset,
         size: file.size,
+        checksum: file.checksum,
       };
     }
   }
@@ -869,6 +875,7 @@ export const collectDfs2Ranges = (
   localFiles: Embedded[],
   dfsSource: string,
   hashKey: DfsMetadataHashType,
+  dirPatches: DfsMetadataDirPatchInfo[] = [],
 ): string[] => {
   const { remote } = getDfsSourceType(dfsSource);
   if (remote !== 'dfs2') {
@@ -927,14 +934,18 @@ export const collectDfs2Ranges = (
               `${originalDfsFile.offset}-${originalDfsFile.offset + originalDfsFile.size - 1}`,
             );
           }
-        } else if (originalFile.patch) {
-          // Patch mode: need both patch file and original file ranges
-          const patchHash = `${originalFile.patch.from[hashKey]}_${originalFile.patch.to[hashKey]}`;
-          const patchFile = cache.index.get(patchHash);
-          if (patchFile) {
-            ranges.add(
-              `${patchFile.offset}-${patchFile.offset + patchFile.size - 1}`,
-            );
+        } else if (originalFile.patch || originalFile.patch_chain) {
+          // Patch mode: need every patch in the chain and original file ranges
+          for (const patch of originalFile.patch_chain ?? [
+            originalFile.patch!,
+          ]) {
+            const patchHash = `${patch.from[hashKey]}_${patch.to[hashKey]}`;
+            const patchFile = cache.index.get(patchHash);
+            if (patchFile) {
+              ranges.add(
+                `${patchFile.offset}-${patchFile.offset + patchFile.size - 1}`,
+              );
+            }
           }
           const originalDfsFile = cache.index.get(
             originalFile[hashKey] as string,
@@ -985,14 +996,16 @@ export const collectDfs2Ranges = (
             `${originalFile.offset}-${originalFile.offset + originalFile.size - 1}`,
           );
         }
-      } else if (dfsFile.patch) {
-        // Patch mode: need both patch file and original file ranges
-        const patchHash = `${dfsFile.patch.from[hashKey]}_${dfsFile.patch.to[hashKey]}`;
-        const patchFile = cache.index.get(patchHash);
-        if (patchFile) {
-          ranges.add(
-            `${patchFile.offset}-${patchFile.offset + patchFile.size - 1}`,
-          );
+      } else if (dfsFile.patch || dfsFile.patch_chain) {
+        // Patch mode: need every patch in the chain and original file ranges
+        for (const patch of dfsFile.patch_chain ?? [dfsFile.patch!]) {
+          const patchHash = `${patch.from[hashKey]}_${patch.to[hashKey]}`;
+          const patchFile = cache.index.get(patchHash);
+          if (patchFile) {
+            ranges.add(
+              `${patchFile.offset}-${patchFile.offset + patchFile.size - 1}`,
+            );
+          }
         }
         const originalFile = cache.index.get(dfsFile[hashKey] as string);
         if (originalFile) {
@@ -1015,6 +1028,31 @@ export const collectDfs2Ranges = (
     }
   });
 
+  // 目录补丁失败时成员文件退回逐个下载，上面已经收集了它们的 range
+  dirPatches.forEach((dirPatch) => {
+    const dirPatchFile = cache.index.get(dirPatch.name);
+    if (dirPatchFile) {
+      ranges.add(
+        `${dirPatchFile.offset}-${dirPatchFile.offset + dirPatchFile.size - 1}`,
+      );
+    }
+  });
+
+  // 使用字典压缩的文件需要先下载字典
+  const dictHash = cache.metadata.dictionary?.[hashKey];
+  if (
+    dictHash &&
+    diffFiles.some((f) => f.codec === 'dict') &&
+    !localFiles.find((l) => l.name === dictHash)
+  ) {
+    const dictFile = cache.index.get(dictHash);
+    if (dictFile) {
+      ranges.add(
+        `${dictFile.offset}-${dictFile.offset + dictFile.size - 1}`,
+      );
+    }
+  }
+
   const rangeArray = Array.from(ranges);
   log('DFS2 ranges collected:', {
     totalRanges: rangeArray.length,
@@ -1027,6 +1065,109 @@ export const collectDfs2Ranges = (
   return Array.from(ranges);
 };
 
+// 使用字典压缩的文件安装前，先把字典加载到（可能已提权的）安装进程中
+export const loadDfsDictionary = async (
+  metadata: InvokeGetDfsMetadataRes,
+  dfsSource: string,
+  hashKey: DfsMetadataHashType,
+  local: Embedded[],
+  elevate = false,
+): Promise<void> => {
+  const hash = metadata.dictionary?.[hashKey];
+  if (!hash) {
+    throw new Error('Dictionary not found in metadata');
+  }
+  if (loadedDictionary === hash) return;
+  const hasLocalFile = local.find((l) => l.name === hash);
+  const source = hasLocalFile
+    ? { ...hasLocalFile }
+    : await getDfsUrl(dfsSource, hash);
+  await ipc(LoadDictionary(hash, source), elevate);
+  loadedDictionary = hash;
+  log('Dictionary loaded:', hash);
+};
+
+// 选出成员文件都能从本地旧版本更新的目录补丁，每个文件最多属于一个目录补丁。
+// 已有单文件补丁或能从安装包内直接安装的文件不使用目录补丁
+export const pickDirPatches = (
+  metadata: InvokeGetDfsMetadataRes,
+  diffFiles: DfsUpdateTask[],
+  local: Embedded[],
+  hashKey: DfsMetadataHashType,
+  checkKey: DfsMetadataCheckHashType,
+): DfsMetadataDirPatchInfo[] => {
+  const picked: DfsMetadataDirPatchInfo[] = [];
+  const claimed = new Set<string>();
+  for (const dirPatch of metadata.dir_patches ?? []) {
+    const applicable = dirPatch.files.every((member) => {
+      const file = diffFiles.find((f) => f.file_name === member.file_name);
+      return (
+        file &&
+        !claimed.has(file.file_name) &&
+        !file.patch &&
+        !file.patch_chain &&
+        !file.unwritable &&
+        file.old_hash === member.from[checkKey] &&
+        file[checkKey] === member.to[checkKey] &&
+        !local.find((l) => l.name === file[hashKey])
+      );
+    });
+    if (!applicable) continue;
+    dirPatch.files.forEach((member) => claimed.add(member.file_name));
+    picked.push(dirPatch);
+  }
+  return picked;
+};
+
+export const runDfsDirPatch = async (
+  dfsSource: string,
+  local: Embedded[],
+  source: string,
+  dirPatch: DfsMetadataDirPatchInfo,
+  onProgress: (downloaded: number) => void,
+  elevate = false,
+): Promise<void> => {
+  const hasLocalFile = local.find((l) => l.name === dirPatch.name);
+  const url = hasLocalFile
+    ? { ...hasLocalFile }
+    : await getDfsUrl(dfsSource, dirPatch.name);
+  Object.assign(url, codecSource(dirPatch.codec));
+  const files = dirPatch.files.map((member) => ({
+    target:
+      source +
+      (member.file_name.startsWith('/')
+        ? member.file_name
+        : `/${member.file_name}`),
+    old_size: member.from.size,
+    size: member.to.size,
+    from: fileHashes(member.from),
+    ...fileHashes(member.to),
+  }));
+  try {
+    const result: {
+      insight?: InsightItem;
+    } = await ipc(
+      InstallDirPatch(url, dirPatch.size, files, source),
+      elevate,
+      ({ payload }: { payload: number }) => {
+        if (!isNaN(payload)) onProgress(payload);
+      },
+    );
+    if (result.insight) addInsightWithMode(result.insight, 'patch');
+  } catch (e) {
+    if (e instanceof TAError && e.insight) {
+      addInsightWithMode(e.insight, 'patch');
+    }
+    throw e;
+  }
+};
+
+// 补丁或补丁链的总大小，没有补丁时为 undefined
+export const getPatchSize = (item: DfsUpdateTask): number | undefined =>
+  item.patch_chain
+    ? item.patch_chain.reduce((sum, patch) => sum + patch.size, 0)
+    : item.patch?.size;
+
 export const runDfsDownload = async (
   dfsSource: string,
   extras: string | undefined,
@@ -1055,17 +1196,32 @@ export const runDfsDownload = async (
   // Track insight for return
   let collectedInsight: InsightItem | undefined = undefined;
 
+  // 补丁源文件与 from 不符时后端改为完整下载。只有不需要额外请求就能得到
+  // 完整文件地址时才预先提供，否则后端报 SOURCE_MISMATCH_ERR，由重试禁用补丁下载
+  const patchSourceCheck = async (
+    from: DfsMetadataPatchInfo['from'],
+  ): Promise<PatchSourceCheck> => {
+    const check: PatchSourceCheck = { from: fileHashes(from) };
+    if (getDfsSourceType(dfsSource).remote === 'direct') {
+      check.fallback = await getDfsUrl(
+        dfsSource,
+        item[hashKey] as string,
+        extras,
+        item.installer,
+      );
+      if (item.codec) Object.assign(check.fallback, codecSource(item.codec));
+    }
+    return check;
+  };
+
   try {
     if (hasLocalFile && !disable_local) {
       // Local files don't involve network downloads, so no insight collection
       await ipc(
         InstallFile(
-          hasLocalFile,
+          { ...hasLocalFile, ...codecSource(item.codec) },
           source + filename_with_first_slash,
-          {
-            md5: item.md5,
-            xxh: item.xxh,
-          },
+          fileHashes(item),
           undefined,
           item.installer,
         ),
@@ -1083,58 +1239,101 @@ export const runDfsDownload = async (
       const hash = `${item.lpatch.from[hashKey]}_${item.lpatch.to[hashKey]}`;
       const url = await getDfsUrl(dfsSource, hash);
       url.size = url.size || (item.lpatch?.size as number);
+      if (item.lpatch.codec) Object.assign(url, codecSource(item.lpatch.codec));
+      const result: InstallResult = await ipc(
+        hybridPatch(
+          hasLpatchFile,
+          url,
+          source + filename_with_first_slash,
+          fileHashes(item),
+          item.lpatch.to.size,
+          await patchSourceCheck(item.lpatch.from),
+        ),
+        elevate,
+        onProgress,
+      );
+      if (result.source_mismatch) {
+        warn('Patch source mismatch, downloaded in full:', item.file_name);
+      }
+      if (result.insight) {
+        addInsightWithMode(
+          result.insight,
+          result.source_mismatch ? 'direct' : 'hybridpatch',
+        );
+        collectedInsight = result.insight;
+      }
+    } else if (item.patch_chain && !disable_patch) {
+      // PatchChain: apply patches across several versions in order
+      const steps = [];
+      for (const patch of item.patch_chain) {
+        const hash = `${patch.from[hashKey]}_${patch.to[hashKey]}`;
+        steps.push({
+          source: {
+            ...(await getDfsUrl(dfsSource, hash)),
+            ...codecSource(patch.codec),
+          },
+          diff_size: patch.size,
+          new_size: patch.to.size,
+        });
+      }
       const result: {
         insight?: InsightItem;
       } = await ipc(
-        hybridPatch(hasLpatchFile, url, source + filename_with_first_slash, {
-          md5: item.md5,
-          xxh: item.xxh,
-        }),
+        InstallPatchChain(
+          steps,
+          source + filename_with_first_slash,
+          fileHashes(item),
+          item.installer,
+        ),
         elevate,
         onProgress,
       );
       if (result.insight) {
-        addInsightWithMode(result.insight, 'hybridpatch');
+        addInsightWithMode(result.insight, 'patch');
         collectedInsight = result.insight;
       }
     } else if (item.patch && !disable_patch) {
       // Patch: collect insights with 'patch' mode
       const hash = `${item.patch.from[hashKey]}_${item.patch.to[hashKey]}`;
       const url = await getDfsUrl(dfsSource, hash);
-      const result: {
-        insight?: InsightItem;
-      } = await ipc(
+      if (item.patch.codec) Object.assign(url, codecSource(item.patch.codec));
+      const result: InstallResult = await ipc(
         InstallFile(
           url,
           source + filename_with_first_slash,
+          fileHashes(item),
           {
-            md5: item.md5,
-            xxh: item.xxh,
+            diff_size: item.patch.size,
+            new_size: item.patch.to.size,
+            ...(await patchSourceCheck(item.patch.from)),
           },
-          item.patch.size,
           item.installer,
         ),
         elevate,
         onProgress,
       );
+      if (result.source_mismatch) {
+        warn('Patch source mismatch, downloaded in full:', item.file_name);
+      }
       if (result.insight) {
-        addInsightWithMode(result.insight, 'patch');
+        addInsightWithMode(
+          result.insight,
+          result.source_mismatch ? 'direct' : 'patch',
+        );
         collectedInsight = result.insight;
       }
     } else {
       // Direct: collect insights with 'direct' mode
       const hash = item[hashKey] as string;
       const url = await getDfsUrl(dfsSource, hash, extras, item.installer);
+      if (item.codec) Object.assign(url, codecSource(item.codec));
       const result: {
         insight?: InsightItem;
       } = await ipc(
         InstallFile(
           url,
           source + filename_with_first_slash,
-          {
-            md5: item.md5,
-            xxh: item.xxh,
-          },
+          fileHashes(item),
           undefined,
           item.installer,
         ),
@@ -1162,7 +1361,7 @@ export const runDfsDownload = async (
         !disable_local
       ) {
         mode = 'hybridpatch';
-      } else if (item.patch && !disable_patch) {
+      } else if ((item.patch || item.patch_chain) && !disable_patch) {
         mode = 'patch';
       } else {
         mode = 'direct';
@@ -1179,7 +1378,7 @@ export const runDfsDownload = async (
   } finally {
     item.running = false;
   }
-  item.downloaded = item.patch ? item.patch.size : item.size;
+  item.downloaded = getPatchSize(item) ?? item.size;
   return { insight: collectedInsight };
 };
 
@@ -1290,6 +1489,7 @@ export const mergeSmallFilesIntoGroups = (
         ...f,
         dfsOffset: dfsFile?.offset || 0,
         dfsSize: dfsFile?.size || f.size,
+        dfsChecksum: dfsFile?.checksum,
       };
     })
     .sort((a, b) => a.dfsOffset - b.dfsOffset);
@@ -1359,7 +1559,7 @@ export const getFileInstallMode = (
 
   if (hasLocalFile) return 'local';
   if (hasLpatchFile && file.lpatch) return 'hybridpatch';
-  if (file.patch) return 'patch';
+  if (file.patch || file.patch_chain) return 'patch';
   return 'direct';
 };
 
@@ -1564,14 +1764,14 @@ export const runMergedGroupDownload = async (
         url: cdnUrl,
         offset: relativeOffset, // 使用相对偏移而不是绝对偏移
         size: fileWithPosition.dfsSize,
-        skip_decompress: false,
+        ...codecSource(file.codec),
+        checksum: fileWithPosition.dfsChecksum,
       };
 
       return {
         mode: { type: 'Direct' as const, source: source_info },
         target: source + filename_with_first_slash,
-        md5: file.md5,
-        xxh: file.xxh,
+        ...fileHashes(file),
         type: 'InstallFile' as const,
       };
     });
@@ -28,6 +28,8 @@ export type ProjectConfig = {
   uacStrategy: 'prefer-admin' | 'prefer-user' | 'force';
   runtimes?: string[];
   windowBorderless?: boolean;
+  // 可信的 Ed25519 公钥（hex），配置后安装包与远程索引必须带有效签名
+  trustedKeys?: string[];
 };
 
 export type InstallStat = {
@@ -36,13 +38,20 @@ export type InstallStat = {
   speed: number;
 };
 
+// 条目与补丁按 md5 或 xxh 命名
 export type DfsMetadataHashType = 'md5' | 'xxh';
+// 校验文件时可用的哈希，包括 gen --strong-hash 写入的强哈希
+export type DfsMetadataCheckHashType = DfsMetadataHashType | 'sha256' | 'blake3';
 
 export type DfsMetadataHashInfo = {
   file_name: string;
   size: number;
   md5?: string;
   xxh?: string;
+  sha256?: string;
+  blake3?: string;
+  // 未指定时为 zstd，raw 表示文件未压缩
+  codec?: 'zstd' | 'raw' | 'dict';
   installer?: true;
 };
 
@@ -51,10 +60,28 @@ export type DfsMetadataPatchInfo = {
   size: number;
   from: Omit<DfsMetadataHashInfo, 'file_name'>;
   to: Omit<DfsMetadataHashInfo, 'file_name'>;
+  // 补丁由几个版本之前的构建带入，新生成的补丁没有
+  age?: number;
+  // raw: 补丁由 hdiff 内部压缩，下载后直接交给 hpatch
+  codec?: 'zstd' | 'raw';
+};
+
+// 目录补丁，新旧成员文件分别按顺序拼接后生成，一次更新所有成员
+export type DfsMetadataDirPatchInfo = {
+  name: string;
+  size: number;
+  codec?: 'zstd' | 'raw';
+  files: Array<{
+    file_name: string;
+    from: Omit<DfsMetadataHashInfo, 'file_name'>;
+    to: Omit<DfsMetadataHashInfo, 'file_name'>;
+  }>;
 };
 
 export interface DfsUpdateTask extends DfsMetadataHashInfo {
   patch?: DfsMetadataPatchInfo;
+  // 跨多个版本的补丁链，按应用顺序排列，只有一步时使用 patch
+  patch_chain?: DfsMetadataPatchInfo[];
   lpatch?: DfsMetadataPatchInfo;
   downloaded: number;
   running: boolean;
@@ -68,6 +95,7 @@ export interface DfsUpdateTask extends DfsMetadataHashInfo {
 export interface FileWithPosition extends DfsUpdateTask {
   dfsOffset: number;
   dfsSize: number;
+  dfsChecksum?: string;
 }
 
 export interface MergedGroupInfo {
@@ -93,8 +121,16 @@ export type InvokeGetDfsMetadataRes = {
     size: number;
     md5?: string;
     xxh?: string;
+    sha256?: string;
+    blake3?: string;
   };
   deletes?: string[];
+  dictionary?: {
+    size: number;
+    md5?: string;
+    xxh?: string;
+  };
+  dir_patches?: Array<DfsMetadataDirPatchInfo>;
 };
 
 export type InvokeDeepReaddirWithMetadataRes = Array<{
@@ -128,6 +164,7 @@ export type Dfs2FileInfo = {
   offset: number;
   raw_offset: number;
   size: number;
+  checksum?: string;
 };
 
 export type Dfs2SessionResponse = {
@@ -167,6 +204,8 @@ export interface InsightItem {
 export interface InstallResult {
   bytes_transferred: number;
   insight?: InsightItem;
+  // 补丁源文件不符，已改为完整下载
+  source_mismatch?: boolean;
 }
 
 export type Dfs2SessionInsights = {
@@ -209,6 +248,8 @@ export interface Embedded {
   offset: number;
   raw_offset: number;
   size: number;
+  // xxh3-64 of the stored bytes from a v3 index, hex
+  checksum?: string;
 }
 
 export interface InstallerConfig {